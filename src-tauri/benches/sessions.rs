@@ -0,0 +1,41 @@
+//! Listing the playback sessions: the live table kept by notifications
+//! against a fresh walk over every endpoint's session enumerator. WASAPI
+//! only; run with `cargo bench --bench sessions` while some apps play.
+
+#[cfg(target_os = "windows")]
+fn main() {
+    use std::hint::black_box;
+    use std::time::{Duration, Instant};
+    use win_control_center_lib::SessionBench;
+
+    /// Median and mean time per call, over about a second of calls.
+    fn measure(name: &str, mut f: impl FnMut() -> usize) {
+        let found = f();
+        let mut samples = Vec::new();
+        let end = Instant::now() + Duration::from_secs(1);
+        while Instant::now() < end || samples.len() < 10 {
+            let start = Instant::now();
+            black_box(f());
+            samples.push(start.elapsed());
+        }
+        samples.sort();
+        let mean = samples.iter().sum::<Duration>() / samples.len() as u32;
+        println!(
+            "{:<10} {:>4} sessions  median {:>10.1?}  mean {:>10.1?}  ({} calls)",
+            name,
+            found,
+            samples[samples.len() / 2],
+            mean,
+            samples.len()
+        );
+    }
+
+    let mut bench = SessionBench::new().expect("no audio backend");
+    measure("enumerate", || bench.enumerate());
+    measure("table", || bench.table());
+}
+
+#[cfg(not(target_os = "windows"))]
+fn main() {
+    eprintln!("sessions: the session table is WASAPI only, nothing to measure");
+}
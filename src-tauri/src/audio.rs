@@ -1,770 +1,2916 @@
-#![allow(non_snake_case)]
-#![allow(non_camel_case_types)]
-#![allow(unused_imports)]
-#![allow(dead_code)]
+pub mod helpers;
+pub mod memory;
+#[cfg(test)]
+pub mod mock;
+#[cfg(target_os = "linux")]
+pub mod pulse;
+pub mod scene;
+pub mod view;
+#[cfg(target_os = "windows")]
+mod wasapi;
+#[cfg(target_os = "windows")]
+#[doc(hidden)]
+pub use wasapi::SessionBench;
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::ffi::c_void;
-use std::sync::mpsc::{channel, Sender};
-use std::sync::Mutex;
-use std::thread;
-use windows::core::{interface, ComInterface, IUnknown, Result, GUID, HRESULT, PCWSTR, PWSTR};
-use windows::Win32::Foundation::{CloseHandle, E_NOINTERFACE};
-
-use base64::{engine::general_purpose, Engine as _};
-use windows::Win32::Devices::FunctionDiscovery::PKEY_Device_FriendlyName;
-use windows::Win32::Graphics::Gdi::{
-    CreateCompatibleDC, DeleteDC, DeleteObject, GetDIBits, GetObjectW, SelectObject, BITMAP,
-    BITMAPINFO, BITMAPINFOHEADER, DIB_RGB_COLORS,
-};
-use windows::Win32::Media::Audio::Endpoints::IAudioEndpointVolume;
-use windows::Win32::Media::Audio::*;
-use windows::Win32::System::Com::StructuredStorage::{PropVariantClear, PROPVARIANT};
-use windows::Win32::System::Com::*;
-use windows::Win32::System::Diagnostics::ToolHelp::*;
-use windows::Win32::System::ProcessStatus::GetModuleBaseNameW;
-use windows::Win32::System::Threading::{
-    OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_INFORMATION,
-    PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_VM_READ,
-};
-use windows::Win32::UI::Shell::{
-    ExtractIconExW, SHGetFileInfoW, SHFILEINFOW, SHGFI_ICON, SHGFI_LARGEICON,
-    SHGFI_USEFILEATTRIBUTES,
-};
-use windows::Win32::UI::WindowsAndMessaging::{
-    DestroyIcon, GetIconInfo, PrivateExtractIconsW, HICON, ICONINFO,
-};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, SendError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use tokio::sync::oneshot;
 
-// Modern Client (Win 10/11)
-const CLSID_POLICY_CONFIG_CLIENT: GUID = GUID {
-    data1: 0x870af99c,
-    data2: 0x171d,
-    data3: 0x4f9e,
-    data4: [0xaf, 0x0d, 0xe6, 0x3d, 0xf4, 0x0c, 0x2b, 0xc9],
-};
-
-// Vista Client (Classic, often more stable on Win11 for simple switching)
-const CLSID_POLICY_CONFIG_VISTA: GUID = GUID {
-    data1: 0x294935ce,
-    data2: 0xf637,
-    data3: 0x4e7c,
-    data4: [0xa4, 0x1b, 0xab, 0x25, 0x54, 0x60, 0xf8, 0x62],
-};
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
+use crate::error::ControlError;
+use crate::icons::{IconImage, IconStore};
+use helpers::HelperRules;
+use memory::VolumeMemory;
+use scene::{Scene, SceneApp};
+use view::{MixerView, ViewStore};
+
+pub type AudioResult<T> = std::result::Result<T, ControlError>;
+
+/// Run the hidden `--switch-audio-device <id> --role <role>` helper mode if
+/// the command line asks for it, returning the process exit code.
+#[cfg(target_os = "windows")]
+pub fn helper_main(args: &[String]) -> Option<i32> {
+    wasapi::helper_main(args)
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn helper_main(_args: &[String]) -> Option<i32> {
+    None
+}
+
+/// Whether the platform backend keeps a separate default device for
+/// [`DeviceRole::Communications`]. PulseAudio has one default per direction.
+pub const SEPARATE_ROLES: bool = cfg!(target_os = "windows");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct AppVolume {
+    /// The loudest member, kept for per-process commands.
     pub pid: u32,
     pub name: String,
     pub volume: f32,
     pub is_muted: bool,
     pub icon_path: String,
+    /// Stable key of the merged row: the executable path, or the name when
+    /// the path could not be resolved.
+    pub group: String,
+    /// Every process with a session in this row.
+    pub pids: Vec<u32>,
+    /// Endpoint the app is routed to, `None` while it follows the default.
+    pub output_device_id: Option<String>,
+    /// Loudest member's peak level (0..=1) when the row was read. Live
+    /// values arrive through `audio://levels`.
+    pub peak: f32,
+    /// The system sounds row, which has no process to route or describe.
+    pub is_system: bool,
+    /// Active while any member session is playing.
+    pub state: SessionState,
+    /// Unix time in milliseconds the row was last seen active, or first
+    /// seen when it has not been active since. Stamped by the worker.
+    pub last_active: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    Active,
+    #[default]
+    Inactive,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct AudioDevice {
     pub id: String,
     pub name: String,
+    /// Default for [`DeviceRole::Multimedia`], what most apps play through.
     pub is_default: bool,
+    /// Every role this device is the default for.
+    pub default_roles: Vec<DeviceRole>,
 }
 
-pub enum AudioRequest {
-    GetMasterVolume(oneshot::Sender<Result<(f32, bool)>>),
-    GetMicVolume(oneshot::Sender<Result<(f32, bool)>>),
-    GetAppVolumes(oneshot::Sender<Result<Vec<AppVolume>>>),
-    SetMasterVolume(f32),
-    SetMicVolume(f32),
-    SetMasterMute(bool),
-    SetMicMute(bool),
-    SetAppVolume(u32, f32),
-    SetAppMute(u32, bool),
-    GetPlaybackDevices(oneshot::Sender<Result<Vec<AudioDevice>>>),
-    GetCaptureDevices(oneshot::Sender<Result<Vec<AudioDevice>>>),
-    SetDefaultDevice(String),
+/// Outcome of a default-device switch, sent back to the caller and
+/// published as `audio://device-switch`.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct DeviceSwitch {
+    pub id: String,
+    /// Friendly name, or the id when the device is unknown.
+    pub name: String,
+    /// Every requested role and whether the device is now its default.
+    pub roles: Vec<RoleCheck>,
+    pub success: bool,
+    pub error: Option<ControlError>,
 }
 
-pub struct AppCache {
-    pub names: Mutex<HashMap<u32, (String, String)>>,
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct RoleCheck {
+    pub role: DeviceRole,
+    pub verified: bool,
 }
 
-impl AppCache {
-    pub fn new() -> Self {
-        Self {
-            names: Mutex::new(HashMap::new()),
+/// The roles Windows keeps a separate default device for. Backends with a
+/// single default treat every role the same.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceRole {
+    Console,
+    Multimedia,
+    Communications,
+}
+
+impl DeviceRole {
+    pub const ALL: [DeviceRole; 3] = [
+        DeviceRole::Console,
+        DeviceRole::Multimedia,
+        DeviceRole::Communications,
+    ];
+    /// What the device lists in the tray change: everything but calls.
+    pub const EVERYDAY: [DeviceRole; 2] = [DeviceRole::Console, DeviceRole::Multimedia];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeviceRole::Console => "console",
+            DeviceRole::Multimedia => "multimedia",
+            DeviceRole::Communications => "communications",
         }
     }
 }
 
-pub struct AudioState {
-    pub tx: Sender<AudioRequest>,
+/// A single audio session as reported by a backend, before it is merged
+/// per process and per application name.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AppSession {
+    /// Backend-specific identity of the session, stable for its lifetime.
+    pub id: String,
+    pub pid: u32,
+    pub volume: f32,
+    pub is_muted: bool,
+    /// Name the session gives itself, preferred over the process's.
+    pub display_name: Option<String>,
+    /// Icon the session gives itself: a file, or a `<file>,<index>` icon
+    /// resource as passed to [`AudioBackend::render_icon`].
+    pub icon_path: Option<String>,
+    /// Sessions sharing a grouping parameter belong to one row, even across
+    /// processes.
+    pub grouping: Option<String>,
+    /// The shared session of system notification sounds, which has no
+    /// process of its own.
+    pub is_system: bool,
+    /// Playing, as opposed to open but silent or paused.
+    pub is_active: bool,
 }
 
-impl AudioState {
-    pub fn new(cache: std::sync::Arc<AppCache>) -> Self {
-        let (tx, rx) = channel::<AudioRequest>();
-        let worker_cache = cache.clone();
-
-        thread::spawn(move || {
-            unsafe {
-                let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
-            }
-            struct AudioContext {
-                enumerator: IMMDeviceEnumerator,
-            }
-            impl AudioContext {
-                unsafe fn new() -> Result<Self> {
-                    let enumerator: IMMDeviceEnumerator =
-                        CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
-                    Ok(Self { enumerator })
-                }
-                unsafe fn get_sys(&mut self) -> Result<IAudioEndpointVolume> {
-                    let device = self
-                        .enumerator
-                        .GetDefaultAudioEndpoint(eRender, eMultimedia)?;
-                    device.Activate(CLSCTX_ALL, None::<*const PROPVARIANT>)
-                }
-                unsafe fn get_mic(&mut self) -> Result<IAudioEndpointVolume> {
-                    let device = self
-                        .enumerator
-                        .GetDefaultAudioEndpoint(eCapture, eMultimedia)?;
-                    device.Activate(CLSCTX_ALL, None::<*const PROPVARIANT>)
-                }
-            }
-            let mut ctx = unsafe { AudioContext::new().ok() };
-            while let Ok(req) = rx.recv() {
-                if ctx.is_none() {
-                    ctx = unsafe { AudioContext::new().ok() };
-                }
-                if let Some(ref mut c) = ctx {
-                    match req {
-                        AudioRequest::GetMasterVolume(res_tx) => {
-                            let res = unsafe {
-                                c.get_sys().and_then(|v| {
-                                    let vol = v.GetMasterVolumeLevelScalar()?;
-                                    let mute = v.GetMute()?.as_bool();
-                                    Ok((vol, mute))
-                                })
-                            };
-                            let _ = res_tx.send(res);
-                        }
-                        AudioRequest::GetMicVolume(res_tx) => {
-                            let res = unsafe {
-                                c.get_mic().and_then(|v| {
-                                    let vol = v.GetMasterVolumeLevelScalar()?;
-                                    let mute = v.GetMute()?.as_bool();
-                                    Ok((vol, mute))
-                                })
-                            };
-                            let _ = res_tx.send(res);
-                        }
-                        AudioRequest::GetAppVolumes(res_tx) => {
-                            let res =
-                                unsafe { internal_get_app_volumes(&c.enumerator, &worker_cache) };
-                            let _ = res_tx.send(res);
-                        }
-                        AudioRequest::SetMasterVolume(vol) => {
-                            if let Ok(v) = unsafe { c.get_sys() } {
-                                let _ = unsafe { v.SetMute(false, std::ptr::null()) };
-                                let _ =
-                                    unsafe { v.SetMasterVolumeLevelScalar(vol, std::ptr::null()) };
-                            }
-                        }
-                        AudioRequest::SetMicVolume(vol) => {
-                            if let Ok(v) = unsafe { c.get_mic() } {
-                                let _ = unsafe { v.SetMute(false, std::ptr::null()) };
-                                let _ =
-                                    unsafe { v.SetMasterVolumeLevelScalar(vol, std::ptr::null()) };
-                            }
-                        }
-                        AudioRequest::SetMasterMute(mute) => {
-                            if let Ok(v) = unsafe { c.get_sys() } {
-                                let _ = unsafe { v.SetMute(mute, std::ptr::null()) };
-                            }
-                        }
-                        AudioRequest::SetMicMute(mute) => {
-                            if let Ok(v) = unsafe { c.get_mic() } {
-                                let _ = unsafe { v.SetMute(mute, std::ptr::null()) };
-                            }
-                        }
-                        AudioRequest::SetAppVolume(pid, vol) => {
-                            let _ = unsafe { internal_set_app_vol(&c.enumerator, pid, vol) };
-                        }
-                        AudioRequest::SetAppMute(pid, mute) => {
-                            let _ = unsafe { internal_set_app_mute(&c.enumerator, pid, mute) };
-                        }
-                        AudioRequest::GetPlaybackDevices(tx) => {
-                            let res = unsafe { get_audio_endpoints(&c.enumerator, eRender) };
-                            let _ = tx.send(res);
-                        }
-                        AudioRequest::GetCaptureDevices(tx) => {
-                            let res = unsafe { get_audio_endpoints(&c.enumerator, eCapture) };
-                            let _ = tx.send(res);
-                        }
-                        AudioRequest::SetDefaultDevice(id) => {
-                            let _ = unsafe { set_default_device(&id) };
-                        }
-                    }
-                }
-            }
-        });
-        Self { tx }
+/// What [`AudioBackend::describe_processes`] knows about a process.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcessInfo {
+    pub name: String,
+    pub icon_path: String,
+    /// Full path of the executable, empty when it could not be resolved.
+    pub exe_path: String,
+}
+
+impl ProcessInfo {
+    fn unknown(pid: u32) -> Self {
+        Self {
+            name: format!("App {}", pid),
+            ..Default::default()
+        }
+    }
+
+    fn system_sounds() -> Self {
+        Self {
+            name: "System sounds".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The key sessions are merged on.
+    pub fn group(&self) -> &str {
+        if self.exe_path.is_empty() {
+            &self.name
+        } else {
+            &self.exe_path
+        }
     }
 }
 
-unsafe fn internal_get_app_volumes(
-    enumerator: &IMMDeviceEnumerator,
-    cache: &AppCache,
-) -> Result<Vec<AppVolume>> {
-    let mut session_map: HashMap<u32, (f32, bool)> = HashMap::new();
-    let device = enumerator.GetDefaultAudioEndpoint(eRender, eMultimedia)?;
-    let session_manager: IAudioSessionManager2 =
-        device.Activate(CLSCTX_ALL, None::<*const PROPVARIANT>)?;
-    let session_enumerator = session_manager.GetSessionEnumerator()?;
-    let count = session_enumerator.GetCount()?;
-    for i in 0..count {
-        if let Ok(session_control) = session_enumerator.GetSession(i) {
-            let state = session_control.GetState()?;
-            if state.0 == 2 {
-                continue;
-            }
-            if let Ok(session_control2) = session_control.cast::<IAudioSessionControl2>() {
-                let pid = session_control2.GetProcessId()?;
-                if pid == 0 {
-                    continue;
-                }
-                if let Ok(simple_volume) = session_control.cast::<ISimpleAudioVolume>() {
-                    let vol = simple_volume.GetMasterVolume()?;
-                    let mute = simple_volume.GetMute()?.as_bool();
-                    let entry = session_map.entry(pid).or_insert((0.0, false));
-                    if vol > entry.0 {
-                        entry.0 = vol;
-                        entry.1 = mute;
-                    }
-                }
-            }
+/// Something changed inside the backend. Posted from whatever thread the
+/// platform delivers notifications on; the worker turns it into [`AudioEvent`]s.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BackendEvent {
+    Master,
+    Mic,
+    Sessions,
+    /// A capture session appeared, went away, or started or stopped
+    /// recording.
+    CaptureSessions,
+    /// An endpoint was added, removed, enabled/disabled or became default.
+    Devices,
+}
+
+/// Peak levels sampled by a backend, all in 0..=1.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Peaks {
+    pub master: f32,
+    /// `None` without a capture device.
+    pub mic: Option<f32>,
+    /// Loudest session of each process.
+    pub sessions: HashMap<u32, f32>,
+}
+
+/// Payload of `audio://levels`; apps are keyed by [`AppVolume::group`].
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Levels {
+    pub master: f32,
+    pub mic: Option<f32>,
+    pub apps: HashMap<String, f32>,
+}
+
+/// Upper bound for [`AudioRequest::SetMeterRate`]; faster is wasted on the UI.
+pub const MAX_METER_RATE_HZ: u32 = 30;
+
+/// How often rows are checked against [`MixerView::hide_inactive_after_mins`]
+/// while it is set.
+const HIDE_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct DeviceLists {
+    pub playback: Vec<AudioDevice>,
+    pub capture: Vec<AudioDevice>,
+}
+
+/// Change pushed to the frontend. Serializes as the bare payload; the event
+/// name comes from [`AudioEvent::name`].
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum AudioEvent {
+    Master((f32, bool)),
+    Mic((f32, bool)),
+    SessionAdded(AppVolume),
+    SessionRemoved(AppVolume),
+    SessionChanged(AppVolume),
+    Devices(DeviceLists),
+    Levels(Levels),
+    DeviceSwitch(DeviceSwitch),
+    /// Every app with a session on a capture device.
+    CaptureApps(Vec<AppVolume>),
+    Recording(RecordingChange),
+}
+
+impl AudioEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AudioEvent::Master(_) => "audio://master",
+            AudioEvent::Mic(_) => "audio://mic",
+            AudioEvent::SessionAdded(_) => "audio://session-added",
+            AudioEvent::SessionRemoved(_) => "audio://session-removed",
+            AudioEvent::SessionChanged(_) => "audio://session-changed",
+            AudioEvent::Devices(_) => "audio://devices-changed",
+            AudioEvent::Levels(_) => "audio://levels",
+            AudioEvent::DeviceSwitch(_) => "audio://device-switch",
+            AudioEvent::CaptureApps(_) => "audio://capture-apps",
+            AudioEvent::Recording(_) => "audio://recording",
         }
     }
-    let pids: Vec<u32> = session_map.keys().cloned().collect();
-    update_cache_batch(&pids, cache);
+}
 
-    // Deduplicate by Name (merge sessions)
-    // Key: App Name -> (PID, Volume, Mute, Icon)
-    // We prioritize the session with the highest volume or first found
-    let mut merged_map: HashMap<String, AppVolume> = HashMap::new();
+/// Payload of `audio://recording`: an app started or stopped recording.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RecordingChange {
+    pub app: AppVolume,
+    pub recording: bool,
+    /// Names of every app recording after the change, for the tray.
+    pub recording_now: Vec<String>,
+}
 
-    if let Ok(map) = cache.names.lock() {
-        for (pid, (vol, mute)) in session_map {
-            let (name, icon_path) = map
-                .get(&pid)
-                .cloned()
-                .unwrap_or_else(|| (format!("App {}", pid), "".into()));
+/// Shared by every worker the supervisor starts, hence `Sync`.
+pub type EventSink = Arc<dyn Fn(AudioEvent) + Send + Sync>;
 
-            // Skip "System" sounds if they act weird or generic duplicates (optional, but good for cleanliness)
-            if name == "Windows Audio Session" {
-                continue;
-            }
+/// Creates the platform backend. Called on the worker thread, again after a
+/// failure and again for every replacement worker.
+pub type BackendFactory = Arc<dyn Fn() -> AudioResult<Box<dyn AudioBackend>> + Send + Sync>;
 
-            // Deduplication logic
-            merged_map
-                .entry(name.clone())
-                .and_modify(|e| {
-                    // Update existing entry if this pid seems "more active" (e.g. higher volume)
-                    if vol > e.volume {
-                        e.volume = vol;
-                        e.pid = pid; // Switch control to the louder process
-                        e.is_muted = mute;
-                    }
-                })
-                .or_insert(AppVolume {
-                    pid,
-                    name,
-                    volume: vol,
-                    is_muted: mute,
-                    icon_path,
-                });
+/// How long a caller waits for an ordinary request before giving up.
+#[cfg(not(test))]
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Deadline for requests that run the switch helper once per role, or
+/// touch every session of a scene.
+#[cfg(not(test))]
+const SLOW_REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+/// A worker busy with one request for this long is considered hung and is
+/// replaced. Longer than any deadline, so a slow but healthy request is
+/// never cut short.
+#[cfg(not(test))]
+const STUCK_AFTER: Duration = Duration::from_secs(30);
+const SUPERVISE_INTERVAL: Duration = Duration::from_millis(500);
+
+// Shorter for tests, in the same order, so hung workers are replaced
+// within a test's run time.
+#[cfg(test)]
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+#[cfg(test)]
+const SLOW_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+#[cfg(test)]
+const STUCK_AFTER: Duration = Duration::from_secs(3);
+
+/// Payload of `get_backend_health`.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct BackendHealth {
+    /// Seconds since the current worker was started.
+    pub uptime_secs: u64,
+    /// Workers replaced after they died or hung.
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    /// Whether the current worker has a backend.
+    pub connected: bool,
+}
+
+struct HealthState {
+    started: Instant,
+    restarts: u32,
+    last_error: Option<String>,
+    connected: bool,
+}
+
+/// What a worker reports to the supervisor.
+#[derive(Default)]
+struct Heartbeat {
+    /// When the request being handled was picked up; `None` while idle.
+    busy_since: Mutex<Option<Instant>>,
+    /// Set by the supervisor once a replacement runs. A hung worker that
+    /// wakes up again stops instead of competing with it.
+    abandoned: AtomicBool,
+}
+
+impl Heartbeat {
+    fn beat<R>(&self, f: impl FnOnce() -> R) -> R {
+        *lock(&self.busy_since) = Some(Instant::now());
+        let res = f();
+        *lock(&self.busy_since) = None;
+        res
+    }
+
+    fn is_abandoned(&self) -> bool {
+        self.abandoned.load(Ordering::Relaxed)
+    }
+}
+
+/// A panicking worker poisons whatever it held; the data is still usable.
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Handle given to a backend so it can wake the worker from its own
+/// notification threads.
+#[derive(Clone)]
+pub struct Notifier(Queue);
+
+impl Notifier {
+    pub fn notify(&self, event: BackendEvent) {
+        let _ = self.0.send(AudioRequest::Notify(event));
+    }
+}
+
+/// Snapshot of the worker queue, for `get_audio_queue_stats`.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct QueueStats {
+    /// Requests sent but not yet picked up by the worker.
+    pub depth: usize,
+    /// Highest `depth` since start.
+    pub max_depth: usize,
+    pub received: u64,
+    /// Writes and notifications dropped because a later one to the same
+    /// target made them redundant.
+    pub coalesced: u64,
+    /// Most requests the worker found waiting at once.
+    pub max_batch: usize,
+}
+
+#[derive(Default)]
+struct QueueCounters {
+    depth: AtomicUsize,
+    max_depth: AtomicUsize,
+    received: AtomicU64,
+    coalesced: AtomicU64,
+    max_batch: AtomicUsize,
+}
+
+/// Sending side of the worker channel, counting what is in flight. Every
+/// clone follows the channel to a replacement worker.
+#[derive(Clone)]
+struct Queue {
+    tx: Arc<Mutex<Sender<AudioRequest>>>,
+    counters: Arc<QueueCounters>,
+}
+
+impl Queue {
+    fn new() -> (Self, Receiver<AudioRequest>) {
+        let (tx, rx) = channel();
+        let queue = Queue {
+            tx: Arc::new(Mutex::new(tx)),
+            counters: Arc::default(),
+        };
+        (queue, rx)
+    }
+
+    fn send(&self, req: AudioRequest) -> Result<(), SendError<AudioRequest>> {
+        let c = &self.counters;
+        let depth = c.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        c.max_depth.fetch_max(depth, Ordering::Relaxed);
+        lock(&self.tx).send(req).inspect_err(|_| {
+            c.depth.fetch_sub(1, Ordering::Relaxed);
+        })
+    }
+
+    /// Point every sender at a new channel. Whatever was queued for the old
+    /// worker is left with it.
+    fn reconnect(&self) -> Receiver<AudioRequest> {
+        let (tx, rx) = channel();
+        *lock(&self.tx) = tx;
+        self.counters.depth.store(0, Ordering::Relaxed);
+        rx
+    }
+
+    /// Take everything waiting behind `first`.
+    fn drain(&self, rx: &Receiver<AudioRequest>, first: AudioRequest) -> Vec<AudioRequest> {
+        let mut batch = vec![first];
+        batch.extend(rx.try_iter());
+        let c = &self.counters;
+        c.depth.fetch_sub(batch.len(), Ordering::Relaxed);
+        c.received.fetch_add(batch.len() as u64, Ordering::Relaxed);
+        c.max_batch.fetch_max(batch.len(), Ordering::Relaxed);
+        batch
+    }
+
+    fn stats(&self) -> QueueStats {
+        let c = &self.counters;
+        QueueStats {
+            depth: c.depth.load(Ordering::Relaxed),
+            max_depth: c.max_depth.load(Ordering::Relaxed),
+            received: c.received.load(Ordering::Relaxed),
+            coalesced: c.coalesced.load(Ordering::Relaxed),
+            max_batch: c.max_batch.load(Ordering::Relaxed),
         }
     }
+}
 
-    let mut apps: Vec<AppVolume> = merged_map.values().cloned().collect();
-    apps.sort_by(|a, b| a.name.cmp(&b.name));
-    Ok(apps)
+pub enum AudioRequest {
+    GetMasterVolume(oneshot::Sender<AudioResult<(f32, bool)>>),
+    GetMicVolume(oneshot::Sender<AudioResult<(f32, bool)>>),
+    GetAppVolumes(oneshot::Sender<AudioResult<Vec<AppVolume>>>),
+    /// Apps with a session on a capture device.
+    GetCaptureApps(oneshot::Sender<AudioResult<Vec<AppVolume>>>),
+    SetMasterVolume(f32, oneshot::Sender<AudioResult<()>>),
+    SetMicVolume(f32, oneshot::Sender<AudioResult<()>>),
+    SetMasterMute(bool, oneshot::Sender<AudioResult<()>>),
+    SetMicMute(bool, oneshot::Sender<AudioResult<()>>),
+    SetAppVolume(u32, f32, oneshot::Sender<AudioResult<()>>),
+    SetAppMute(u32, bool, oneshot::Sender<AudioResult<()>>),
+    SetCaptureAppVolume(u32, f32, oneshot::Sender<AudioResult<()>>),
+    SetCaptureAppMute(u32, bool, oneshot::Sender<AudioResult<()>>),
+    /// Apply to every session of an [`AppVolume::group`], including sessions
+    /// the group gains later on.
+    SetGroupVolume(String, f32, oneshot::Sender<AudioResult<()>>),
+    SetGroupMute(String, bool, oneshot::Sender<AudioResult<()>>),
+    /// Groups opted out of per-app volume memory.
+    GetVolumeMemoryExclusions(oneshot::Sender<AudioResult<Vec<String>>>),
+    SetVolumeMemoryExcluded(String, bool, oneshot::Sender<AudioResult<()>>),
+    GetPlaybackDevices(oneshot::Sender<AudioResult<Vec<AudioDevice>>>),
+    GetCaptureDevices(oneshot::Sender<AudioResult<Vec<AudioDevice>>>),
+    /// Replies once the switch has been verified; failures are reported in
+    /// the [`DeviceSwitch`] rather than as an error.
+    SetDefaultDevice(
+        String,
+        Vec<DeviceRole>,
+        oneshot::Sender<AudioResult<DeviceSwitch>>,
+    ),
+    /// Route a process to an output device; `None` resets it to the default.
+    SetAppOutputDevice(u32, Option<String>, oneshot::Sender<AudioResult<()>>),
+    /// Snapshot the current mixer under the given name.
+    CaptureScene(String, oneshot::Sender<AudioResult<Scene>>),
+    ApplyScene(Scene, oneshot::Sender<AudioResult<()>>),
+    /// Sample peak meters this many times per second, at most
+    /// [`MAX_METER_RATE_HZ`]; 0 stops sampling.
+    SetMeterRate(u32, oneshot::Sender<AudioResult<()>>),
+    GetMixerView(oneshot::Sender<AudioResult<MixerView>>),
+    /// Save the view and re-emit the rows it changes.
+    SetMixerView(MixerView, oneshot::Sender<AudioResult<()>>),
+    Notify(BackendEvent),
 }
-unsafe fn internal_set_app_vol(
-    enumerator: &IMMDeviceEnumerator,
-    target_pid: u32,
-    vol: f32,
-) -> Result<()> {
-    let device = enumerator.GetDefaultAudioEndpoint(eRender, eMultimedia)?;
-    let session_manager: IAudioSessionManager2 =
-        device.Activate(CLSCTX_ALL, None::<*const PROPVARIANT>)?;
-    let session_enumerator = session_manager.GetSessionEnumerator()?;
-    for i in 0..session_enumerator.GetCount()? {
-        if let Ok(session_control) = session_enumerator.GetSession(i) {
-            if let Ok(sc2) = session_control.cast::<IAudioSessionControl2>() {
-                if sc2.GetProcessId()? == target_pid {
-                    if let Ok(sv) = session_control.cast::<ISimpleAudioVolume>() {
-                        let _ = sv.SetMasterVolume(vol, std::ptr::null());
-                    }
-                }
+
+/// What a write request overwrites. Of several pending writes to the same
+/// target only the last one is applied.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum WriteTarget {
+    MasterVolume,
+    MasterMute,
+    MicVolume,
+    MicMute,
+    AppVolume(u32),
+    AppMute(u32),
+    CaptureAppVolume(u32),
+    CaptureAppMute(u32),
+    GroupVolume(String),
+    GroupMute(String),
+}
+
+type WriteReply = oneshot::Sender<AudioResult<()>>;
+
+impl AudioRequest {
+    fn deadline(&self) -> Duration {
+        match self {
+            AudioRequest::SetDefaultDevice(..) | AudioRequest::ApplyScene(..) => {
+                SLOW_REQUEST_TIMEOUT
+            }
+            _ => REQUEST_TIMEOUT,
+        }
+    }
+
+    /// A read whose caller stopped waiting; answering it is wasted work.
+    /// Writes still run, since the caller may have given up on a change
+    /// that was about to land.
+    fn is_cancelled(&self) -> bool {
+        match self {
+            AudioRequest::GetMasterVolume(tx) | AudioRequest::GetMicVolume(tx) => tx.is_closed(),
+            AudioRequest::GetAppVolumes(tx) | AudioRequest::GetCaptureApps(tx) => tx.is_closed(),
+            AudioRequest::GetVolumeMemoryExclusions(tx) => tx.is_closed(),
+            AudioRequest::GetMixerView(tx) => tx.is_closed(),
+            AudioRequest::GetPlaybackDevices(tx) | AudioRequest::GetCaptureDevices(tx) => {
+                tx.is_closed()
             }
+            _ => false,
         }
     }
-    Ok(())
+
+    fn as_write(&mut self) -> Option<(WriteTarget, &mut WriteReply)> {
+        Some(match self {
+            AudioRequest::SetMasterVolume(_, tx) => (WriteTarget::MasterVolume, tx),
+            AudioRequest::SetMasterMute(_, tx) => (WriteTarget::MasterMute, tx),
+            AudioRequest::SetMicVolume(_, tx) => (WriteTarget::MicVolume, tx),
+            AudioRequest::SetMicMute(_, tx) => (WriteTarget::MicMute, tx),
+            AudioRequest::SetAppVolume(pid, _, tx) => (WriteTarget::AppVolume(*pid), tx),
+            AudioRequest::SetAppMute(pid, _, tx) => (WriteTarget::AppMute(*pid), tx),
+            AudioRequest::SetCaptureAppVolume(pid, _, tx) => {
+                (WriteTarget::CaptureAppVolume(*pid), tx)
+            }
+            AudioRequest::SetCaptureAppMute(pid, _, tx) => (WriteTarget::CaptureAppMute(*pid), tx),
+            AudioRequest::SetGroupVolume(group, _, tx) => {
+                (WriteTarget::GroupVolume(group.clone()), tx)
+            }
+            AudioRequest::SetGroupMute(group, _, tx) => (WriteTarget::GroupMute(group.clone()), tx),
+            _ => return None,
+        })
+    }
 }
 
-unsafe fn internal_set_app_mute(
-    enumerator: &IMMDeviceEnumerator,
-    target_pid: u32,
-    mute: bool,
-) -> Result<()> {
-    let device = enumerator.GetDefaultAudioEndpoint(eRender, eMultimedia)?;
-    let session_manager: IAudioSessionManager2 =
-        device.Activate(CLSCTX_ALL, None::<*const PROPVARIANT>)?;
-    let session_enumerator = session_manager.GetSessionEnumerator()?;
-    for i in 0..session_enumerator.GetCount()? {
-        if let Ok(session_control) = session_enumerator.GetSession(i) {
-            if let Ok(sc2) = session_control.cast::<IAudioSessionControl2>() {
-                if sc2.GetProcessId()? == target_pid {
-                    if let Ok(sv) = session_control.cast::<ISimpleAudioVolume>() {
-                        let _ = sv.SetMute(mute, std::ptr::null());
-                    }
-                }
+/// A request left after coalescing, plus the callers of the writes it
+/// replaced. They all get its result.
+struct Pending {
+    req: AudioRequest,
+    superseded: Vec<WriteReply>,
+}
+
+/// Drop every write that a later write in the same batch overrides, and
+/// repeats of the same change notification, keeping the order of everything
+/// else. Also returns how many requests were dropped.
+fn coalesce(batch: Vec<AudioRequest>) -> (Vec<Pending>, u64) {
+    let mut kept: Vec<Pending> = Vec::with_capacity(batch.len());
+    let mut latest: HashMap<WriteTarget, usize> = HashMap::new();
+    let mut notified: HashSet<BackendEvent> = HashSet::new();
+    let mut dropped = 0;
+    for mut req in batch.into_iter().rev() {
+        if let AudioRequest::Notify(event) = req {
+            if !notified.insert(event) {
+                dropped += 1;
+                continue;
             }
         }
+        if let Some((target, reply)) = req.as_write() {
+            if let Some(&i) = latest.get(&target) {
+                let reply = std::mem::replace(reply, oneshot::channel().0);
+                kept[i].superseded.push(reply);
+                dropped += 1;
+                continue;
+            }
+            latest.insert(target, kept.len());
+        }
+        kept.push(Pending {
+            req,
+            superseded: Vec::new(),
+        });
     }
-    Ok(())
+    kept.reverse();
+    (kept, dropped)
 }
 
-// PowerShell Script with C# embedding for safe Process Isolation
-// This prevents STATUS_ACCESS_VIOLATION in the main app by moving the dangerous COM interaction to a child process.
-const POWERSHELL_SWITCH_SCRIPT: &str = r#"
-param($DeviceId)
+/// Everything the audio worker needs from the platform. Implementations are
+/// created on the worker thread and never leave it, so they need not be `Send`.
+pub trait AudioBackend {
+    fn master_volume(&mut self) -> AudioResult<(f32, bool)>;
+    fn mic_volume(&mut self) -> AudioResult<(f32, bool)>;
+    fn set_master_volume(&mut self, vol: f32) -> AudioResult<()>;
+    fn set_mic_volume(&mut self, vol: f32) -> AudioResult<()>;
+    fn set_master_mute(&mut self, mute: bool) -> AudioResult<()>;
+    fn set_mic_mute(&mut self, mute: bool) -> AudioResult<()>;
+    fn app_sessions(&mut self) -> AudioResult<Vec<AppSession>>;
+    fn set_app_volume(&mut self, pid: u32, vol: f32) -> AudioResult<()>;
+    fn set_app_mute(&mut self, pid: u32, mute: bool) -> AudioResult<()>;
+    /// Sessions of every capture device. Backends that cannot tell keep
+    /// the default and report none.
+    fn capture_sessions(&mut self) -> AudioResult<Vec<AppSession>> {
+        Ok(Vec::new())
+    }
+    fn set_capture_app_volume(&mut self, _pid: u32, _vol: f32) -> AudioResult<()> {
+        Err(ControlError::BackendUnavailable(
+            "Per-app microphone volume is not supported by this audio backend".into(),
+        ))
+    }
+    fn set_capture_app_mute(&mut self, _pid: u32, _mute: bool) -> AudioResult<()> {
+        Err(ControlError::BackendUnavailable(
+            "Per-app microphone mute is not supported by this audio backend".into(),
+        ))
+    }
+    fn playback_devices(&mut self) -> AudioResult<Vec<AudioDevice>>;
+    fn capture_devices(&mut self) -> AudioResult<Vec<AudioDevice>>;
+    fn set_default_device(&mut self, id: &str, roles: &[DeviceRole]) -> AudioResult<()>;
+    /// Send one process's playback to `device_id`, or back to whatever the
+    /// default is with `None`.
+    fn set_app_output_device(&mut self, _pid: u32, _device_id: Option<&str>) -> AudioResult<()> {
+        Err(ControlError::BackendUnavailable(
+            "Per-app output devices are not supported by this audio backend".into(),
+        ))
+    }
+    /// Output devices of the given processes. Processes that follow the
+    /// default device are left out.
+    fn app_output_devices(&mut self, _pids: &[u32]) -> HashMap<u32, String> {
+        HashMap::new()
+    }
+    /// Resolve display name and icon for processes missing from the cache.
+    /// Pids that cannot be resolved are simply left out of the result.
+    fn describe_processes(&mut self, pids: &[u32]) -> HashMap<u32, ProcessInfo>;
+    /// Opaque start time of a process, to tell a recycled pid from the
+    /// process cached under it. `None` once the process has exited, or when
+    /// the backend cannot tell; cache entries then age out instead.
+    fn process_start_time(&mut self, _pid: u32) -> Option<u64> {
+        None
+    }
+    /// The process that started `pid`, used to attribute helper processes
+    /// to their application. `None` when unknown or the process is gone.
+    fn parent_pid(&mut self, _pid: u32) -> Option<u32> {
+        None
+    }
+    /// The executable's icon at about `size` pixels square, for the icon
+    /// store. Processes described with an `icon_path` of their own are not
+    /// asked. Also given [`AppSession::icon_path`], which may name an icon
+    /// resource as `<file>,<index>`.
+    fn render_icon(&mut self, _exe_path: &str, _size: u32) -> Option<IconImage> {
+        None
+    }
+    /// Sample the default endpoints' and every session's peak meter.
+    fn peaks(&mut self) -> AudioResult<Peaks> {
+        Err(ControlError::BackendUnavailable(
+            "Peak meters are not supported by this audio backend".into(),
+        ))
+    }
+    /// Start reporting changes through `notifier`. Backends without change
+    /// notifications keep the default, and the UI only sees its own writes.
+    fn subscribe(&mut self, _notifier: Notifier) -> AudioResult<()> {
+        Ok(())
+    }
+}
 
-$Source = @"
-using System;
-using System.Runtime.InteropServices;
+/// Entries whose process no longer has a session are kept this long, so an
+/// app that closes and reopens its stream keeps its icon.
+const CACHE_SESSION_GRACE: Duration = Duration::from_secs(5 * 60);
+/// How often the start times of known processes are compared, to notice
+/// reused pids and exited processes. New pids are looked up right away.
+const CACHE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Upper bounds for the cache; past them the entries seen longest ago go
+/// first. Icons dominate the size.
+const CACHE_MAX_ENTRIES: usize = 512;
+const CACHE_MAX_BYTES: usize = 16 * 1024 * 1024;
 
-[ComImport, Guid("870af99c-171d-4f9e-af0d-e63df40c2bc9")]
-public class PolicyConfigClient { }
+/// Payload of `get_cache_stats`.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub entries: usize,
+    /// Approximate size of the cached names, paths and icons.
+    pub bytes: usize,
+    pub max_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Process exited.
+    pub evicted_exited: u64,
+    /// Pid reused by another process.
+    pub evicted_recycled: u64,
+    /// No session for longer than the grace period.
+    pub evicted_expired: u64,
+    /// Pushed out by the size cap.
+    pub evicted_capacity: u64,
+}
 
-[ComImport, Guid("f8679f50-850a-41cf-9c72-430f290290c8"), InterfaceType(ComInterfaceType.InterfaceIsIUnknown)]
-public interface IPolicyConfig {
-    [PreserveSig] int GetMixFormat(string PCWSTR_id, out IntPtr __WAVEFORMATEX_ppFormat);
-    [PreserveSig] int GetDeviceFormat(string PCWSTR_id, int INT_bDefault, out IntPtr __WAVEFORMATEX_ppFormat);
-    [PreserveSig] int ResetDeviceFormat(string PCWSTR_id);
-    [PreserveSig] int SetDeviceFormat(string PCWSTR_id, IntPtr __WAVEFORMATEX_pEndpointFormat, IntPtr __WAVEFORMATEX_pMixFormat);
-    [PreserveSig] int GetProcessingPeriod(string PCWSTR_id, int INT_bDefault, out long __PINT64_pmftDefaultPeriod, out long __PINT64_pmftMinimumPeriod);
-    [PreserveSig] int SetProcessingPeriod(string PCWSTR_id, long __PINT64_pmftPeriod);
-    [PreserveSig] int GetShareMode(string PCWSTR_id, out IntPtr __DeviceShareMode_pMode);
-    [PreserveSig] int SetShareMode(string PCWSTR_id, IntPtr __DeviceShareMode_mode);
-    [PreserveSig] int GetPropertyValue(string PCWSTR_id, IntPtr __PROPERTYKEY_pKey, out IntPtr __PROPVARIANT_pv);
-    [PreserveSig] int SetPropertyValue(string PCWSTR_id, IntPtr __PROPERTYKEY_pKey, IntPtr __PROPVARIANT_pv);
-    [PreserveSig] int SetDefaultEndpoint(string PCWSTR_id, int INT_role);
-    [PreserveSig] int SetEndpointVisibility(string PCWSTR_id, int INT_bVisible);
+struct CacheEntry {
+    info: ProcessInfo,
+    started: Option<u64>,
+    last_seen: Instant,
 }
 
-public class AudioSwitcher {
-    public static int SetDefault(string deviceId) {
-        try {
-            var policyConfig = new PolicyConfigClient();
-            var policyInterface = (IPolicyConfig)policyConfig;
-            // Role: 0=eConsole, 1=eMultimedia, 2=eCommunications
-            policyInterface.SetDefaultEndpoint(deviceId, 0); 
-            policyInterface.SetDefaultEndpoint(deviceId, 1);
-            policyInterface.SetDefaultEndpoint(deviceId, 2);
-            return 0;
-        } catch (Exception e) {
-            Console.WriteLine("Error: " + e.Message);
-            return 1;
-        }
+impl CacheEntry {
+    fn size(&self) -> usize {
+        self.info.name.len() + self.info.icon_path.len() + self.info.exe_path.len()
     }
 }
-"@
 
-Add-Type -TypeDefinition $Source -Language CSharp
-[AudioSwitcher]::SetDefault($DeviceId)
-"#;
+/// Descriptions of processes with audio sessions, keyed by pid.
+#[derive(Default)]
+pub struct ProcessCache {
+    entries: HashMap<u32, CacheEntry>,
+    bytes: usize,
+    stats: CacheStats,
+    /// When start times are next compared; `None` until the first refresh.
+    next_check: Option<Instant>,
+}
 
-unsafe fn set_default_device(id: &str) -> Result<()> {
-    println!(
-        "DEBUG: AUDIO FIX V30 (Process Isolation) START for device {}",
-        id
-    );
+impl ProcessCache {
+    pub fn get(&self, pid: &u32) -> Option<&ProcessInfo> {
+        self.entries.get(pid).map(|e| &e.info)
+    }
 
-    use std::io::Write;
-    use std::process::Command;
+    /// Whether the start times of known processes are due for another
+    /// look. Starts the next interval when they are.
+    fn check_due(&mut self, now: Instant) -> bool {
+        if self.next_check.is_some_and(|at| now < at) {
+            return false;
+        }
+        self.next_check = Some(now + CACHE_CHECK_INTERVAL);
+        true
+    }
 
-    // 1. Prepare temp script path
-    let mut temp_path = std::env::temp_dir();
-    temp_path.push("tauri_audio_switch_v30.ps1");
+    fn insert(&mut self, pid: u32, info: ProcessInfo, started: Option<u64>, now: Instant) {
+        let entry = CacheEntry {
+            info,
+            started,
+            last_seen: now,
+        };
+        self.bytes += entry.size();
+        if let Some(old) = self.entries.insert(pid, entry) {
+            self.bytes -= old.size();
+        }
+    }
 
-    // 2. Write script to file
-    if let Ok(mut file) = std::fs::File::create(&temp_path) {
-        if let Err(e) = file.write_all(POWERSHELL_SWITCH_SCRIPT.as_bytes()) {
-            println!("ERROR: Failed to write temp PowerShell script: {}", e);
-            return Ok(());
+    fn remove(&mut self, pid: u32) {
+        if let Some(old) = self.entries.remove(&pid) {
+            self.bytes -= old.size();
         }
-    } else {
-        println!("ERROR: Failed to create temp file for PowerShell script");
-        return Ok(());
-    }
-
-    // 3. Execute PowerShell
-    println!("  Executing PowerShell wrapper...");
-    use std::os::windows::process::CommandExt;
-    let output = Command::new("powershell")
-        .creation_flags(0x08000000) // CREATE_NO_WINDOW
-        .args(&[
-            "-NoProfile",
-            "-NonInteractive",
-            "-ExecutionPolicy",
-            "Bypass",
-            "-File",
-            temp_path.to_str().unwrap_or(""),
-            "-DeviceId",
-            id,
-        ])
-        .output();
-
-    // 4. Handle Result
-    match output {
-        Ok(out) => {
-            if out.status.success() {
-                println!("  Process exited successfully.");
-                // Optional: verify
-                thread::sleep(std::time::Duration::from_millis(150));
-                if verify_default_device(id) {
-                    println!("  SUCCESS: Device switched verified.");
-                } else {
-                    println!("  WARNING: Process finished but verification failed.");
+    }
+
+    /// Mark the live pids as seen and return those that need describing:
+    /// unknown ones, and ones whose start time shows the pid was reused. A
+    /// pid without a start time is taken to be the process already known.
+    fn refresh(&mut self, live: &[(u32, Option<u64>)], now: Instant) -> Vec<u32> {
+        let mut missing = Vec::new();
+        for &(pid, started) in live {
+            match self.entries.get_mut(&pid) {
+                Some(e) if e.started.is_some() && started.is_some() && e.started != started => {
+                    self.stats.evicted_recycled += 1;
+                    self.stats.misses += 1;
+                    self.remove(pid);
+                    missing.push(pid);
                 }
+                Some(e) => {
+                    e.last_seen = now;
+                    self.stats.hits += 1;
+                }
+                None => {
+                    self.stats.misses += 1;
+                    missing.push(pid);
+                }
+            }
+        }
+        missing
+    }
+
+    /// Drop entries without a live session whose process exited or whose
+    /// grace period ran out, then enforce the size cap. Live entries are
+    /// never evicted, whatever their size. Exits are only looked for when
+    /// `start_time` is given.
+    fn evict(
+        &mut self,
+        live: &[(u32, Option<u64>)],
+        now: Instant,
+        mut start_time: Option<impl FnMut(u32) -> Option<u64>>,
+    ) {
+        let live: HashSet<u32> = live.iter().map(|&(pid, _)| pid).collect();
+        let idle: Vec<u32> = self
+            .entries
+            .keys()
+            .filter(|pid| !live.contains(pid))
+            .copied()
+            .collect();
+        for pid in idle {
+            let e = &self.entries[&pid];
+            if now.duration_since(e.last_seen) >= CACHE_SESSION_GRACE {
+                self.stats.evicted_expired += 1;
+            } else if e.started.is_some()
+                && start_time.as_mut().is_some_and(|f| f(pid) != e.started)
+            {
+                self.stats.evicted_exited += 1;
             } else {
-                println!("  Process exited with error code: {:?}", out.status.code());
-                let stderr = String::from_utf8_lossy(&out.stderr);
-                println!("  STDERR: {}", stderr);
+                continue;
             }
+            self.remove(pid);
         }
-        Err(e) => {
-            println!("  ERROR: Failed to spawn PowerShell process: {}", e);
+        if self.entries.len() <= CACHE_MAX_ENTRIES && self.bytes <= CACHE_MAX_BYTES {
+            return;
+        }
+        let mut by_age: Vec<(Instant, u32)> = self
+            .entries
+            .iter()
+            .filter(|(pid, _)| !live.contains(pid))
+            .map(|(&pid, e)| (e.last_seen, pid))
+            .collect();
+        by_age.sort_unstable();
+        for (_, pid) in by_age {
+            if self.entries.len() <= CACHE_MAX_ENTRIES && self.bytes <= CACHE_MAX_BYTES {
+                break;
+            }
+            self.stats.evicted_capacity += 1;
+            self.remove(pid);
         }
     }
 
-    Ok(())
-}
+    /// Forget every description, so the next refresh describes all live
+    /// pids again.
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
 
-unsafe fn verify_default_device(target_id: &str) -> bool {
-    if let Ok(enumerator) =
-        CoCreateInstance::<_, IMMDeviceEnumerator>(&MMDeviceEnumerator, None, CLSCTX_ALL)
-    {
-        for role in [eMultimedia, eConsole, eCommunications] {
-            if let Ok(def_dev) = enumerator.GetDefaultAudioEndpoint(eRender, role) {
-                if let Ok(def_id) = def_dev.GetId() {
-                    let s = def_id.to_string().unwrap_or_default();
-                    println!("    Verify Role {:?}: {}", role, s);
-                    CoTaskMemFree(Some(def_id.as_ptr() as *const c_void));
-                    if s.to_lowercase() == target_id.to_lowercase() {
-                        if role == eMultimedia {
-                            return true;
-                        }
-                    }
-                }
-            }
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            bytes: self.bytes,
+            max_bytes: CACHE_MAX_BYTES,
+            ..self.stats.clone()
         }
     }
-    false
-}
-
-pub unsafe fn get_audio_endpoints(
-    enumerator: &IMMDeviceEnumerator,
-    data_flow: EDataFlow,
-) -> Result<Vec<AudioDevice>> {
-    let mut devices = Vec::new();
-    let collection = enumerator.EnumAudioEndpoints(data_flow, DEVICE_STATE_ACTIVE)?;
-    let count = collection.GetCount()?;
-    for i in 0..count {
-        let device = collection.Item(i)?;
-        if let Ok(id_ptr) = device.GetId() {
-            let id = id_ptr.to_string().unwrap_or_default();
-            CoTaskMemFree(Some(id_ptr.as_ptr() as *const c_void));
-            let props = device.OpenPropertyStore(STGM_READ)?;
-            let mut name = String::new();
-            if let Ok(mut val) = props.GetValue(&PKEY_Device_FriendlyName) {
-                if !val.Anonymous.Anonymous.Anonymous.pwszVal.is_null() {
-                    name = val
-                        .Anonymous
-                        .Anonymous
-                        .Anonymous
-                        .pwszVal
-                        .to_string()
-                        .unwrap_or_default();
-                }
-                let _ = PropVariantClear(&mut val as *mut _);
-            }
-            devices.push(AudioDevice {
-                id: id.clone(),
-                name: if name.is_empty() {
-                    id.clone()
-                } else {
-                    name.clone()
-                },
-                is_default: false,
-            });
+}
+
+pub struct AppCache {
+    pub processes: Mutex<ProcessCache>,
+    pub icons: IconStore,
+    helpers: Mutex<HelperRules>,
+}
+
+impl AppCache {
+    pub fn new(icons: IconStore, helpers: HelperRules) -> Self {
+        Self {
+            processes: Mutex::new(ProcessCache::default()),
+            icons,
+            helpers: Mutex::new(helpers),
         }
     }
-    if let Ok(def_dev) = enumerator.GetDefaultAudioEndpoint(data_flow, eMultimedia) {
-        if let Ok(def_id) = def_dev.GetId() {
-            let s = def_id.to_string().unwrap_or_default();
-            for d in &mut devices {
-                if d.id == s {
-                    d.is_default = true;
-                }
-            }
-            CoTaskMemFree(Some(def_id.as_ptr() as *const c_void));
+
+    pub fn helper_rules(&self) -> Vec<String> {
+        lock(&self.helpers).patterns()
+    }
+
+    /// Replace the helper list. When it changed, cached descriptions are
+    /// dropped so sessions regroup on the next refresh.
+    pub fn set_helper_rules(&self, patterns: Vec<String>) -> AudioResult<()> {
+        let mut helpers = lock(&self.helpers);
+        if helpers.patterns() == patterns {
+            return Ok(());
         }
+        helpers.set(patterns)?;
+        lock(&self.processes).clear();
+        Ok(())
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        lock(&self.processes).stats()
     }
-    devices.sort_by(|a, b| a.name.cmp(&b.name));
-    Ok(devices)
 }
 
-fn update_cache_batch(pids: &[u32], cache: &AppCache) {
-    let mut missing_pids = Vec::new();
-    if let Ok(map) = cache.names.lock() {
-        for &pid in pids {
-            if !map.contains_key(&pid) {
-                missing_pids.push(pid);
-            }
+pub struct AudioState {
+    queue: Queue,
+    health: Arc<Mutex<HealthState>>,
+}
+
+impl AudioState {
+    #[cfg(target_os = "windows")]
+    pub fn new(
+        cache: Arc<AppCache>,
+        events: EventSink,
+        memory: VolumeMemory,
+        view: ViewStore,
+    ) -> Self {
+        Self::with_backend(
+            cache,
+            events,
+            memory,
+            view,
+            Arc::new(|| {
+                let b = wasapi::WasapiBackend::new()?;
+                Ok(Box::new(b) as Box<dyn AudioBackend>)
+            }),
+        )
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn new(
+        cache: Arc<AppCache>,
+        events: EventSink,
+        memory: VolumeMemory,
+        view: ViewStore,
+    ) -> Self {
+        Self::with_backend(
+            cache,
+            events,
+            memory,
+            view,
+            Arc::new(|| {
+                let b = pulse::PulseBackend::new()?;
+                Ok(Box::new(b) as Box<dyn AudioBackend>)
+            }),
+        )
+    }
+
+    /// Spawn the worker thread around a backend factory, and a supervisor
+    /// that replaces the worker when it panics or hangs. The factory runs
+    /// on the worker thread and is retried on the next request while it
+    /// fails.
+    pub fn with_backend(
+        cache: Arc<AppCache>,
+        events: EventSink,
+        memory: VolumeMemory,
+        view: ViewStore,
+        factory: BackendFactory,
+    ) -> Self {
+        let (queue, rx) = Queue::new();
+        let health = Arc::new(Mutex::new(HealthState {
+            started: Instant::now(),
+            restarts: 0,
+            last_error: None,
+            connected: false,
+        }));
+        let shared = Shared {
+            cache,
+            events,
+            memory: Arc::new(Mutex::new(memory)),
+            view: Arc::new(Mutex::new(view)),
+            factory,
+            queue: queue.clone(),
+            health: health.clone(),
+        };
+        let (handle, heartbeat) = spawn_worker(shared.clone(), rx);
+        thread::spawn(move || supervise(shared, handle, heartbeat));
+        Self { queue, health }
+    }
+
+    pub fn queue_stats(&self) -> QueueStats {
+        self.queue.stats()
+    }
+
+    pub fn health(&self) -> BackendHealth {
+        let h = lock(&self.health);
+        BackendHealth {
+            uptime_secs: h.started.elapsed().as_secs(),
+            restarts: h.restarts,
+            last_error: h.last_error.clone(),
+            connected: h.connected,
         }
     }
-    if missing_pids.is_empty() {
-        return;
+
+    /// Shared by the volume commands: check the level, then queue the
+    /// request `make` builds around it.
+    pub async fn request_volume(
+        &self,
+        vol: f32,
+        make: impl FnOnce(f32, oneshot::Sender<AudioResult<()>>) -> AudioRequest,
+    ) -> AudioResult<()> {
+        let vol = crate::validate::volume(vol)?;
+        self.request(|tx| make(vol, tx)).await
     }
-    let mut found_names = HashMap::new();
-    unsafe {
-        if let Ok(handle) = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) {
-            let mut pe = PROCESSENTRY32W {
-                dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
-                ..Default::default()
-            };
-            if Process32FirstW(handle, &mut pe).is_ok() {
-                loop {
-                    if missing_pids.contains(&pe.th32ProcessID) {
-                        let name = String::from_utf16_lossy(&pe.szExeFile)
-                            .trim_matches('\0')
-                            .to_string();
-                        let mut path = String::new();
-                        if let Ok(h_proc) = OpenProcess(
-                            PROCESS_QUERY_LIMITED_INFORMATION,
-                            windows::Win32::Foundation::FALSE,
-                            pe.th32ProcessID,
-                        ) {
-                            let mut buffer = [0u16; 1024];
-                            let mut size = buffer.len() as u32;
-                            if QueryFullProcessImageNameW(
-                                h_proc,
-                                PROCESS_NAME_WIN32,
-                                PWSTR(buffer.as_mut_ptr()),
-                                &mut size,
-                            )
-                            .is_ok()
-                            {
-                                path = String::from_utf16_lossy(&buffer[..size as usize]);
-                            }
-                            let _ = CloseHandle(h_proc);
-                        }
-                        let icon_b64 = if !path.is_empty() {
-                            get_icon_as_base64(&path)
-                        } else {
-                            String::new()
-                        };
-                        found_names.insert(pe.th32ProcessID, (name, icon_b64));
-                    }
-                    if Process32NextW(handle, &mut pe).is_err() {
-                        break;
-                    }
-                }
+
+    pub async fn request_meter_rate(&self, hz: u32) -> AudioResult<()> {
+        let hz = crate::validate::meter_rate(hz)?;
+        self.request(|tx| AudioRequest::SetMeterRate(hz, tx)).await
+    }
+
+    /// Send a request carrying a oneshot reply channel and await the answer
+    /// until the request's deadline. The worker drops requests it has no
+    /// backend for, which surfaces here as
+    /// [`ControlError::BackendUnavailable`].
+    pub async fn request<T>(
+        &self,
+        make: impl FnOnce(oneshot::Sender<AudioResult<T>>) -> AudioRequest,
+    ) -> AudioResult<T> {
+        let unavailable = || ControlError::BackendUnavailable("No audio backend available".into());
+        let (tx, rx) = oneshot::channel();
+        let req = make(tx);
+        let deadline = req.deadline();
+        self.queue.send(req).map_err(|_| unavailable())?;
+        match tokio::time::timeout(deadline, rx).await {
+            Ok(res) => res.map_err(|_| unavailable())?,
+            Err(_) => Err(ControlError::Timeout(format!(
+                "The audio backend did not answer within {}s",
+                deadline.as_secs()
+            ))),
+        }
+    }
+}
+
+/// What outlives a single worker. The supervisor hands it to every
+/// replacement, so volume memory, the mixer view, the app cache and the
+/// frontend's event sink carry over; backend and mixer state are rebuilt
+/// from scratch.
+#[derive(Clone)]
+struct Shared {
+    cache: Arc<AppCache>,
+    events: EventSink,
+    memory: Arc<Mutex<VolumeMemory>>,
+    view: Arc<Mutex<ViewStore>>,
+    factory: BackendFactory,
+    queue: Queue,
+    health: Arc<Mutex<HealthState>>,
+}
+
+fn spawn_worker(shared: Shared, rx: Receiver<AudioRequest>) -> (JoinHandle<()>, Arc<Heartbeat>) {
+    let heartbeat = Arc::new(Heartbeat::default());
+    let hb = heartbeat.clone();
+    let handle = thread::spawn(move || run_worker(shared, rx, &hb));
+    (handle, heartbeat)
+}
+
+/// Watch the worker and start a new one, on a fresh channel and with a
+/// fresh backend, once it has died or been busy for [`STUCK_AFTER`]. A hung
+/// thread cannot be killed; it is abandoned and exits if it ever returns.
+fn supervise(shared: Shared, mut handle: JoinHandle<()>, mut heartbeat: Arc<Heartbeat>) {
+    loop {
+        thread::sleep(SUPERVISE_INTERVAL);
+        let reason = if handle.is_finished() {
+            match handle.join() {
+                Err(panic) => format!("Audio worker panicked: {}", panic_message(&*panic)),
+                Ok(()) => "Audio worker exited".to_string(),
             }
-            let _ = CloseHandle(handle);
-        }
-    }
-    for &pid in &missing_pids {
-        if !found_names.contains_key(&pid) {
-            unsafe {
-                if let Ok(handle) = OpenProcess(
-                    PROCESS_QUERY_INFORMATION | PROCESS_VM_READ,
-                    windows::Win32::Foundation::FALSE,
-                    pid,
-                ) {
-                    let mut buf = [0u16; 1024];
-                    let len = GetModuleBaseNameW(handle, None, &mut buf);
-                    if len > 0 {
-                        let name = String::from_utf16_lossy(&buf[..len as usize]);
-                        let mut path = String::new();
-                        if let Ok(h_proc) = OpenProcess(
-                            PROCESS_QUERY_LIMITED_INFORMATION,
-                            windows::Win32::Foundation::FALSE,
-                            pid,
-                        ) {
-                            let mut buffer = [0u16; 1024];
-                            let mut size = buffer.len() as u32;
-                            if QueryFullProcessImageNameW(
-                                h_proc,
-                                PROCESS_NAME_WIN32,
-                                PWSTR(buffer.as_mut_ptr()),
-                                &mut size,
-                            )
-                            .is_ok()
-                            {
-                                path = String::from_utf16_lossy(&buffer[..size as usize]);
-                            }
-                            let _ = CloseHandle(h_proc);
-                        }
-                        let icon_b64 = if !path.is_empty() {
-                            get_icon_as_base64(&path)
-                        } else {
-                            String::new()
-                        };
-                        found_names.insert(pid, (name, icon_b64));
-                    }
-                    let _ = CloseHandle(handle);
+        } else {
+            let busy = *lock(&heartbeat.busy_since);
+            match busy.map(|since| since.elapsed()) {
+                Some(busy) if busy >= STUCK_AFTER => {
+                    heartbeat.abandoned.store(true, Ordering::Relaxed);
+                    format!("Audio worker stuck for {}s", busy.as_secs())
                 }
+                _ => continue,
             }
+        };
+        println!("{}; restarting it", reason);
+        {
+            let mut h = lock(&shared.health);
+            h.started = Instant::now();
+            h.restarts += 1;
+            h.last_error = Some(reason);
+            h.connected = false;
         }
+        let rx = shared.queue.reconnect();
+        (handle, heartbeat) = spawn_worker(shared.clone(), rx);
     }
-    if let Ok(mut map) = cache.names.lock() {
-        for pid in missing_pids {
-            let data = found_names
-                .remove(&pid)
-                .unwrap_or_else(|| (format!("App {}", pid), "".into()));
-            map.insert(pid, data);
-        }
-    }
-}
-
-fn get_icon_as_base64(path: &str) -> String {
-    unsafe {
-        let path_v16: Vec<u16> = path.encode_utf16().chain(std::iter::once(0)).collect();
-        let mut h_icons = [HICON::default(); 1];
-        let mut path_fixed = [0u16; 260];
-        let copy_len = path_v16.len().min(260);
-        path_fixed[..copy_len].copy_from_slice(&path_v16[..copy_len]);
-        let count = PrivateExtractIconsW(&path_fixed, 0, 48, 48, Some(&mut h_icons), None, 0);
-        if count == 0 || h_icons[0].0 == 0 {
-            // Fallback 1: ExtractIconExW
-            let mut h_large = [HICON::default(); 1];
-            if ExtractIconExW(
-                windows::core::PCWSTR(path_v16.as_ptr()),
-                0,
-                Some(h_large.as_mut_ptr()),
-                None,
-                1,
-            ) > 0
-            {
-                h_icons[0] = h_large[0];
-            }
-        }
+}
 
-        if h_icons[0].0 == 0 {
-            // Fallback 2: SHGetFileInfoW (Shell Icon - most robust for system apps)
-            let mut shfi = SHFILEINFOW::default();
-            if SHGetFileInfoW(
-                windows::core::PCWSTR(path_v16.as_ptr()),
-                windows::Win32::Storage::FileSystem::FILE_FLAGS_AND_ATTRIBUTES(0),
-                Some(&mut shfi),
-                std::mem::size_of::<SHFILEINFOW>() as u32,
-                SHGFI_ICON | SHGFI_LARGEICON,
-            ) != 0
-            {
-                h_icons[0] = shfi.hIcon;
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(s) = panic.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s
+    } else {
+        "unknown panic"
+    }
+}
+
+fn run_worker(shared: Shared, rx: Receiver<AudioRequest>, heartbeat: &Heartbeat) {
+    let notifier = Notifier(shared.queue.clone());
+    let mut worker = Worker {
+        cache: shared.cache.clone(),
+        events: shared.events.clone(),
+        notifier: notifier.clone(),
+        last_apps: None,
+        last_capture: None,
+        group_targets: HashMap::new(),
+        memory: shared.memory.clone(),
+        view: shared.view.clone(),
+        last_active: HashMap::new(),
+        known_sessions: None,
+        meter_interval: None,
+        last_levels: None,
+    };
+    let connect = || {
+        let res = heartbeat.beat(|| (shared.factory)());
+        let mut h = lock(&shared.health);
+        h.connected = res.is_ok();
+        let mut b = match res {
+            Ok(b) => b,
+            Err(e) => {
+                println!("Audio backend unavailable: {}", e);
+                h.last_error = Some(e.to_string());
+                return None;
             }
+        };
+        drop(h);
+        if let Err(e) = b.subscribe(notifier.clone()) {
+            println!("Audio change notifications unavailable: {}", e);
         }
-
-        if h_icons[0].0 != 0 {
-            let h_icon = h_icons[0];
-            let mut icon_info = ICONINFO::default();
-            if GetIconInfo(h_icon, &mut icon_info).is_ok() {
-                let h_bm = if icon_info.hbmColor.0 != 0 {
-                    icon_info.hbmColor
-                } else {
-                    icon_info.hbmMask
-                };
-                let mut bm = BITMAP::default();
-                if GetObjectW(
-                    h_bm,
-                    std::mem::size_of::<BITMAP>() as i32,
-                    Some(&mut bm as *mut _ as *mut _),
-                ) > 0
-                {
-                    let width = bm.bmWidth;
-                    let height = bm.bmHeight;
-                    let hdc_screen = windows::Win32::Graphics::Gdi::GetDC(None);
-                    let hdc_mem = CreateCompatibleDC(hdc_screen);
-                    let old_bm = SelectObject(hdc_mem, h_bm);
-                    let mut bmi = BITMAPINFO {
-                        bmiHeader: BITMAPINFOHEADER {
-                            biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
-                            biWidth: width,
-                            biHeight: -height,
-                            biPlanes: 1,
-                            biBitCount: 32,
-                            biCompression: 0,
-                            ..Default::default()
-                        },
-                        ..Default::default()
-                    };
-                    let mut buffer: Vec<u8> = vec![0; (width * height * 4) as usize];
-                    let ret = GetDIBits(
-                        hdc_mem,
-                        h_bm,
-                        0,
-                        height as u32,
-                        Some(buffer.as_mut_ptr() as *mut _),
-                        &mut bmi,
-                        DIB_RGB_COLORS,
-                    );
-                    if !old_bm.is_invalid() {
-                        SelectObject(hdc_mem, old_bm);
+        // Apps already recording get the tray badge without waiting for
+        // their next change.
+        notifier.notify(BackendEvent::CaptureSessions);
+        Some(b)
+    };
+    let mut backend = connect();
+    let mut next_sample = Instant::now();
+    let mut next_sweep = Instant::now() + HIDE_SWEEP_INTERVAL;
+    while !heartbeat.is_abandoned() {
+        // Block for requests, waking up for the next meter sample and, while
+        // inactive rows are hidden, the next look for rows to hide.
+        let wake = [
+            worker.meter_interval.map(|_| next_sample),
+            worker.hides_inactive().then_some(next_sweep),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let (mut sample, mut sweep) = (false, false);
+        let req = match wake {
+            Some(wake) => match rx.recv_timeout(wake.saturating_duration_since(Instant::now())) {
+                Ok(req) => Some(req),
+                Err(RecvTimeoutError::Timeout) => {
+                    let now = Instant::now();
+                    if let Some(interval) = worker.meter_interval.filter(|_| now >= next_sample) {
+                        next_sample = now + interval;
+                        sample = true;
                     }
-                    let _ = DeleteDC(hdc_mem);
-                    let _ = windows::Win32::Graphics::Gdi::ReleaseDC(None, hdc_screen);
-                    if ret > 0 {
-                        for chunk in buffer.chunks_exact_mut(4) {
-                            chunk.swap(0, 2);
-                        }
-                        let mut png_data = Vec::new();
-                        use image::ImageEncoder;
-                        let encoder = image::codecs::png::PngEncoder::new(&mut png_data);
-                        if encoder
-                            .write_image(
-                                &buffer,
-                                width as u32,
-                                height as u32,
-                                image::ColorType::Rgba8.into(),
-                            )
-                            .is_ok()
-                        {
-                            let b64 = general_purpose::STANDARD.encode(png_data);
-                            if icon_info.hbmColor.0 != 0 {
-                                let _ = DeleteObject(icon_info.hbmColor);
-                            }
-                            if icon_info.hbmMask.0 != 0 {
-                                let _ = DeleteObject(icon_info.hbmMask);
-                            }
-                            let _ = DestroyIcon(h_icon);
-                            return format!("data:image/png;base64,{}", b64);
-                        }
+                    if now >= next_sweep {
+                        next_sweep = now + HIDE_SWEEP_INTERVAL;
+                        sweep = true;
                     }
+                    None
                 }
-                if icon_info.hbmColor.0 != 0 {
-                    let _ = DeleteObject(icon_info.hbmColor);
-                }
-                if icon_info.hbmMask.0 != 0 {
-                    let _ = DeleteObject(icon_info.hbmMask);
-                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(req) => Some(req),
+                Err(_) => break,
+            },
+        };
+        if backend.is_none() {
+            backend = connect();
+        }
+        let Some(ref mut b) = backend else {
+            // Drop the rest of the queue too, so callers hear about it
+            // now rather than one request at a time.
+            if let Some(req) = req {
+                shared.queue.drain(&rx, req);
+            }
+            continue;
+        };
+        let Some(req) = req else {
+            if sample {
+                heartbeat.beat(|| worker.sample_levels(b.as_mut()));
+            }
+            if sweep && worker.hides_inactive() {
+                heartbeat.beat(|| worker.notify(b.as_mut(), BackendEvent::Sessions));
+            }
+            continue;
+        };
+        let (batch, dropped) = coalesce(shared.queue.drain(&rx, req));
+        shared
+            .queue
+            .counters
+            .coalesced
+            .fetch_add(dropped, Ordering::Relaxed);
+        for pending in batch {
+            if heartbeat.is_abandoned() {
+                break;
             }
-            let _ = DestroyIcon(h_icon);
+            if pending.req.is_cancelled() {
+                continue;
+            }
+            heartbeat.beat(|| worker.handle_pending(b.as_mut(), pending));
+        }
+    }
+}
+
+/// State the worker thread keeps between requests.
+struct Worker {
+    cache: Arc<AppCache>,
+    events: EventSink,
+    notifier: Notifier,
+    /// Mixer rows as last seen by the UI, to diff session notifications against.
+    last_apps: Option<Vec<AppVolume>>,
+    /// Capture rows as last reported, to tell when an app starts or stops
+    /// recording.
+    last_capture: Option<Vec<AppVolume>>,
+    /// Group-level settings still being applied to sessions that join later.
+    group_targets: HashMap<String, GroupTarget>,
+    memory: Arc<Mutex<VolumeMemory>>,
+    view: Arc<Mutex<ViewStore>>,
+    /// When each row was last seen active, keyed by group.
+    last_active: HashMap<String, u64>,
+    /// Session ids seen on the previous pass, to spot sessions that just
+    /// appeared. `None` until the first pass, whose sessions are left as-is.
+    known_sessions: Option<HashSet<String>>,
+    /// Time between peak meter samples; `None` while metering is off.
+    meter_interval: Option<Duration>,
+    /// Last `audio://levels` payload, so silence is only reported once.
+    last_levels: Option<Levels>,
+}
+
+/// A group-level volume/mute and the sessions it has been applied to.
+#[derive(Default)]
+struct GroupTarget {
+    volume: Option<f32>,
+    mute: Option<bool>,
+    applied: HashSet<String>,
+}
+
+fn apply_levels(backend: &mut dyn AudioBackend, pid: u32, volume: Option<f32>, mute: Option<bool>) {
+    if let Some(vol) = volume {
+        let _ = backend.set_app_volume(pid, vol);
+    }
+    if let Some(mute) = mute {
+        let _ = backend.set_app_mute(pid, mute);
+    }
+}
+
+impl Worker {
+    fn memory(&self) -> MutexGuard<'_, VolumeMemory> {
+        lock(&self.memory)
+    }
+
+    /// Handle a coalesced request, answering the writes it replaced with its
+    /// own result.
+    fn handle_pending(&mut self, backend: &mut dyn AudioBackend, mut pending: Pending) {
+        if pending.superseded.is_empty() {
+            return self.handle(backend, pending.req);
         }
+        let (tx, mut rx) = oneshot::channel();
+        if let Some((_, reply)) = pending.req.as_write() {
+            pending.superseded.push(std::mem::replace(reply, tx));
+        }
+        self.handle(backend, pending.req);
+        if let Ok(res) = rx.try_recv() {
+            for reply in pending.superseded {
+                let _ = reply.send(res.clone());
+            }
+        }
+    }
+
+    fn handle(&mut self, backend: &mut dyn AudioBackend, req: AudioRequest) {
+        match req {
+            AudioRequest::GetMasterVolume(res_tx) => {
+                let _ = res_tx.send(backend.master_volume());
+            }
+            AudioRequest::GetMicVolume(res_tx) => {
+                let _ = res_tx.send(backend.mic_volume());
+            }
+            AudioRequest::GetAppVolumes(res_tx) => {
+                let res = self.app_rows(backend);
+                if let Ok(apps) = &res {
+                    self.last_apps = Some(apps.clone());
+                }
+                let _ = res_tx.send(res);
+            }
+            AudioRequest::GetCaptureApps(tx) => {
+                let _ = tx.send(internal_get_capture_apps(backend, &self.cache));
+            }
+            AudioRequest::SetMasterVolume(vol, tx) => {
+                let _ = tx.send(backend.set_master_volume(vol));
+            }
+            AudioRequest::SetMicVolume(vol, tx) => {
+                let _ = tx.send(backend.set_mic_volume(vol));
+            }
+            AudioRequest::SetMasterMute(mute, tx) => {
+                let _ = tx.send(backend.set_master_mute(mute));
+            }
+            AudioRequest::SetMicMute(mute, tx) => {
+                let _ = tx.send(backend.set_mic_mute(mute));
+            }
+            AudioRequest::SetAppVolume(pid, vol, tx) => {
+                let res = backend.set_app_volume(pid, vol);
+                if let (Ok(()), Some(group)) = (&res, self.group_of(pid)) {
+                    self.memory().remember_volume(&group, vol);
+                }
+                let _ = tx.send(res);
+            }
+            AudioRequest::SetAppMute(pid, mute, tx) => {
+                let res = backend.set_app_mute(pid, mute);
+                if let (Ok(()), Some(group)) = (&res, self.group_of(pid)) {
+                    self.memory().remember_mute(&group, mute);
+                }
+                let _ = tx.send(res);
+            }
+            AudioRequest::SetCaptureAppVolume(pid, vol, tx) => {
+                let _ = tx.send(backend.set_capture_app_volume(pid, vol));
+            }
+            AudioRequest::SetCaptureAppMute(pid, mute, tx) => {
+                let _ = tx.send(backend.set_capture_app_mute(pid, mute));
+            }
+            AudioRequest::SetGroupVolume(group, vol, tx) => {
+                let target = self.group_targets.entry(group.clone()).or_default();
+                target.volume = Some(vol);
+                target.applied.clear();
+                self.memory().remember_volume(&group, vol);
+                let _ = tx.send(self.apply_group_target(backend, &group));
+            }
+            AudioRequest::SetGroupMute(group, mute, tx) => {
+                let target = self.group_targets.entry(group.clone()).or_default();
+                target.mute = Some(mute);
+                target.applied.clear();
+                self.memory().remember_mute(&group, mute);
+                let _ = tx.send(self.apply_group_target(backend, &group));
+            }
+            AudioRequest::GetVolumeMemoryExclusions(tx) => {
+                let _ = tx.send(Ok(self.memory().excluded()));
+            }
+            AudioRequest::SetVolumeMemoryExcluded(group, excluded, tx) => {
+                self.memory().set_excluded(&group, excluded);
+                let _ = tx.send(Ok(()));
+            }
+            AudioRequest::GetPlaybackDevices(tx) => {
+                let _ = tx.send(backend.playback_devices());
+            }
+            AudioRequest::GetCaptureDevices(tx) => {
+                let _ = tx.send(backend.capture_devices());
+            }
+            AudioRequest::SetDefaultDevice(id, roles, tx) => {
+                let switch = switch_default_device(backend, &id, &roles);
+                (self.events)(AudioEvent::DeviceSwitch(switch.clone()));
+                let _ = tx.send(Ok(switch));
+            }
+            AudioRequest::SetAppOutputDevice(pid, device_id, tx) => {
+                let res = backend.set_app_output_device(pid, device_id.as_deref());
+                let _ = tx.send(res);
+                // Not every backend reports a moved stream; refresh the row.
+                self.notify(backend, BackendEvent::Sessions);
+            }
+            AudioRequest::CaptureScene(name, tx) => {
+                let _ = tx.send(self.capture_scene(backend, name));
+            }
+            AudioRequest::ApplyScene(scene, tx) => {
+                let _ = tx.send(self.apply_scene(backend, &scene));
+            }
+            AudioRequest::SetMeterRate(hz, tx) => {
+                self.meter_interval = (hz > 0).then(|| Duration::from_secs(1) / hz);
+                self.last_levels = None;
+                let _ = tx.send(Ok(()));
+            }
+            AudioRequest::GetMixerView(tx) => {
+                let _ = tx.send(Ok(lock(&self.view).get().clone()));
+            }
+            AudioRequest::SetMixerView(view, tx) => {
+                let res = lock(&self.view).set(view);
+                let _ = tx.send(res);
+                self.notify(backend, BackendEvent::Sessions);
+            }
+            AudioRequest::Notify(event) => self.notify(backend, event),
+        }
+    }
+
+    /// The mixer rows as the UI shows them: stamped with their activity,
+    /// filtered and sorted by the mixer view.
+    fn app_rows(&mut self, backend: &mut dyn AudioBackend) -> AudioResult<Vec<AppVolume>> {
+        let mut apps = internal_get_app_volumes(backend, &self.cache)?;
+        let now = unix_millis();
+        self.last_active
+            .retain(|group, _| apps.iter().any(|a| a.group == *group));
+        for app in &mut apps {
+            let stamp = self.last_active.entry(app.group.clone()).or_insert(now);
+            if app.state == SessionState::Active {
+                *stamp = now;
+            }
+            app.last_active = Some(*stamp);
+        }
+        lock(&self.view).get().arrange(&mut apps, now);
+        Ok(apps)
+    }
+
+    /// Whether rows can disappear with time alone, so the worker has to look
+    /// again now and then.
+    fn hides_inactive(&self) -> bool {
+        lock(&self.view).get().hide_inactive_after_mins.is_some()
+    }
+
+    fn sample_levels(&mut self, backend: &mut dyn AudioBackend) {
+        let Ok(peaks) = backend.peaks() else {
+            return;
+        };
+        let mut levels = Levels {
+            master: peaks.master,
+            mic: peaks.mic,
+            apps: HashMap::new(),
+        };
+        let map = lock(&self.cache.processes);
+        for (pid, peak) in peaks.sessions {
+            let Some(info) = map.get(&pid) else {
+                continue;
+            };
+            let entry = levels.apps.entry(info.group().to_string()).or_default();
+            *entry = entry.max(peak);
+        }
+        drop(map);
+        // Only the first of a run of identical samples (usually silence).
+        if self.last_levels.as_ref() == Some(&levels) {
+            return;
+        }
+        self.last_levels = Some(levels.clone());
+        (self.events)(AudioEvent::Levels(levels));
+    }
+
+    fn capture_scene(
+        &mut self,
+        backend: &mut dyn AudioBackend,
+        name: String,
+    ) -> AudioResult<Scene> {
+        let apps = internal_get_app_volumes(backend, &self.cache)?;
+        let default_id = |devices: AudioResult<Vec<AudioDevice>>| {
+            devices
+                .ok()?
+                .into_iter()
+                .find(|d| d.is_default)
+                .map(|d| d.id)
+        };
+        Ok(Scene {
+            name,
+            master: backend.master_volume()?.into(),
+            mic: backend.mic_volume().ok().map(Into::into),
+            apps: apps
+                .into_iter()
+                .map(|a| SceneApp {
+                    group: a.group,
+                    name: a.name,
+                    volume: a.volume,
+                    is_muted: a.is_muted,
+                })
+                .collect(),
+            playback_device: default_id(backend.playback_devices()),
+            capture_device: default_id(backend.capture_devices()),
+        })
+    }
+
+    /// Push a freshly set group target to the group's sessions. A group with
+    /// no session left has its target dropped by the reconcile pass.
+    fn apply_group_target(
+        &mut self,
+        backend: &mut dyn AudioBackend,
+        group: &str,
+    ) -> AudioResult<()> {
+        self.reconcile_sessions(backend)?;
+        if self.group_targets.contains_key(group) {
+            Ok(())
+        } else {
+            Err(ControlError::SessionGone(format!(
+                "{} has no audio session",
+                group
+            )))
+        }
+    }
+
+    /// Apply as much of `scene` as possible; failures are collected rather
+    /// than stopping halfway. Apps that are not running are skipped.
+    fn apply_scene(&mut self, backend: &mut dyn AudioBackend, scene: &Scene) -> AudioResult<()> {
+        let mut errors = Vec::new();
+        // Devices first, so the levels below land on the scene's endpoints.
+        for id in [&scene.playback_device, &scene.capture_device]
+            .into_iter()
+            .flatten()
+        {
+            // Scenes keep whatever the user picked for calls.
+            if let Err(e) = backend.set_default_device(id, &DeviceRole::EVERYDAY) {
+                errors.push(e);
+            }
+        }
+        let mut results = vec![
+            backend.set_master_volume(scene.master.volume),
+            backend.set_master_mute(scene.master.is_muted),
+        ];
+        if let Some(mic) = scene.mic {
+            results.push(backend.set_mic_volume(mic.volume));
+            results.push(backend.set_mic_mute(mic.is_muted));
+        }
+        errors.extend(results.into_iter().filter_map(Result::err));
+        for app in &scene.apps {
+            let target = self.group_targets.entry(app.group.clone()).or_default();
+            target.volume = Some(app.volume);
+            target.mute = Some(app.is_muted);
+            target.applied.clear();
+        }
+        if let Err(e) = self.reconcile_sessions(backend) {
+            errors.push(e);
+        }
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(ControlError::Failed(
+                errors
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            )),
+        }
+    }
+
+    fn notify(&mut self, backend: &mut dyn AudioBackend, event: BackendEvent) {
+        match event {
+            BackendEvent::Master => {
+                if let Ok(v) = backend.master_volume() {
+                    (self.events)(AudioEvent::Master(v));
+                }
+            }
+            BackendEvent::Mic => {
+                if let Ok(v) = backend.mic_volume() {
+                    (self.events)(AudioEvent::Mic(v));
+                }
+            }
+            BackendEvent::Sessions => {
+                let _ = self.reconcile_sessions(backend);
+                let Ok(apps) = self.app_rows(backend) else {
+                    return;
+                };
+                let old = self.last_apps.replace(apps.clone()).unwrap_or_default();
+                for ev in diff_sessions(&old, &apps) {
+                    (self.events)(ev);
+                }
+            }
+            BackendEvent::CaptureSessions => {
+                let Ok(apps) = internal_get_capture_apps(backend, &self.cache) else {
+                    return;
+                };
+                let old = self.last_capture.replace(apps.clone());
+                if old.as_ref() == Some(&apps) {
+                    return;
+                }
+                for ev in diff_recording(&old.unwrap_or_default(), &apps) {
+                    (self.events)(ev);
+                }
+                (self.events)(AudioEvent::CaptureApps(apps));
+            }
+            BackendEvent::Devices => {
+                // Endpoint callbacks are bound to the old default devices.
+                if let Err(e) = backend.subscribe(self.notifier.clone()) {
+                    println!("Audio change notifications unavailable: {}", e);
+                }
+                let devices = DeviceLists {
+                    playback: backend.playback_devices().unwrap_or_default(),
+                    capture: backend.capture_devices().unwrap_or_default(),
+                };
+                (self.events)(AudioEvent::Devices(devices));
+                self.notify(backend, BackendEvent::Master);
+                self.notify(backend, BackendEvent::Mic);
+                self.notify(backend, BackendEvent::Sessions);
+                self.notify(backend, BackendEvent::CaptureSessions);
+            }
+        }
+    }
+
+    fn group_of(&self, pid: u32) -> Option<String> {
+        let map = lock(&self.cache.processes);
+        map.get(&pid).map(|info| info.group().to_string())
+    }
+
+    /// Bring every session of a targeted group in line, once per session so
+    /// later per-process changes are left alone, and give sessions that just
+    /// appeared their executable's remembered level. Targets of groups that
+    /// no longer have any session are dropped.
+    fn reconcile_sessions(&mut self, backend: &mut dyn AudioBackend) -> AudioResult<()> {
+        let sessions = grouped_sessions(backend, &self.cache)?;
+        self.group_targets
+            .retain(|group, _| sessions.iter().any(|(g, _)| g == group));
+        let live = sessions.iter().map(|(_, s)| s.id.clone()).collect();
+        let known = self.known_sessions.replace(live);
+        for (group, session) in sessions {
+            if let Some(target) = self.group_targets.get_mut(&group) {
+                if target.applied.insert(session.id) {
+                    apply_levels(backend, session.pid, target.volume, target.mute);
+                }
+                continue;
+            }
+            let is_new = known.as_ref().is_some_and(|k| !k.contains(&session.id));
+            if let (true, Some(r)) = (is_new, self.memory().get(&group)) {
+                apply_levels(backend, session.pid, r.volume, r.is_muted);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ask the backend to switch, then read the device lists back to see which
+/// roles actually moved.
+fn switch_default_device(
+    backend: &mut dyn AudioBackend,
+    id: &str,
+    roles: &[DeviceRole],
+) -> DeviceSwitch {
+    let call = backend.set_default_device(id, roles);
+    let device = [backend.playback_devices(), backend.capture_devices()]
+        .into_iter()
+        .flat_map(Result::unwrap_or_default)
+        .find(|d| d.id == id);
+    let roles: Vec<RoleCheck> = roles
+        .iter()
+        .map(|&role| RoleCheck {
+            role,
+            verified: device
+                .as_ref()
+                .is_some_and(|d| d.default_roles.contains(&role)),
+        })
+        .collect();
+    let name = device.map_or_else(|| id.to_string(), |d| d.name);
+    let unverified: Vec<&str> = roles
+        .iter()
+        .filter(|r| !r.verified)
+        .map(|r| r.role.as_str())
+        .collect();
+    let error = match call {
+        Err(e) => Some(e),
+        Ok(()) if !unverified.is_empty() => Some(ControlError::Failed(format!(
+            "{} did not become the default for {}",
+            name,
+            unverified.join(", ")
+        ))),
+        Ok(()) => None,
+    };
+    DeviceSwitch {
+        id: id.to_string(),
+        name,
+        success: error.is_none(),
+        roles,
+        error,
+    }
+}
+
+/// Rows are keyed by group, the same key sessions are merged on.
+fn diff_sessions(old: &[AppVolume], new: &[AppVolume]) -> Vec<AudioEvent> {
+    let mut events = Vec::new();
+    for app in new {
+        match old.iter().find(|o| o.group == app.group) {
+            None => events.push(AudioEvent::SessionAdded(app.clone())),
+            // Peaks change constantly and travel through `audio://levels`;
+            // an active row's stamp moves with every read.
+            Some(o)
+                if (AppVolume {
+                    peak: app.peak,
+                    last_active: app.last_active,
+                    ..o.clone()
+                }) != *app =>
+            {
+                events.push(AudioEvent::SessionChanged(app.clone()))
+            }
+            Some(_) => {}
+        }
+    }
+    for app in old {
+        if !new.iter().any(|n| n.group == app.group) {
+            events.push(AudioEvent::SessionRemoved(app.clone()));
+        }
+    }
+    events
+}
+
+/// An app records while one of its capture sessions is active. Reports the
+/// rows that started, then those that stopped or went away while recording.
+fn diff_recording(old: &[AppVolume], new: &[AppVolume]) -> Vec<AudioEvent> {
+    let recording = |a: &&AppVolume| a.state == SessionState::Active;
+    let was: Vec<&AppVolume> = old.iter().filter(recording).collect();
+    let now: Vec<&AppVolume> = new.iter().filter(recording).collect();
+    let recording_now: Vec<String> = now.iter().map(|a| a.name.clone()).collect();
+    let started = now
+        .iter()
+        .filter(|a| !was.iter().any(|w| w.group == a.group));
+    let stopped = was
+        .iter()
+        .filter(|w| !now.iter().any(|a| a.group == w.group));
+    let change = |app: &AppVolume, recording: bool| {
+        AudioEvent::Recording(RecordingChange {
+            app: app.clone(),
+            recording,
+            recording_now: recording_now.clone(),
+        })
+    };
+    started
+        .map(|a| change(a, true))
+        .chain(stopped.map(|a| change(a, false)))
+        .collect()
+}
+
+/// Every live session paired with the group it belongs to.
+fn grouped_sessions(
+    backend: &mut dyn AudioBackend,
+    cache: &AppCache,
+) -> AudioResult<Vec<(String, AppSession)>> {
+    let sessions = backend.app_sessions()?;
+    update_cache_batch(&sessions, cache, backend);
+    let map = lock(&cache.processes);
+    Ok(sessions
+        .into_iter()
+        .map(|s| {
+            let group = map
+                .get(&s.pid)
+                .map(|info| info.group().to_string())
+                .unwrap_or_else(|| ProcessInfo::unknown(s.pid).name);
+            (group, s)
+        })
+        .collect())
+}
+
+fn internal_get_app_volumes(
+    backend: &mut dyn AudioBackend,
+    cache: &AppCache,
+) -> AudioResult<Vec<AppVolume>> {
+    let sessions = backend.app_sessions()?;
+    let mut apps = merge_sessions(sessions, backend, cache);
+    let pids: Vec<u32> = apps
+        .iter()
+        .filter(|a| !a.is_system)
+        .map(|a| a.pid)
+        .collect();
+    let mut routes = backend.app_output_devices(&pids);
+    let peaks = backend.peaks().map(|p| p.sessions).unwrap_or_default();
+    for app in &mut apps {
+        app.output_device_id = routes.remove(&app.pid);
+        app.peak = app
+            .pids
+            .iter()
+            .filter_map(|pid| peaks.get(pid))
+            .fold(0.0, |a: f32, &b| a.max(b));
+    }
+    apps.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.group.cmp(&b.group)));
+    Ok(apps)
+}
+
+/// Apps with a capture session, one row per group like the mixer. Rows are
+/// sorted by name and carry no route or peak.
+fn internal_get_capture_apps(
+    backend: &mut dyn AudioBackend,
+    cache: &AppCache,
+) -> AudioResult<Vec<AppVolume>> {
+    let sessions = backend.capture_sessions()?;
+    let mut apps = merge_sessions(sessions, backend, cache);
+    apps.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.group.cmp(&b.group)));
+    Ok(apps)
+}
+
+/// Describe the sessions' processes and merge the sessions into one row per
+/// group, unsorted.
+fn merge_sessions(
+    sessions: Vec<AppSession>,
+    backend: &mut dyn AudioBackend,
+    cache: &AppCache,
+) -> Vec<AppVolume> {
+    update_cache_batch(&sessions, cache, backend);
+    let active: HashSet<u32> = sessions
+        .iter()
+        .filter(|s| s.is_active)
+        .map(|s| s.pid)
+        .collect();
+    // The loudest session of each process stands for it.
+    let mut session_map: HashMap<u32, AppSession> = HashMap::new();
+    for s in sessions {
+        match session_map.get(&s.pid) {
+            Some(loudest) if loudest.volume >= s.volume => {}
+            _ => {
+                session_map.insert(s.pid, s);
+            }
+        }
+    }
+    let mut pids: Vec<u32> = session_map.keys().copied().collect();
+    pids.sort_unstable();
+
+    // Deduplicate by group (merge sessions)
+    // Key: exe path -> row; the loudest process is shown and kept as `pid`,
+    // every process is listed in `pids`. The first process of a row names
+    // it, preferring what its session calls itself.
+    let mut merged_map: HashMap<String, AppVolume> = HashMap::new();
+
+    let map = lock(&cache.processes);
+    for pid in pids {
+        let s = session_map.remove(&pid).unwrap_or_default();
+        let info = map
+            .get(&pid)
+            .cloned()
+            .unwrap_or_else(|| ProcessInfo::unknown(pid));
+        let group = info.group().to_string();
+        let state = if active.contains(&pid) {
+            SessionState::Active
+        } else {
+            SessionState::Inactive
+        };
+        if let Some(e) = merged_map.get_mut(&group) {
+            e.pids.push(pid);
+            if state == SessionState::Active {
+                e.state = state;
+            }
+            // Update existing entry if this pid seems "more active" (e.g. higher volume)
+            if s.volume > e.volume {
+                e.volume = s.volume;
+                e.pid = pid; // Switch control to the louder process
+                e.is_muted = s.is_muted;
+            }
+            continue;
+        }
+        let icon_path = s
+            .icon_path
+            .as_deref()
+            .and_then(|src| {
+                cache
+                    .icons
+                    .ensure(src, |size| backend.render_icon(src, size))
+            })
+            .unwrap_or(info.icon_path);
+        merged_map.insert(
+            group.clone(),
+            AppVolume {
+                pid,
+                group,
+                name: s.display_name.unwrap_or(info.name),
+                volume: s.volume,
+                is_muted: s.is_muted,
+                icon_path,
+                pids: vec![pid],
+                output_device_id: None,
+                peak: 0.0,
+                is_system: s.is_system,
+                state,
+                last_active: None,
+            },
+        );
+    }
+
+    let mut apps: Vec<AppVolume> = merged_map.into_values().collect();
+    for app in &mut apps {
+        app.pids.sort_unstable();
+    }
+    apps
+}
+
+/// Ancestors walked at most when looking for a helper's application.
+const MAX_ANCESTRY: usize = 16;
+
+/// The nearest ancestor of a helper process that is not a helper itself,
+/// or `None` when `info` is no helper or no such ancestor can be found.
+/// Pids 0 to 4 are the idle, system and init processes on either platform
+/// and never count as an application.
+fn owning_app(
+    pid: u32,
+    info: &ProcessInfo,
+    helpers: &HelperRules,
+    backend: &mut dyn AudioBackend,
+) -> Option<ProcessInfo> {
+    if !helpers.is_helper(&info.exe_path) {
+        return None;
+    }
+    let mut child = pid;
+    for _ in 0..MAX_ANCESTRY {
+        let parent = backend.parent_pid(child)?;
+        if parent <= 4 || parent == child {
+            return None;
+        }
+        // A parent that started after its child exited long ago and left
+        // its pid to an unrelated process.
+        let started = (
+            backend.process_start_time(child),
+            backend.process_start_time(parent),
+        );
+        if let (Some(child_started), Some(parent_started)) = started {
+            if parent_started > child_started {
+                return None;
+            }
+        }
+        let info = backend.describe_processes(&[parent]).remove(&parent)?;
+        if !helpers.is_helper(&info.exe_path) {
+            return Some(info);
+        }
+        child = parent;
+    }
+    None
+}
+
+/// The process whose row `pid` joins because one of its sessions shares a
+/// grouping parameter with one of that process's: the lowest such pid below
+/// `pid` itself.
+fn grouped_with(pid: u32, sessions: &[AppSession]) -> Option<u32> {
+    let params: HashSet<&str> = sessions
+        .iter()
+        .filter(|s| s.pid == pid)
+        .filter_map(|s| s.grouping.as_deref())
+        .collect();
+    sessions
+        .iter()
+        .filter(|s| s.pid < pid && !s.is_system)
+        .filter(|s| s.grouping.as_deref().is_some_and(|g| params.contains(g)))
+        .map(|s| s.pid)
+        .min()
+}
+
+/// Describe the pids with sessions that the cache does not know (or knows
+/// under a process that has since exited), then evict what is stale.
+/// `sessions` must be every live session.
+fn update_cache_batch(sessions: &[AppSession], cache: &AppCache, backend: &mut dyn AudioBackend) {
+    let now = Instant::now();
+    let mut pids: Vec<u32> = sessions.iter().map(|s| s.pid).collect();
+    pids.sort_unstable();
+    pids.dedup();
+    let system: HashSet<u32> = sessions
+        .iter()
+        .filter(|s| s.is_system)
+        .map(|s| s.pid)
+        .collect();
+    let (known, check) = {
+        let mut map = lock(&cache.processes);
+        let known: HashSet<u32> = pids
+            .iter()
+            .filter(|pid| map.get(pid).is_some())
+            .copied()
+            .collect();
+        (known, map.check_due(now))
+    };
+    // New pids need a start time for their entry; known ones are only
+    // compared on the timer.
+    let live: Vec<(u32, Option<u64>)> = pids
+        .iter()
+        .map(|&pid| {
+            let started = if check || !known.contains(&pid) {
+                backend.process_start_time(pid)
+            } else {
+                None
+            };
+            (pid, started)
+        })
+        .collect();
+    let missing_pids = lock(&cache.processes).refresh(&live, now);
+    let described: Vec<u32> = missing_pids
+        .iter()
+        .filter(|pid| !system.contains(pid))
+        .copied()
+        .collect();
+    let mut found = if described.is_empty() {
+        HashMap::new()
+    } else {
+        backend.describe_processes(&described)
+    };
+    {
+        let helpers = lock(&cache.helpers);
+        for (&pid, info) in found.iter_mut() {
+            if let Some(owner) = owning_app(pid, info, &helpers, backend) {
+                *info = owner;
+            }
+        }
+    }
+    let joined: Vec<(u32, u32)> = found
+        .keys()
+        .filter_map(|&pid| Some((pid, grouped_with(pid, sessions)?)))
+        .collect();
+    for (pid, leader) in joined {
+        let info = match found.get(&leader) {
+            Some(info) => Some(info.clone()),
+            None => lock(&cache.processes).get(&leader).cloned(),
+        };
+        if let Some(info) = info {
+            found.insert(pid, info);
+        }
+    }
+    for &pid in &system {
+        found.insert(pid, ProcessInfo::system_sounds());
+    }
+    for info in found.values_mut() {
+        if info.icon_path.is_empty() && !info.exe_path.is_empty() {
+            let exe = &info.exe_path;
+            if let Some(url) = cache
+                .icons
+                .ensure(exe, |size| backend.render_icon(exe, size))
+            {
+                info.icon_path = url;
+            }
+        }
+    }
+    let mut map = lock(&cache.processes);
+    for &(pid, started) in &live {
+        if missing_pids.contains(&pid) {
+            let info = found
+                .remove(&pid)
+                .unwrap_or_else(|| ProcessInfo::unknown(pid));
+            map.insert(pid, info, started, now);
+        }
+    }
+    map.evict(
+        &live,
+        now,
+        check.then_some(|pid| backend.process_start_time(pid)),
+    );
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::mock::{MockBackend, Trap};
+    use super::*;
+    use std::future::Future;
+    use std::sync::atomic::AtomicU32;
+    use std::sync::Arc;
+
+    /// A worker around a [`MockBackend`], with every event it published.
+    pub(crate) struct Harness {
+        pub state: AudioState,
+        pub mock: MockBackend,
+        pub events: Arc<Mutex<Vec<AudioEvent>>>,
+        /// Backends the factory has handed out, one per worker.
+        pub backends: Arc<AtomicU32>,
+    }
+
+    impl Harness {
+        pub fn new(mock: MockBackend) -> Self {
+            Self::with_memory(mock, VolumeMemory::in_memory())
+        }
+
+        pub fn with_memory(mock: MockBackend, memory: VolumeMemory) -> Self {
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let sink = events.clone();
+            let backend = mock.clone();
+            let backends = Arc::new(AtomicU32::new(0));
+            let made = backends.clone();
+            let state = AudioState::with_backend(
+                Arc::new(app_cache()),
+                Arc::new(move |event| lock(&sink).push(event)),
+                memory,
+                ViewStore::in_memory(),
+                Arc::new(move || {
+                    made.fetch_add(1, Ordering::Relaxed);
+                    Ok(Box::new(backend.clone()) as Box<dyn AudioBackend>)
+                }),
+            );
+            Self {
+                state,
+                mock,
+                events,
+                backends,
+            }
+        }
+
+        pub fn request<T>(
+            &self,
+            make: impl FnOnce(oneshot::Sender<AudioResult<T>>) -> AudioRequest,
+        ) -> AudioResult<T> {
+            block_on(self.state.request(make))
+        }
+
+        /// Whether the backend was called as `call`, e.g. `"set_master_volume 0.5"`.
+        pub fn called(&self, call: &str) -> bool {
+            self.mock.mixer().calls.iter().any(|c| c == call)
+        }
+
+        /// Backend calls that change something, leaving out the reads the
+        /// worker makes on its own.
+        pub fn writes(&self) -> Vec<String> {
+            let calls = self.mock.mixer().calls.clone();
+            calls
+                .into_iter()
+                .filter(|c| c.starts_with("set_"))
+                .collect()
+        }
+
+        pub fn apps(&self) -> Vec<AppVolume> {
+            self.request(AudioRequest::GetAppVolumes).unwrap()
+        }
+    }
+
+    fn app_cache() -> AppCache {
+        AppCache::new(
+            IconStore::new(std::env::temp_dir().join("wcc-test-icons")),
+            HelperRules::defaults(),
+        )
+    }
+
+    pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn player() -> Harness {
+        Harness::new(
+            MockBackend::new()
+                .with_session(10, "player", 0.5)
+                .with_playback("speakers", "Speakers", true)
+                .with_playback("headphones", "Headphones", false)
+                .with_capture("mic", "Microphone", true),
+        )
+    }
+
+    #[test]
+    fn master_volume_and_mute() {
+        let h = player();
+        h.request(|tx| AudioRequest::SetMasterVolume(0.25, tx))
+            .unwrap();
+        h.request(|tx| AudioRequest::SetMasterMute(true, tx))
+            .unwrap();
+        assert_eq!(
+            h.request(AudioRequest::GetMasterVolume).unwrap(),
+            (0.25, true)
+        );
+        assert!(h.called("set_master_volume 0.25"));
+        assert!(h.called("set_master_mute true"));
+    }
+
+    #[test]
+    fn mic_volume_and_mute() {
+        let h = player();
+        h.request(|tx| AudioRequest::SetMicVolume(0.75, tx))
+            .unwrap();
+        h.request(|tx| AudioRequest::SetMicMute(true, tx)).unwrap();
+        assert_eq!(h.request(AudioRequest::GetMicVolume).unwrap(), (0.75, true));
+        assert!(h.called("set_mic_volume 0.75"));
+        assert!(h.called("set_mic_mute true"));
+    }
+
+    #[test]
+    fn app_volume_and_mute() {
+        let h = player();
+        h.request(|tx| AudioRequest::SetAppVolume(10, 0.3, tx))
+            .unwrap();
+        h.request(|tx| AudioRequest::SetAppMute(10, true, tx))
+            .unwrap();
+        let apps = h.apps();
+        assert_eq!(apps.len(), 1);
+        assert_eq!(apps[0].name, "player");
+        assert_eq!((apps[0].volume, apps[0].is_muted), (0.3, true));
+        assert_eq!(
+            h.writes(),
+            ["set_app_volume 10 0.3", "set_app_mute 10 true"]
+        );
+    }
+
+    #[test]
+    fn app_volume_of_unknown_pid_is_session_gone() {
+        let h = player();
+        let res = h.request(|tx| AudioRequest::SetAppVolume(99, 0.3, tx));
+        assert!(matches!(res, Err(ControlError::SessionGone(_))));
+    }
+
+    #[test]
+    fn sessions_of_one_executable_share_a_row() {
+        let h = player();
+        h.mock.add_session(11, "player", 0.8);
+        let apps = h.apps();
+        assert_eq!(apps.len(), 1);
+        assert_eq!(apps[0].pids, [10, 11]);
+        // The louder process is the one per-process commands go to.
+        assert_eq!((apps[0].pid, apps[0].volume), (11, 0.8));
+    }
+
+    #[test]
+    fn group_volume_and_mute_reach_every_session() {
+        let h = player();
+        h.mock.add_session(11, "player", 0.8);
+        let group = h.apps()[0].group.clone();
+        h.request(|tx| AudioRequest::SetGroupVolume(group.clone(), 0.2, tx))
+            .unwrap();
+        h.request(|tx| AudioRequest::SetGroupMute(group, true, tx))
+            .unwrap();
+        h.apps();
+        let m = h.mock.mixer();
+        assert!(m.sessions.iter().all(|s| s.volume == 0.2 && s.is_muted));
+    }
+
+    #[test]
+    fn group_volume_reaches_sessions_that_join_later() {
+        let h = player();
+        let group = h.apps()[0].group.clone();
+        h.request(|tx| AudioRequest::SetGroupVolume(group, 0.2, tx))
+            .unwrap();
+        h.mock.add_session(11, "player", 0.8);
+        h.mock.notify(BackendEvent::Sessions);
+        h.apps();
+        assert!(h.called("set_app_volume 11 0.2"));
+    }
+
+    #[test]
+    fn group_without_sessions_is_session_gone() {
+        let h = player();
+        let res = h.request(|tx| AudioRequest::SetGroupVolume("/mock/gone".into(), 0.2, tx));
+        assert!(matches!(res, Err(ControlError::SessionGone(_))));
+        assert!(h.writes().is_empty());
+    }
+
+    #[test]
+    fn default_device_switch_is_verified() {
+        let h = player();
+        let switch = h
+            .request(|tx| {
+                AudioRequest::SetDefaultDevice(
+                    "headphones".into(),
+                    DeviceRole::EVERYDAY.to_vec(),
+                    tx,
+                )
+            })
+            .unwrap();
+        assert!(switch.success);
+        assert_eq!(switch.name, "Headphones");
+        assert!(switch.roles.iter().all(|r| r.verified));
+        let devices = h.request(AudioRequest::GetPlaybackDevices).unwrap();
+        let default: Vec<&str> = devices
+            .iter()
+            .filter(|d| d.is_default)
+            .map(|d| d.id.as_str())
+            .collect();
+        assert_eq!(default, ["headphones"]);
+        assert!(lock(&h.events)
+            .iter()
+            .any(|e| matches!(e, AudioEvent::DeviceSwitch(s) if s.id == "headphones")));
+    }
+
+    #[test]
+    fn default_device_switch_to_unknown_device_fails() {
+        let h = player();
+        let switch = h
+            .request(|tx| {
+                AudioRequest::SetDefaultDevice("nope".into(), DeviceRole::ALL.to_vec(), tx)
+            })
+            .unwrap();
+        assert!(!switch.success);
+        assert_eq!(switch.name, "nope");
+        assert!(matches!(
+            switch.error,
+            Some(ControlError::DeviceNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn default_device_is_set_per_role() {
+        let h = player();
+        h.request(|tx| {
+            AudioRequest::SetDefaultDevice("headphones".into(), DeviceRole::EVERYDAY.to_vec(), tx)
+        })
+        .unwrap();
+        let devices = h.request(AudioRequest::GetPlaybackDevices).unwrap();
+        let roles = |id: &str| {
+            let device = devices.iter().find(|d| d.id == id).unwrap();
+            (device.is_default, device.default_roles.clone())
+        };
+        assert_eq!(roles("headphones"), (true, DeviceRole::EVERYDAY.to_vec()));
+        // Calls stay where they were.
+        assert_eq!(roles("speakers"), (false, vec![DeviceRole::Communications]));
+    }
+
+    #[test]
+    fn app_output_device_is_set_and_reset() {
+        let h = player();
+        h.request(|tx| AudioRequest::SetAppOutputDevice(10, Some("headphones".into()), tx))
+            .unwrap();
+        assert_eq!(h.apps()[0].output_device_id.as_deref(), Some("headphones"));
+        h.request(|tx| AudioRequest::SetAppOutputDevice(10, None, tx))
+            .unwrap();
+        assert_eq!(h.apps()[0].output_device_id, None);
+        let res = h.request(|tx| AudioRequest::SetAppOutputDevice(10, Some("nope".into()), tx));
+        assert!(matches!(res, Err(ControlError::DeviceNotFound(_))));
+        assert_eq!(h.apps()[0].output_device_id, None);
+    }
+
+    /// Every `audio://levels` payload published so far, waiting up to a
+    /// second for the first one.
+    fn levels(h: &Harness) -> Vec<Levels> {
+        for _ in 0..100 {
+            let levels: Vec<Levels> = lock(&h.events)
+                .iter()
+                .filter_map(|e| match e {
+                    AudioEvent::Levels(l) => Some(l.clone()),
+                    _ => None,
+                })
+                .collect();
+            if !levels.is_empty() {
+                return levels;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        Vec::new()
+    }
+
+    #[test]
+    fn levels_are_published_while_metering() {
+        let h = player();
+        h.mock.add_session(11, "player", 0.8);
+        {
+            let mut m = h.mock.mixer();
+            m.peaks = (0.6, 0.1);
+            m.sessions[0].peak = 0.4;
+            m.sessions[1].peak = 0.2;
+        }
+        h.apps();
+        h.request(|tx| AudioRequest::SetMeterRate(MAX_METER_RATE_HZ, tx))
+            .unwrap();
+        let first = levels(&h);
+        assert_eq!(first.len(), 1);
+        assert_eq!((first[0].master, first[0].mic), (0.6, Some(0.1)));
+        // Sessions are reported per row, at the loudest member's level.
+        assert_eq!(first[0].apps.get("/mock/player"), Some(&0.4));
+
+        // Samples that did not change are not published again.
+        std::thread::sleep(Duration::from_millis(100));
+        h.request(|tx| AudioRequest::SetMeterRate(0, tx)).unwrap();
+        h.apps();
+        assert_eq!(levels(&h).len(), 1);
+    }
+
+    #[test]
+    fn scene_round_trip() {
+        let h = player();
+        h.request(|tx| AudioRequest::SetMasterVolume(0.4, tx))
+            .unwrap();
+        let scene = h
+            .request(|tx| AudioRequest::CaptureScene("evening".into(), tx))
+            .unwrap();
+        assert_eq!(scene.name, "evening");
+        assert_eq!(scene.master.volume, 0.4);
+        assert_eq!(scene.playback_device.as_deref(), Some("speakers"));
+        assert_eq!(scene.capture_device.as_deref(), Some("mic"));
+        assert_eq!(scene.apps.len(), 1);
+        assert_eq!(scene.apps[0].volume, 0.5);
+
+        h.request(|tx| AudioRequest::SetMasterVolume(1.0, tx))
+            .unwrap();
+        h.request(|tx| AudioRequest::SetAppVolume(10, 0.9, tx))
+            .unwrap();
+        h.request(|tx| AudioRequest::ApplyScene(scene.clone(), tx))
+            .unwrap();
+        assert_eq!(h.request(AudioRequest::GetMasterVolume).unwrap().0, 0.4);
+        assert_eq!(h.apps()[0].volume, 0.5);
+    }
+
+    #[test]
+    fn scene_skips_apps_that_are_not_running() {
+        let h = player();
+        let mut scene = h
+            .request(|tx| AudioRequest::CaptureScene("s".into(), tx))
+            .unwrap();
+        scene.apps[0].group = "/mock/gone".into();
+        h.request(|tx| AudioRequest::ApplyScene(scene, tx)).unwrap();
+        assert!(!h.writes().iter().any(|c| c.starts_with("set_app_")));
+    }
+
+    #[test]
+    fn backend_errors_reach_the_caller() {
+        let h = player();
+        h.mock.mixer().fail_with = Some(ControlError::PermissionDenied("denied".into()));
+        let denied = ControlError::PermissionDenied("denied".into());
+        assert_eq!(
+            h.request(AudioRequest::GetMasterVolume),
+            Err(denied.clone())
+        );
+        assert_eq!(
+            h.request(|tx| AudioRequest::SetAppVolume(10, 0.3, tx)),
+            Err(denied)
+        );
+        assert!(h.request(AudioRequest::GetAppVolumes).is_err());
+        // The call was attempted, it just failed.
+        assert!(h.called("set_app_volume 10 0.3"));
+
+        h.mock.mixer().fail_with = None;
+        assert_eq!(h.apps()[0].volume, 0.5);
+    }
+
+    #[test]
+    fn outside_changes_are_published() {
+        let h = player();
+        h.apps();
+        h.mock.add_session(20, "recorder", 1.0);
+        h.mock.notify(BackendEvent::Sessions);
+        // Queued behind the notification, so it has been handled once
+        // this returns.
+        h.request(AudioRequest::GetMasterVolume).unwrap();
+        assert!(lock(&h.events)
+            .iter()
+            .any(|e| matches!(e, AudioEvent::SessionAdded(a) if a.name == "recorder")));
+    }
+
+    #[test]
+    fn device_changes_publish_the_lists_and_levels() {
+        let h = player();
+        // The worker subscribes once it has a backend.
+        h.request(AudioRequest::GetMasterVolume).unwrap();
+        h.mock.mixer().playback.retain(|d| d.id != "headphones");
+        h.mock.notify(BackendEvent::Devices);
+        h.request(AudioRequest::GetMasterVolume).unwrap();
+        let events = lock(&h.events);
+        let lists = events
+            .iter()
+            .find_map(|e| match e {
+                AudioEvent::Devices(lists) => Some(lists),
+                _ => None,
+            })
+            .expect("a device list");
+        let ids: Vec<&str> = lists.playback.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, ["speakers"]);
+        assert_eq!(lists.capture.len(), 1);
+        // Volumes belong to the default devices, which may have changed.
+        assert!(events.contains(&AudioEvent::Master((1.0, false))));
+        assert!(events.contains(&AudioEvent::Mic((1.0, false))));
+    }
+
+    #[test]
+    fn capture_apps_and_recording_events() {
+        let h = player();
+        h.mock.add_recording(30, "voice", 0.6);
+        h.mock.notify(BackendEvent::CaptureSessions);
+        let apps = h.request(AudioRequest::GetCaptureApps).unwrap();
+        assert_eq!(apps.len(), 1);
+        assert_eq!(apps[0].name, "voice");
+        assert_eq!(apps[0].state, SessionState::Active);
+        h.request(|tx| AudioRequest::SetCaptureAppVolume(30, 0.2, tx))
+            .unwrap();
+        h.request(|tx| AudioRequest::SetCaptureAppMute(30, true, tx))
+            .unwrap();
+        let voice = h.mock.mixer().recording[0].clone();
+        assert_eq!((voice.volume, voice.is_muted), (0.2, true));
+
+        h.mock.mixer().recording.clear();
+        h.mock.notify(BackendEvent::CaptureSessions);
+        h.request(AudioRequest::GetMasterVolume).unwrap();
+        let recording: Vec<bool> = lock(&h.events)
+            .iter()
+            .filter_map(|e| match e {
+                AudioEvent::Recording(change) => Some(change.recording),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(recording, [true, false]);
+    }
+
+    #[test]
+    fn superseded_writes_share_the_last_result() {
+        let batch = vec![
+            AudioRequest::SetMasterVolume(0.1, oneshot::channel().0),
+            AudioRequest::SetMasterMute(true, oneshot::channel().0),
+            AudioRequest::SetMasterVolume(0.2, oneshot::channel().0),
+        ];
+        let (kept, dropped) = coalesce(batch);
+        assert_eq!(dropped, 1);
+        assert_eq!(kept.len(), 2);
+        assert!(matches!(kept[0].req, AudioRequest::SetMasterMute(true, _)));
+        assert!(matches!(kept[1].req, AudioRequest::SetMasterVolume(v, _) if v == 0.2));
+        assert_eq!(kept[1].superseded.len(), 1);
+    }
+
+    #[test]
+    fn repeated_notifications_are_dropped_but_reads_are_kept() {
+        let batch = vec![
+            AudioRequest::Notify(BackendEvent::Sessions),
+            AudioRequest::GetMasterVolume(oneshot::channel().0),
+            AudioRequest::Notify(BackendEvent::Sessions),
+            AudioRequest::GetMasterVolume(oneshot::channel().0),
+            AudioRequest::Notify(BackendEvent::Master),
+        ];
+        let (kept, dropped) = coalesce(batch);
+        assert_eq!(dropped, 1);
+        let kinds: Vec<&str> = kept
+            .iter()
+            .map(|p| match p.req {
+                AudioRequest::Notify(BackendEvent::Sessions) => "sessions",
+                AudioRequest::Notify(BackendEvent::Master) => "master",
+                AudioRequest::GetMasterVolume(_) => "read",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(kinds, ["read", "sessions", "read", "master"]);
+    }
+
+    #[test]
+    fn queue_stats_count_received_requests() {
+        let h = player();
+        h.apps();
+        h.request(|tx| AudioRequest::SetMasterVolume(0.5, tx))
+            .unwrap();
+        let stats = h.state.queue_stats();
+        assert!(stats.received >= 2, "{:?}", stats);
+        assert_eq!(stats.depth, 0);
+    }
+
+    /// Health once the supervisor has replaced the worker `restarts` times
+    /// and the new one is connected.
+    fn restarted(h: &Harness, restarts: u32) -> BackendHealth {
+        for _ in 0..100 {
+            let health = h.state.health();
+            if health.restarts == restarts && health.connected {
+                return health;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        panic!("worker not replaced: {:?}", h.state.health());
+    }
+
+    #[test]
+    fn hung_worker_is_replaced_with_a_fresh_backend() {
+        let h = player();
+        h.apps();
+        h.mock.mixer().trap = Some(("set_master_volume".into(), Trap::Hang(STUCK_AFTER * 2)));
+        let res = h.request(|tx| AudioRequest::SetMasterVolume(0.3, tx));
+        assert!(matches!(res, Err(ControlError::Timeout(_))), "{:?}", res);
+
+        let health = restarted(&h, 1);
+        let reason = health.last_error.unwrap();
+        assert!(reason.starts_with("Audio worker stuck"), "{}", reason);
+        assert_eq!(h.backends.load(Ordering::Relaxed), 2);
+        h.request(|tx| AudioRequest::SetMasterVolume(0.4, tx))
+            .unwrap();
+        assert_eq!(
+            h.request(AudioRequest::GetMasterVolume).unwrap(),
+            (0.4, false)
+        );
+    }
+
+    #[test]
+    fn panicked_worker_is_replaced_with_a_fresh_backend() {
+        let h = player();
+        h.apps();
+        h.mock.mixer().trap = Some(("set_master_volume".into(), Trap::Panic));
+        let res = h.request(|tx| AudioRequest::SetMasterVolume(0.3, tx));
+        assert!(
+            matches!(res, Err(ControlError::BackendUnavailable(_))),
+            "{:?}",
+            res
+        );
+
+        let health = restarted(&h, 1);
+        assert_eq!(
+            health.last_error.as_deref(),
+            Some("Audio worker panicked: mock backend trap")
+        );
+        assert_eq!(h.backends.load(Ordering::Relaxed), 2);
+        h.request(|tx| AudioRequest::SetMasterVolume(0.4, tx))
+            .unwrap();
+        assert_eq!(h.apps().len(), 1);
+        assert_eq!(h.mock.mixer().master, (0.4, false));
+    }
+
+    fn described(cache: &AppCache, pid: u32) -> Option<String> {
+        lock(&cache.processes)
+            .get(&pid)
+            .map(|info| info.name.clone())
+    }
+
+    fn start_time_queries(mock: &MockBackend) -> usize {
+        let m = mock.mixer();
+        m.calls
+            .iter()
+            .filter(|c| c.starts_with("process_start_time"))
+            .count()
+    }
+
+    /// Run the session listing's cache update against the mock's sessions.
+    fn update_cache(cache: &AppCache, mock: &mut MockBackend) {
+        let sessions = mock.app_sessions().unwrap();
+        update_cache_batch(&sessions, cache, mock);
+    }
+
+    /// As if [`CACHE_CHECK_INTERVAL`] had passed.
+    fn check_now(cache: &AppCache) {
+        lock(&cache.processes).next_check = Some(Instant::now());
+    }
+
+    #[test]
+    fn recycled_pids_are_described_again() {
+        let cache = app_cache();
+        let mut mock = MockBackend::new().with_session(10, "player", 0.5);
+        update_cache(&cache, &mut mock);
+        assert_eq!(described(&cache, 10).as_deref(), Some("player"));
+        assert_eq!(start_time_queries(&mock), 1);
+
+        // The player exits and its pid goes to another program.
+        mock.mixer().sessions.clear();
+        mock.add_session(10, "recorder", 0.5);
+        update_cache(&cache, &mut mock);
+        // Known pids are only compared on the timer.
+        assert_eq!(described(&cache, 10).as_deref(), Some("player"));
+        assert_eq!(start_time_queries(&mock), 1);
+
+        check_now(&cache);
+        update_cache(&cache, &mut mock);
+        assert_eq!(described(&cache, 10).as_deref(), Some("recorder"));
+        assert_eq!(cache.stats().evicted_recycled, 1);
+    }
+
+    #[test]
+    fn exited_processes_are_evicted() {
+        let cache = app_cache();
+        let mut mock = MockBackend::new()
+            .with_session(10, "player", 0.5)
+            .with_session(11, "recorder", 0.5);
+        update_cache(&cache, &mut mock);
+        mock.mixer().sessions.retain(|s| s.pid != 11);
+        update_cache(&cache, &mut mock);
+        assert_eq!(described(&cache, 11).as_deref(), Some("recorder"));
+
+        check_now(&cache);
+        update_cache(&cache, &mut mock);
+        assert_eq!(described(&cache, 11), None);
+        assert_eq!(described(&cache, 10).as_deref(), Some("player"));
+        assert_eq!(cache.stats().evicted_exited, 1);
+    }
+
+    fn info(name: &str) -> ProcessInfo {
+        ProcessInfo {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Eviction without looking for exited processes.
+    const NO_EXITS: Option<fn(u32) -> Option<u64>> = None;
+
+    #[test]
+    fn sessions_gone_past_the_grace_period_are_dropped() {
+        let mut cache = ProcessCache::default();
+        let t0 = Instant::now();
+        cache.insert(10, info("player"), Some(1), t0);
+        cache.insert(11, info("recorder"), Some(2), t0);
+        let live = [(11, None)];
+
+        cache.evict(&live, t0 + CACHE_SESSION_GRACE / 2, NO_EXITS);
+        assert!(cache.get(&10).is_some());
+        cache.evict(&live, t0 + CACHE_SESSION_GRACE, NO_EXITS);
+        assert!(cache.get(&10).is_none());
+        // A live session keeps its entry however long ago it was described.
+        assert!(cache.get(&11).is_some());
+        assert_eq!(cache.stats().evicted_expired, 1);
+    }
+
+    #[test]
+    fn size_cap_pushes_out_the_idle_entries_seen_longest_ago() {
+        let mut cache = ProcessCache::default();
+        let t0 = Instant::now();
+        let extra = 10;
+        for pid in 0..(CACHE_MAX_ENTRIES + extra) as u32 {
+            let seen = t0 + Duration::from_millis(pid as u64);
+            cache.insert(pid, info("app"), None, seen);
+        }
+        // The oldest entry of all still has a session.
+        cache.evict(&[(0, None)], t0 + Duration::from_secs(1), NO_EXITS);
+        assert_eq!(cache.stats().entries, CACHE_MAX_ENTRIES);
+        assert_eq!(cache.stats().evicted_capacity, extra as u64);
+        assert!(cache.get(&0).is_some());
+        assert!((1..=extra as u32).all(|pid| cache.get(&pid).is_none()));
+        assert!(cache.get(&(extra as u32 + 1)).is_some());
+
+        // Live entries stay even when they alone are over the byte cap.
+        let mut cache = ProcessCache::default();
+        cache.insert(1, info(&"x".repeat(CACHE_MAX_BYTES)), None, t0);
+        cache.insert(2, info("app"), None, t0 + Duration::from_millis(1));
+        cache.evict(&[(1, None)], t0 + Duration::from_secs(1), NO_EXITS);
+        assert!(cache.get(&1).is_some());
+        assert!(cache.get(&2).is_none());
+    }
+
+    #[test]
+    fn cache_stats_count_lookups_and_evictions() {
+        let mut cache = ProcessCache::default();
+        let now = Instant::now();
+        let missing = cache.refresh(&[(10, Some(1)), (11, Some(2))], now);
+        assert_eq!(missing, [10, 11]);
+        cache.insert(10, info("player"), Some(1), now);
+        cache.insert(11, info("recorder"), Some(2), now);
+        // 10 unchecked, 11 reused.
+        let missing = cache.refresh(&[(10, None), (11, Some(3))], now);
+        assert_eq!(missing, [11]);
+        cache.insert(11, info("voice"), Some(3), now);
+        // 10 exited.
+        cache.evict(&[(11, None)], now, Some(|_| None));
+
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                entries: 1,
+                bytes: "voice".len(),
+                max_bytes: CACHE_MAX_BYTES,
+                hits: 1,
+                misses: 3,
+                evicted_exited: 1,
+                evicted_recycled: 1,
+                evicted_expired: 0,
+                evicted_capacity: 0,
+            }
+        );
+    }
+
+    fn describe(mock: &mut MockBackend, pid: u32) -> ProcessInfo {
+        mock.describe_processes(&[pid]).remove(&pid).unwrap()
+    }
+
+    #[test]
+    fn helpers_belong_to_their_nearest_ancestor_that_is_no_helper() {
+        let mut mock = MockBackend::new()
+            .with_process(100, "editor")
+            .with_process(101, "msedgewebview2.exe")
+            .with_session(102, "msedgewebview2.exe", 0.5)
+            .with_parent(102, 101)
+            .with_parent(101, 100)
+            .with_parent(100, 1);
+        let helpers = HelperRules::defaults();
+        let webview = describe(&mut mock, 102);
+        let owner = owning_app(102, &webview, &helpers, &mut mock);
+        assert_eq!(owner.map(|info| info.name).as_deref(), Some("editor"));
+        // The editor is no helper and keeps its own row.
+        let editor = describe(&mut mock, 100);
+        assert_eq!(owning_app(100, &editor, &helpers, &mut mock), None);
+    }
+
+    #[test]
+    fn helpers_without_an_application_above_them_keep_their_row() {
+        let helpers = HelperRules::defaults();
+        let cases = [
+            // Started by init.
+            MockBackend::new()
+                .with_session(102, "msedgewebview2.exe", 0.5)
+                .with_parent(102, 1),
+            // Nothing but helpers up to an unknown parent.
+            MockBackend::new()
+                .with_process(101, "msedgewebview2.exe")
+                .with_session(102, "msedgewebview2.exe", 0.5)
+                .with_parent(102, 101),
+            // The parent pid now belongs to a later process.
+            MockBackend::new()
+                .with_session(102, "msedgewebview2.exe", 0.5)
+                .with_process(101, "editor")
+                .with_parent(102, 101),
+            // Its own parent.
+            MockBackend::new()
+                .with_session(102, "msedgewebview2.exe", 0.5)
+                .with_parent(102, 102),
+        ];
+        for (i, mut mock) in cases.into_iter().enumerate() {
+            let webview = describe(&mut mock, 102);
+            assert_eq!(
+                owning_app(102, &webview, &helpers, &mut mock),
+                None,
+                "case {}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn changed_helper_rules_regroup_sessions() {
+        let cache = app_cache();
+        let mut mock = MockBackend::new()
+            .with_process(100, "editor")
+            .with_session(102, "preview", 0.5)
+            .with_parent(102, 100);
+        update_cache(&cache, &mut mock);
+        assert_eq!(described(&cache, 102).as_deref(), Some("preview"));
+
+        cache.set_helper_rules(cache.helper_rules()).unwrap();
+        assert_eq!(cache.stats().entries, 1);
+
+        let mut rules = cache.helper_rules();
+        rules.push("preview".into());
+        cache.set_helper_rules(rules).unwrap();
+        assert_eq!(cache.stats().entries, 0);
+        update_cache(&cache, &mut mock);
+        assert_eq!(described(&cache, 102).as_deref(), Some("editor"));
+    }
+
+    #[test]
+    fn remembered_levels_reach_new_sessions_of_the_executable() {
+        let path = std::env::temp_dir().join(format!("wcc-memory-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{
+                "version": 1,
+                "apps": {
+                    "/mock/player": {"volume": 0.3, "is_muted": true},
+                    "/mock/chat": {"volume": 0.1, "is_muted": true}
+                },
+                "excluded": ["/mock/chat"]
+            }"#,
+        )
+        .unwrap();
+        let h = Harness::with_memory(
+            MockBackend::new().with_session(10, "player", 0.5),
+            VolumeMemory::load(path.clone()).unwrap(),
+        );
+        h.apps();
+        // Sessions already there when the worker starts keep their level.
+        h.mock.notify(BackendEvent::Sessions);
+        h.apps();
+        assert_eq!(h.writes(), Vec::<String>::new());
+
+        h.mock.add_session(11, "player", 0.8);
+        h.mock.add_session(12, "chat", 0.8);
+        h.mock.notify(BackendEvent::Sessions);
+        h.apps();
+        assert_eq!(
+            h.writes(),
+            ["set_app_volume 11 0.3", "set_app_mute 11 true"]
+        );
+
+        // Changes are written through to the file.
+        h.request(|tx| AudioRequest::SetAppVolume(11, 0.7, tx))
+            .unwrap();
+        let memory = VolumeMemory::load(path.clone()).unwrap();
+        let player = memory.get("/mock/player").unwrap();
+        assert_eq!((player.volume, player.is_muted), (Some(0.7), Some(true)));
+        assert_eq!(memory.get("/mock/chat"), None);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn row(name: &str, volume: f32, state: SessionState) -> AppVolume {
+        AppVolume {
+            pid: 10,
+            name: name.to_string(),
+            volume,
+            is_muted: false,
+            icon_path: String::new(),
+            group: format!("/mock/{}", name),
+            pids: vec![10],
+            output_device_id: None,
+            peak: 0.0,
+            is_system: false,
+            state,
+            last_active: None,
+        }
+    }
+
+    #[test]
+    fn session_diff_reports_added_removed_and_changed_rows() {
+        use SessionState::{Active, Inactive};
+        let old = [
+            row("player", 0.5, Active),
+            row("chat", 0.5, Active),
+            row("game", 0.5, Inactive),
+            row("radio", 0.5, Active),
+        ];
+        let louder = row("player", 0.8, Active);
+        let paused = row("radio", 0.5, Inactive);
+        // Peaks and activity stamps alone are no change.
+        let game = AppVolume {
+            peak: 0.7,
+            last_active: Some(5),
+            ..row("game", 0.5, Inactive)
+        };
+        let notes = row("notes", 0.5, Active);
+        let new = [louder.clone(), game, paused.clone(), notes.clone()];
+        assert_eq!(
+            diff_sessions(&old, &new),
+            [
+                AudioEvent::SessionChanged(louder),
+                AudioEvent::SessionChanged(paused),
+                AudioEvent::SessionAdded(notes),
+                AudioEvent::SessionRemoved(old[1].clone()),
+            ]
+        );
+        assert_eq!(diff_sessions(&new, &new), []);
+    }
+
+    #[test]
+    fn recording_diff_reports_apps_that_start_and_stop() {
+        use SessionState::{Active, Inactive};
+        let old = [
+            row("voice", 0.5, Active),
+            row("notes", 0.5, Active),
+            row("dictation", 0.5, Inactive),
+            row("call", 0.5, Active),
+        ];
+        let new = [
+            // Louder but still recording: no event.
+            row("voice", 0.9, Active),
+            row("notes", 0.5, Inactive),
+            row("dictation", 0.5, Active),
+            row("radio", 0.5, Active),
+            // Joins without recording.
+            row("player", 0.5, Inactive),
+        ];
+        let recording_now = vec!["voice".to_string(), "dictation".into(), "radio".into()];
+        let change = |app: &AppVolume, recording| {
+            AudioEvent::Recording(RecordingChange {
+                app: app.clone(),
+                recording,
+                recording_now: recording_now.clone(),
+            })
+        };
+        assert_eq!(
+            diff_recording(&old, &new),
+            [
+                change(&new[2], true),
+                change(&new[3], true),
+                change(&old[1], false),
+                change(&old[3], false),
+            ]
+        );
+        assert_eq!(diff_recording(&new, &new), []);
     }
-    String::new()
 }
@@ -0,0 +1,197 @@
+//! Executables that play audio on behalf of another application, such as
+//! embedded browsers. Their sessions are attributed to the nearest ancestor
+//! that is not a helper, so the mixer shows the app the user started
+//! instead of `msedgewebview2.exe`.
+
+use super::AudioResult;
+use crate::error::ControlError;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+pub const HELPER_FILE_VERSION: u32 = 1;
+
+/// Used until the user saves a list of their own.
+pub const DEFAULT_HELPERS: &[&str] = &[
+    "msedgewebview2.exe",
+    "CefSharp.BrowserSubprocess.exe",
+    "QtWebEngineProcess.exe",
+    "QtWebEngineProcess",
+    "steamwebhelper.exe",
+    "steamwebhelper",
+    "WebKitWebProcess",
+    "* Helper (Renderer)",
+    "* Helper (Plugin)",
+];
+
+#[derive(Serialize, Deserialize)]
+struct HelperFile {
+    version: u32,
+    /// Executable file names; `*` matches any run of characters.
+    helpers: Vec<String>,
+}
+
+/// Case-insensitive match of an executable's file name against a pattern
+/// with `*` wildcards.
+fn matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let name = name.to_lowercase();
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard at all.
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+pub struct HelperRules {
+    path: Option<PathBuf>,
+    patterns: Vec<String>,
+}
+
+impl HelperRules {
+    /// Load from `path`, falling back to [`DEFAULT_HELPERS`] when the file
+    /// does not exist yet.
+    pub fn load(path: PathBuf) -> AudioResult<Self> {
+        let patterns = match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                let file: HelperFile = serde_json::from_str(&text)
+                    .map_err(|e| format!("invalid helper rules {}: {}", path.display(), e))?;
+                if file.version > HELPER_FILE_VERSION {
+                    return Err(ControlError::Failed(format!(
+                        "helper rules version {} is newer than supported version {}",
+                        file.version, HELPER_FILE_VERSION
+                    )));
+                }
+                file.helpers
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::defaults().patterns,
+            Err(e) => {
+                return Err(ControlError::Failed(format!(
+                    "failed to read {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        Ok(Self {
+            path: Some(path),
+            patterns,
+        })
+    }
+
+    /// The built-in list, never written to disk.
+    pub fn defaults() -> Self {
+        Self {
+            path: None,
+            patterns: DEFAULT_HELPERS.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    pub fn patterns(&self) -> Vec<String> {
+        self.patterns.clone()
+    }
+
+    /// Write the list to disk, then use it. A list that could not be saved
+    /// leaves the old one in place.
+    pub fn set(&mut self, patterns: Vec<String>) -> AudioResult<()> {
+        if let Some(path) = &self.path {
+            let file = HelperFile {
+                version: HELPER_FILE_VERSION,
+                helpers: patterns.clone(),
+            };
+            let text = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            // Through a temporary file, so a crash never leaves half a list.
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, text)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        self.patterns = patterns;
+        Ok(())
+    }
+
+    pub fn is_helper(&self, exe_path: &str) -> bool {
+        let name = exe_path.rsplit(['/', '\\']).next().unwrap_or(exe_path);
+        !name.is_empty() && self.patterns.iter().any(|p| matches(p, name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_any_run_of_characters() {
+        let cases = [
+            ("steamwebhelper", "steamwebhelper", true),
+            ("steamwebhelper", "steamwebhelper2", false),
+            ("steamwebhelper", "xsteamwebhelper", false),
+            ("* Helper (Renderer)", "Code Helper (Renderer)", true),
+            ("* Helper (Renderer)", " Helper (Renderer)", true),
+            ("* Helper (Renderer)", "Code Helper (GPU)", false),
+            ("web*.exe", "webview.exe", true),
+            ("web*.exe", "web.exe", true),
+            ("web*.exe", "webview.dll", false),
+            ("a*b*c", "axxbyyc", true),
+            ("a*b*c", "axxcyyb", false),
+            // Parts may not overlap.
+            ("ab*ba", "aba", false),
+            ("*", "anything", true),
+            ("**", "", true),
+        ];
+        for (pattern, name, expected) in cases {
+            assert_eq!(matches(pattern, name), expected, "{:?} {:?}", pattern, name);
+        }
+    }
+
+    #[test]
+    fn case_is_ignored() {
+        assert!(matches("MSEdgeWebView2.exe", "msedgewebview2.EXE"));
+        assert!(matches("* helper (plugin)", "Slack Helper (Plugin)"));
+    }
+
+    #[test]
+    fn only_the_file_name_is_matched() {
+        let rules = HelperRules::defaults();
+        assert!(rules.is_helper(r"C:\Program Files (x86)\Microsoft\EdgeWebView\msedgewebview2.exe"));
+        assert!(rules.is_helper("/usr/lib/qt6/libexec/QtWebEngineProcess"));
+        assert!(rules.is_helper("/Applications/Slack.app/Slack Helper (Renderer)"));
+        // A helper's name in a directory does not make a helper.
+        assert!(!rules.is_helper("/opt/steamwebhelper/game"));
+        assert!(!rules.is_helper(r"C:\steamwebhelper\"));
+        assert!(!rules.is_helper(""));
+    }
+
+    #[test]
+    fn rules_are_kept_only_once_saved() {
+        let dir = std::env::temp_dir().join(format!("wcc-helpers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("helpers.json");
+        let mut rules = HelperRules::load(path.clone()).unwrap();
+        rules.set(vec!["game*".into()]).unwrap();
+        assert_eq!(
+            HelperRules::load(path.clone()).unwrap().patterns(),
+            ["game*"]
+        );
+
+        // A directory where the file should go makes the write fail.
+        std::fs::remove_file(&path).unwrap();
+        std::fs::create_dir(&path).unwrap();
+        assert!(rules.set(Vec::new()).is_err());
+        assert_eq!(rules.patterns(), ["game*"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,208 @@
+//! Per-executable volume and mute that outlive the process, so an app that
+//! restarts comes back at the level the user last gave it.
+
+use super::AudioResult;
+use crate::error::ControlError;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+pub const MEMORY_FILE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Remembered {
+    pub volume: Option<f32>,
+    pub is_muted: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct MemoryFile {
+    version: u32,
+    /// Keyed by [`super::AppVolume::group`].
+    apps: BTreeMap<String, Remembered>,
+    /// Groups the user does not want remembered.
+    excluded: BTreeSet<String>,
+}
+
+/// Forget volumes a hand-edited file put outside 0..=1.
+fn sanitize(mut file: MemoryFile) -> MemoryFile {
+    for remembered in file.apps.values_mut() {
+        remembered.volume = remembered
+            .volume
+            .and_then(|v| crate::validate::volume(v).ok());
+    }
+    file
+}
+
+/// Owned by the audio worker; every change is written through to disk.
+pub struct VolumeMemory {
+    path: Option<PathBuf>,
+    file: MemoryFile,
+}
+
+impl VolumeMemory {
+    /// Load from `path`, starting empty when the file does not exist yet.
+    pub fn load(path: PathBuf) -> AudioResult<Self> {
+        let file = match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                let file: MemoryFile = serde_json::from_str(&text)
+                    .map_err(|e| format!("invalid volume memory {}: {}", path.display(), e))?;
+                if file.version > MEMORY_FILE_VERSION {
+                    return Err(ControlError::Failed(format!(
+                        "volume memory version {} is newer than supported version {}",
+                        file.version, MEMORY_FILE_VERSION
+                    )));
+                }
+                sanitize(file)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => MemoryFile::default(),
+            Err(e) => {
+                return Err(ControlError::Failed(format!(
+                    "failed to read {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        Ok(Self {
+            path: Some(path),
+            file,
+        })
+    }
+
+    /// A memory that is never written to disk.
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            file: MemoryFile::default(),
+        }
+    }
+
+    pub fn get(&self, group: &str) -> Option<Remembered> {
+        if self.is_excluded(group) {
+            return None;
+        }
+        self.file.apps.get(group).copied()
+    }
+
+    pub fn is_excluded(&self, group: &str) -> bool {
+        self.file.excluded.contains(group)
+    }
+
+    pub fn excluded(&self) -> Vec<String> {
+        self.file.excluded.iter().cloned().collect()
+    }
+
+    pub fn remember_volume(&mut self, group: &str, volume: f32) {
+        self.update(group, |r| r.volume = Some(volume));
+    }
+
+    pub fn remember_mute(&mut self, group: &str, is_muted: bool) {
+        self.update(group, |r| r.is_muted = Some(is_muted));
+    }
+
+    /// Opt a group out of (or back into) being remembered. Opting out also
+    /// forgets what was stored for it.
+    pub fn set_excluded(&mut self, group: &str, excluded: bool) {
+        let changed = if excluded {
+            let removed = self.file.apps.remove(group).is_some();
+            self.file.excluded.insert(group.to_string()) || removed
+        } else {
+            self.file.excluded.remove(group)
+        };
+        if changed {
+            self.persist();
+        }
+    }
+
+    fn update(&mut self, group: &str, f: impl FnOnce(&mut Remembered)) {
+        if self.is_excluded(group) {
+            return;
+        }
+        let entry = self.file.apps.entry(group.to_string()).or_default();
+        let before = *entry;
+        f(entry);
+        if *entry != before {
+            self.persist();
+        }
+    }
+
+    /// Failures are logged only: losing the memory must not break the mixer.
+    fn persist(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        self.file.version = MEMORY_FILE_VERSION;
+        let res = serde_json::to_string_pretty(&self.file)
+            .map_err(|e| e.to_string())
+            .and_then(|text| {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                let tmp = path.with_extension("json.tmp");
+                std::fs::write(&tmp, text).map_err(|e| e.to_string())?;
+                std::fs::rename(&tmp, path).map_err(|e| e.to_string())
+            });
+        if let Err(e) = res {
+            println!("Failed to save volume memory: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, text: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("wcc-memory-{}-{}.json", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn levels_outside_the_unit_range_are_forgotten_on_load() {
+        let path = file(
+            "sanitize",
+            r#"{
+                "version": 1,
+                "apps": {
+                    "/usr/bin/loud": {"volume": 1.5, "is_muted": true},
+                    "/usr/bin/negative": {"volume": -0.25, "is_muted": null},
+                    "/usr/bin/fine": {"volume": 0.25, "is_muted": false}
+                },
+                "excluded": []
+            }"#,
+        );
+        let memory = VolumeMemory::load(path.clone()).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let remembered = |volume, is_muted| Some(Remembered { volume, is_muted });
+        assert_eq!(memory.get("/usr/bin/loud"), remembered(None, Some(true)));
+        assert_eq!(memory.get("/usr/bin/negative"), remembered(None, None));
+        assert_eq!(
+            memory.get("/usr/bin/fine"),
+            remembered(Some(0.25), Some(false))
+        );
+    }
+
+    #[test]
+    fn excluded_groups_are_not_remembered() {
+        let path = file("excluded", r#"{"version": 1, "apps": {}, "excluded": []}"#);
+        let mut memory = VolumeMemory::load(path.clone()).unwrap();
+        memory.remember_volume("/usr/bin/chat", 0.4);
+        memory.set_excluded("/usr/bin/chat", true);
+        memory.remember_mute("/usr/bin/chat", true);
+        let memory = VolumeMemory::load(path.clone()).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(memory.get("/usr/bin/chat"), None);
+        assert_eq!(memory.excluded(), ["/usr/bin/chat"]);
+    }
+
+    #[test]
+    fn newer_files_are_rejected() {
+        let path = file("newer", r#"{"version": 2, "apps": {}, "excluded": []}"#);
+        let res = VolumeMemory::load(path.clone());
+        let _ = std::fs::remove_file(&path);
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,408 @@
+//! In-memory [`AudioBackend`] used to exercise the worker and the Tauri audio
+//! commands without any sound hardware or OS audio stack.
+
+use super::{
+    AppSession, AudioBackend, AudioDevice, AudioResult, BackendEvent, DeviceRole, Notifier, Peaks,
+    ProcessInfo,
+};
+use crate::error::ControlError;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub struct MockSession {
+    pub id: String,
+    pub pid: u32,
+    pub name: String,
+    pub icon_path: String,
+    /// Executable path; sessions sharing it are merged into one row.
+    pub exe_path: String,
+    pub volume: f32,
+    pub is_muted: bool,
+    /// Playback device the session is routed to, `None` for the default.
+    pub output_device: Option<String>,
+    pub peak: f32,
+    /// Playing rather than paused; sessions start out playing.
+    pub is_active: bool,
+    /// Start time reported for the session's process; re-adding a pid
+    /// looks like a new process.
+    pub started: u64,
+}
+
+/// How a trapped call misbehaves.
+#[derive(Clone, Copy, Debug)]
+pub enum Trap {
+    /// Answer only after this long, like a wedged driver.
+    Hang(Duration),
+    Panic,
+}
+
+/// Scriptable mixer state. Tests set it up before sending requests and
+/// inspect it afterwards; every backend call is also appended to `calls`.
+#[derive(Debug)]
+pub struct MockMixer {
+    pub master: (f32, bool),
+    pub mic: (f32, bool),
+    pub sessions: Vec<MockSession>,
+    /// Sessions on capture devices, i.e. apps using the microphone.
+    pub recording: Vec<MockSession>,
+    /// Processes without a session, such as the app a helper plays for.
+    /// Only their pid, name, path and start time are used.
+    pub processes: Vec<MockSession>,
+    /// Parent pid of each process that has one.
+    pub parents: HashMap<u32, u32>,
+    pub playback: Vec<AudioDevice>,
+    pub capture: Vec<AudioDevice>,
+    /// Peak levels of the default playback and capture device.
+    pub peaks: (f32, f32),
+    /// When set, every call fails with this error until cleared.
+    pub fail_with: Option<ControlError>,
+    /// The first call starting with this prefix hangs or panics; the
+    /// trap is gone afterwards.
+    pub trap: Option<(String, Trap)>,
+    pub calls: Vec<String>,
+    /// Source of unique session ids.
+    next_session: u32,
+}
+
+impl Default for MockMixer {
+    fn default() -> Self {
+        Self {
+            master: (1.0, false),
+            mic: (1.0, false),
+            sessions: Vec::new(),
+            recording: Vec::new(),
+            processes: Vec::new(),
+            parents: HashMap::new(),
+            playback: Vec::new(),
+            capture: Vec::new(),
+            peaks: (0.0, 0.0),
+            fail_with: None,
+            trap: None,
+            calls: Vec::new(),
+            next_session: 0,
+        }
+    }
+}
+
+/// Cheap to clone; all clones share one [`MockMixer`].
+#[derive(Clone, Default)]
+pub struct MockBackend {
+    mixer: Arc<Mutex<MockMixer>>,
+    notifier: Arc<Mutex<Option<Notifier>>>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mixer(&self) -> MutexGuard<'_, MockMixer> {
+        self.mixer.lock().unwrap()
+    }
+
+    /// Simulate a change made outside the app (media keys, another mixer).
+    /// Does nothing until the worker has subscribed.
+    pub fn notify(&self, event: BackendEvent) {
+        if let Some(n) = self.notifier.lock().unwrap().as_ref() {
+            n.notify(event);
+        }
+    }
+
+    pub fn with_session(self, pid: u32, name: &str, volume: f32) -> Self {
+        self.add_session(pid, name, volume);
+        self
+    }
+
+    /// Add a session, e.g. a process starting playback after the worker is
+    /// running. Follow with `notify(BackendEvent::Sessions)` to announce it.
+    pub fn add_session(&self, pid: u32, name: &str, volume: f32) {
+        let mut m = self.mixer();
+        let session = new_session(&mut m, pid, name, volume);
+        m.sessions.push(session);
+    }
+
+    /// Add a capture session, a process starting to record. Follow with
+    /// `notify(BackendEvent::CaptureSessions)` to announce it.
+    pub fn add_recording(&self, pid: u32, name: &str, volume: f32) {
+        let mut m = self.mixer();
+        let session = new_session(&mut m, pid, name, volume);
+        m.recording.push(session);
+    }
+
+    /// A running process without a session; later additions start later.
+    pub fn with_process(self, pid: u32, name: &str) -> Self {
+        let mut m = self.mixer();
+        let process = new_session(&mut m, pid, name, 0.0);
+        m.processes.push(process);
+        drop(m);
+        self
+    }
+
+    pub fn with_parent(self, pid: u32, parent: u32) -> Self {
+        self.mixer().parents.insert(pid, parent);
+        self
+    }
+
+    pub fn with_playback(self, id: &str, name: &str, is_default: bool) -> Self {
+        self.mixer().playback.push(device(id, name, is_default));
+        self
+    }
+
+    pub fn with_capture(self, id: &str, name: &str, is_default: bool) -> Self {
+        self.mixer().capture.push(device(id, name, is_default));
+        self
+    }
+
+    fn enter(&mut self, call: String) -> AudioResult<MutexGuard<'_, MockMixer>> {
+        let mut m = self.mixer();
+        let trap = m
+            .trap
+            .take_if(|(prefix, _)| call.starts_with(prefix.as_str()));
+        m.calls.push(call);
+        if let Some((_, trap)) = trap {
+            // Let go of the mixer first: other backends share it.
+            drop(m);
+            match trap {
+                Trap::Hang(time) => thread::sleep(time),
+                Trap::Panic => panic!("mock backend trap"),
+            }
+            m = self.mixer();
+        }
+        match m.fail_with.clone() {
+            Some(e) => Err(e),
+            None => Ok(m),
+        }
+    }
+}
+
+fn new_session(m: &mut MockMixer, pid: u32, name: &str, volume: f32) -> MockSession {
+    m.next_session += 1;
+    MockSession {
+        id: format!("mock-session-{}", m.next_session),
+        pid,
+        name: name.to_string(),
+        icon_path: String::new(),
+        exe_path: format!("/mock/{}", name),
+        volume,
+        is_muted: false,
+        output_device: None,
+        peak: 0.0,
+        is_active: true,
+        started: m.next_session as u64,
+    }
+}
+
+fn to_app_session(s: &MockSession) -> AppSession {
+    AppSession {
+        id: s.id.clone(),
+        pid: s.pid,
+        volume: s.volume,
+        is_muted: s.is_muted,
+        is_active: s.is_active,
+        ..Default::default()
+    }
+}
+
+fn sessions_of(sessions: &mut [MockSession], pid: u32) -> AudioResult<Vec<&mut MockSession>> {
+    let sessions: Vec<_> = sessions.iter_mut().filter(|s| s.pid == pid).collect();
+    if sessions.is_empty() {
+        return Err(ControlError::SessionGone(format!(
+            "no session for pid {}",
+            pid
+        )));
+    }
+    Ok(sessions)
+}
+
+fn device(id: &str, name: &str, is_default: bool) -> AudioDevice {
+    AudioDevice {
+        id: id.to_string(),
+        name: name.to_string(),
+        is_default,
+        default_roles: if is_default {
+            DeviceRole::ALL.to_vec()
+        } else {
+            Vec::new()
+        },
+    }
+}
+
+impl AudioBackend for MockBackend {
+    fn master_volume(&mut self) -> AudioResult<(f32, bool)> {
+        Ok(self.enter("master_volume".into())?.master)
+    }
+
+    fn mic_volume(&mut self) -> AudioResult<(f32, bool)> {
+        Ok(self.enter("mic_volume".into())?.mic)
+    }
+
+    fn set_master_volume(&mut self, vol: f32) -> AudioResult<()> {
+        let mut m = self.enter(format!("set_master_volume {}", vol))?;
+        m.master = (vol, false);
+        Ok(())
+    }
+
+    fn set_mic_volume(&mut self, vol: f32) -> AudioResult<()> {
+        let mut m = self.enter(format!("set_mic_volume {}", vol))?;
+        m.mic = (vol, false);
+        Ok(())
+    }
+
+    fn set_master_mute(&mut self, mute: bool) -> AudioResult<()> {
+        self.enter(format!("set_master_mute {}", mute))?.master.1 = mute;
+        Ok(())
+    }
+
+    fn set_mic_mute(&mut self, mute: bool) -> AudioResult<()> {
+        self.enter(format!("set_mic_mute {}", mute))?.mic.1 = mute;
+        Ok(())
+    }
+
+    fn app_sessions(&mut self) -> AudioResult<Vec<AppSession>> {
+        let m = self.enter("app_sessions".into())?;
+        Ok(m.sessions.iter().map(to_app_session).collect())
+    }
+
+    fn set_app_volume(&mut self, pid: u32, vol: f32) -> AudioResult<()> {
+        let mut m = self.enter(format!("set_app_volume {} {}", pid, vol))?;
+        let sessions = sessions_of(&mut m.sessions, pid)?;
+        for s in sessions {
+            s.volume = vol;
+        }
+        Ok(())
+    }
+
+    fn set_app_mute(&mut self, pid: u32, mute: bool) -> AudioResult<()> {
+        let mut m = self.enter(format!("set_app_mute {} {}", pid, mute))?;
+        let sessions = sessions_of(&mut m.sessions, pid)?;
+        for s in sessions {
+            s.is_muted = mute;
+        }
+        Ok(())
+    }
+
+    fn capture_sessions(&mut self) -> AudioResult<Vec<AppSession>> {
+        let m = self.enter("capture_sessions".into())?;
+        Ok(m.recording.iter().map(to_app_session).collect())
+    }
+
+    fn set_capture_app_volume(&mut self, pid: u32, vol: f32) -> AudioResult<()> {
+        let mut m = self.enter(format!("set_capture_app_volume {} {}", pid, vol))?;
+        for s in sessions_of(&mut m.recording, pid)? {
+            s.volume = vol;
+        }
+        Ok(())
+    }
+
+    fn set_capture_app_mute(&mut self, pid: u32, mute: bool) -> AudioResult<()> {
+        let mut m = self.enter(format!("set_capture_app_mute {} {}", pid, mute))?;
+        for s in sessions_of(&mut m.recording, pid)? {
+            s.is_muted = mute;
+        }
+        Ok(())
+    }
+
+    fn playback_devices(&mut self) -> AudioResult<Vec<AudioDevice>> {
+        Ok(self.enter("playback_devices".into())?.playback.clone())
+    }
+
+    fn capture_devices(&mut self) -> AudioResult<Vec<AudioDevice>> {
+        Ok(self.enter("capture_devices".into())?.capture.clone())
+    }
+
+    fn set_default_device(&mut self, id: &str, roles: &[DeviceRole]) -> AudioResult<()> {
+        let mut m = self.enter(format!("set_default_device {} {:?}", id, roles))?;
+        let m = &mut *m;
+        for list in [&mut m.playback, &mut m.capture] {
+            if list.iter().any(|d| d.id == id) {
+                for d in list.iter_mut() {
+                    d.default_roles.retain(|r| !roles.contains(r));
+                    if d.id == id {
+                        d.default_roles.extend_from_slice(roles);
+                    }
+                    d.is_default = d.default_roles.contains(&DeviceRole::Multimedia);
+                }
+                return Ok(());
+            }
+        }
+        Err(ControlError::DeviceNotFound(id.to_string()))
+    }
+
+    fn set_app_output_device(&mut self, pid: u32, device_id: Option<&str>) -> AudioResult<()> {
+        let mut m = self.enter(format!("set_app_output_device {} {:?}", pid, device_id))?;
+        if let Some(id) = device_id {
+            if !m.playback.iter().any(|d| d.id == id) {
+                return Err(ControlError::DeviceNotFound(id.to_string()));
+            }
+        }
+        for s in m.sessions.iter_mut().filter(|s| s.pid == pid) {
+            s.output_device = device_id.map(str::to_string);
+        }
+        Ok(())
+    }
+
+    fn app_output_devices(&mut self, pids: &[u32]) -> HashMap<u32, String> {
+        let m = self.mixer();
+        m.sessions
+            .iter()
+            .chain(&m.recording)
+            .filter(|s| pids.contains(&s.pid))
+            .filter_map(|s| Some((s.pid, s.output_device.clone()?)))
+            .collect()
+    }
+
+    fn process_start_time(&mut self, pid: u32) -> Option<u64> {
+        let mut m = self.mixer();
+        m.calls.push(format!("process_start_time {}", pid));
+        m.sessions
+            .iter()
+            .chain(&m.recording)
+            .chain(&m.processes)
+            .find(|s| s.pid == pid)
+            .map(|s| s.started)
+    }
+
+    fn parent_pid(&mut self, pid: u32) -> Option<u32> {
+        self.mixer().parents.get(&pid).copied()
+    }
+
+    fn describe_processes(&mut self, pids: &[u32]) -> HashMap<u32, ProcessInfo> {
+        let m = self.mixer();
+        m.sessions
+            .iter()
+            .chain(&m.recording)
+            .chain(&m.processes)
+            .filter(|s| pids.contains(&s.pid))
+            .map(|s| {
+                let info = ProcessInfo {
+                    name: s.name.clone(),
+                    icon_path: s.icon_path.clone(),
+                    exe_path: s.exe_path.clone(),
+                };
+                (s.pid, info)
+            })
+            .collect()
+    }
+
+    fn peaks(&mut self) -> AudioResult<Peaks> {
+        let m = self.enter("peaks".into())?;
+        let mut sessions = HashMap::new();
+        for s in &m.sessions {
+            let entry = sessions.entry(s.pid).or_insert(0.0f32);
+            *entry = entry.max(s.peak);
+        }
+        Ok(Peaks {
+            master: m.peaks.0,
+            mic: Some(m.peaks.1),
+            sessions,
+        })
+    }
+
+    fn subscribe(&mut self, notifier: Notifier) -> AudioResult<()> {
+        *self.notifier.lock().unwrap() = Some(notifier);
+        Ok(())
+    }
+}
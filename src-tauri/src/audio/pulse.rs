@@ -0,0 +1,1034 @@
+//! PulseAudio implementation of [`AudioBackend`]. Also covers PipeWire through
+//! `pipewire-pulse`. Sinks and non-monitor sources become [`AudioDevice`]s and
+//! sink-inputs become [`AppSession`]s, keyed by `application.process.id`.
+
+use super::{
+    AppSession, AudioBackend, AudioDevice, AudioResult, BackendEvent, DeviceRole, Notifier,
+    ProcessInfo,
+};
+use crate::desktop::{Resolver, Roots};
+use crate::error::ControlError;
+use crate::icons::IconImage;
+use libpulse_binding as pulse;
+use pulse::callbacks::ListResult;
+use pulse::channelmap::Map as ChannelMap;
+use pulse::context::ext_stream_restore::Info as RestoreRule;
+use pulse::context::subscribe::{Facility, InterestMaskSet, Operation as SubscribeOperation};
+use pulse::context::{Context, FlagSet as ContextFlagSet, State as ContextState};
+use pulse::mainloop::standard::{IterateResult, Mainloop};
+use pulse::operation::{Operation, State as OperationState};
+use pulse::proplist::{Proplist, UpdateMode};
+use pulse::time::MicroSeconds;
+use pulse::volume::{ChannelVolumes, Volume};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const DEFAULT_SINK: &str = "@DEFAULT_SINK@";
+const DEFAULT_SOURCE: &str = "@DEFAULT_SOURCE@";
+
+/// Longest the watcher thread waits for events before checking whether it
+/// should stop.
+const WATCH_POLL: MicroSeconds = MicroSeconds(200_000);
+/// Pause before the watcher reconnects to a daemon that went away.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// The fields we need from a sink, source or sink-input.
+struct Node {
+    index: u32,
+    name: String,
+    description: String,
+    volume: ChannelVolumes,
+    mute: bool,
+    /// A paused sink-input; always false for sinks and sources.
+    corked: bool,
+    pid: Option<u32>,
+    app_name: Option<String>,
+    /// Sink a sink-input plays on.
+    sink: Option<u32>,
+    channel_map: ChannelMap,
+    /// module-stream-restore rule the device of a sink-input is saved under.
+    restore_rule: Option<String>,
+}
+
+pub struct PulseBackend {
+    server: Option<String>,
+    mainloop: Mainloop,
+    context: Context,
+    /// Application names seen on sink-inputs and source-outputs, used to describe processes
+    /// without a desktop entry.
+    app_names: HashMap<u32, String>,
+    apps: Resolver,
+    /// Desktop entry `Icon` of each described executable, for `render_icon`.
+    icon_names: HashMap<String, String>,
+    /// The change-event connection, once subscribed. Default changes are
+    /// tracked by name, so it never needs to be rebuilt.
+    watcher: Option<Watcher>,
+}
+
+/// Thread reporting change events on a connection of its own. Stopped and
+/// joined when dropped, so a replaced backend leaves nothing running.
+struct Watcher {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl PulseBackend {
+    pub fn new() -> AudioResult<Self> {
+        Self::connect(None)
+    }
+
+    /// Connect to a specific server, in `PULSE_SERVER` syntax (for example
+    /// `unix:/tmp/pulse-test/native`). This is how a throwaway daemon with
+    /// only null sinks is targeted when no sound hardware is available.
+    pub fn connect(server: Option<&str>) -> AudioResult<Self> {
+        let unavailable = |m: String| ControlError::BackendUnavailable(format!("pulse: {}", m));
+        let mut mainloop =
+            Mainloop::new().ok_or_else(|| unavailable("failed to create mainloop".into()))?;
+        let mut context = Context::new(&mainloop, "win-control-center")
+            .ok_or_else(|| unavailable("failed to create context".into()))?;
+        context
+            .connect(server, ContextFlagSet::NOFLAGS, None)
+            .map_err(|e| unavailable(format!("{}", e)))?;
+        loop {
+            if let IterateResult::Quit(_) | IterateResult::Err(_) = mainloop.iterate(true) {
+                return Err(unavailable("mainloop stopped while connecting".into()));
+            }
+            match context.get_state() {
+                ContextState::Ready => break,
+                ContextState::Failed | ContextState::Terminated => {
+                    return Err(unavailable("connection failed".into()));
+                }
+                _ => {}
+            }
+        }
+        Ok(Self {
+            server: server.map(str::to_string),
+            mainloop,
+            context,
+            app_names: HashMap::new(),
+            apps: Resolver::new(Roots::system()),
+            icon_names: HashMap::new(),
+            watcher: None,
+        })
+    }
+
+    /// Drive the mainloop until `op` completes.
+    fn wait<T: ?Sized>(&mut self, op: Operation<T>) -> AudioResult<()> {
+        loop {
+            match op.get_state() {
+                OperationState::Done => return Ok(()),
+                OperationState::Cancelled => return Err("pulse: operation cancelled".into()),
+                OperationState::Running => {}
+            }
+            if let IterateResult::Quit(_) | IterateResult::Err(_) = self.mainloop.iterate(true) {
+                return Err(ControlError::BackendUnavailable(
+                    "pulse: mainloop stopped".into(),
+                ));
+            }
+        }
+    }
+
+    /// Run a success-reporting operation to completion and check its result.
+    fn run(
+        &mut self,
+        what: &str,
+        start: impl FnOnce(&mut Self, Rc<Cell<bool>>) -> Operation<dyn FnMut(bool)>,
+    ) -> AudioResult<()> {
+        let ok = Rc::new(Cell::new(false));
+        let op = start(self, ok.clone());
+        self.wait(op)?;
+        if ok.get() {
+            return Ok(());
+        }
+        let message = format!("pulse: {} failed", what);
+        Err(match pulse::error::Code::try_from(self.context.errno()) {
+            Ok(pulse::error::Code::Access) => ControlError::PermissionDenied(message),
+            Ok(pulse::error::Code::NoEntity) => ControlError::DeviceNotFound(message),
+            Ok(pulse::error::Code::Timeout) => ControlError::Timeout(message),
+            _ => ControlError::Failed(message),
+        })
+    }
+
+    fn sinks(&mut self) -> AudioResult<Vec<Node>> {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let sink = out.clone();
+        let op = self.context.introspect().get_sink_info_list(move |r| {
+            if let ListResult::Item(i) = r {
+                sink.borrow_mut().push(Node {
+                    index: i.index,
+                    name: i.name.as_deref().unwrap_or_default().to_string(),
+                    description: i.description.as_deref().unwrap_or_default().to_string(),
+                    volume: i.volume,
+                    mute: i.mute,
+                    corked: false,
+                    pid: None,
+                    app_name: None,
+                    sink: None,
+                    channel_map: i.channel_map,
+                    restore_rule: None,
+                });
+            }
+        });
+        self.wait(op)?;
+        Ok(out.take())
+    }
+
+    fn sources(&mut self) -> AudioResult<Vec<Node>> {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let sink = out.clone();
+        let op = self.context.introspect().get_source_info_list(move |r| {
+            if let ListResult::Item(i) = r {
+                // Monitors mirror a sink's output and are not recording devices.
+                if i.monitor_of_sink.is_some() {
+                    return;
+                }
+                sink.borrow_mut().push(Node {
+                    index: i.index,
+                    name: i.name.as_deref().unwrap_or_default().to_string(),
+                    description: i.description.as_deref().unwrap_or_default().to_string(),
+                    volume: i.volume,
+                    mute: i.mute,
+                    corked: false,
+                    pid: None,
+                    app_name: None,
+                    sink: None,
+                    channel_map: i.channel_map,
+                    restore_rule: None,
+                });
+            }
+        });
+        self.wait(op)?;
+        Ok(out.take())
+    }
+
+    fn sink_inputs(&mut self) -> AudioResult<Vec<Node>> {
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let sink = out.clone();
+        let op = self
+            .context
+            .introspect()
+            .get_sink_input_info_list(move |r| {
+                if let ListResult::Item(i) = r {
+                    sink.borrow_mut().push(Node {
+                        index: i.index,
+                        name: i.name.as_deref().unwrap_or_default().to_string(),
+                        description: String::new(),
+                        volume: i.volume,
+                        mute: i.mute,
+                        corked: i.corked,
+                        pid: process_id(&i.proplist),
+                        app_name: i
+                            .proplist
+                            .get_str("application.name")
+                            .or_else(|| i.proplist.get_str("application.process.binary")),
+                        sink: Some(i.sink),
+                        channel_map: i.channel_map,
+                        restore_rule: restore_rule(&i.proplist),
+                    });
+                }
+            });
+        self.wait(op)?;
+        Ok(out.take())
+    }
+
+    /// Source outputs recording from a real source. Monitors are left out:
+    /// streams on them, such as level meters, record no microphone.
+    fn source_outputs(&mut self) -> AudioResult<Vec<Node>> {
+        let sources: Vec<u32> = self.sources()?.iter().map(|n| n.index).collect();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let sink = out.clone();
+        let op = self
+            .context
+            .introspect()
+            .get_source_output_info_list(move |r| {
+                if let ListResult::Item(i) = r {
+                    if !sources.contains(&i.source) {
+                        return;
+                    }
+                    sink.borrow_mut().push(Node {
+                        index: i.index,
+                        name: i.name.as_deref().unwrap_or_default().to_string(),
+                        description: String::new(),
+                        volume: i.volume,
+                        mute: i.mute,
+                        corked: i.corked,
+                        pid: process_id(&i.proplist),
+                        app_name: i
+                            .proplist
+                            .get_str("application.name")
+                            .or_else(|| i.proplist.get_str("application.process.binary")),
+                        sink: None,
+                        channel_map: i.channel_map,
+                        restore_rule: None,
+                    });
+                }
+            });
+        self.wait(op)?;
+        Ok(out.take())
+    }
+
+    /// Source outputs of `pid`; none at all means it stopped recording.
+    fn outputs_of(&mut self, pid: u32) -> AudioResult<Vec<Node>> {
+        let outputs: Vec<Node> = self
+            .source_outputs()?
+            .into_iter()
+            .filter(|n| n.pid == Some(pid))
+            .collect();
+        if outputs.is_empty() {
+            return Err(ControlError::SessionGone(format!(
+                "pulse: no source output for pid {}",
+                pid
+            )));
+        }
+        Ok(outputs)
+    }
+
+    /// Sink inputs of `pid`; none at all means its session is gone.
+    fn inputs_of(&mut self, pid: u32) -> AudioResult<Vec<Node>> {
+        let inputs: Vec<Node> = self
+            .sink_inputs()?
+            .into_iter()
+            .filter(|n| n.pid == Some(pid))
+            .collect();
+        if inputs.is_empty() {
+            return Err(ControlError::SessionGone(format!(
+                "pulse: no sink input for pid {}",
+                pid
+            )));
+        }
+        Ok(inputs)
+    }
+
+    /// Forget the device chosen for sink-input `n`, so it follows the default
+    /// sink again. Moving it to `@DEFAULT_SINK@` would only pin it to the sink
+    /// that is default right now. Without module-stream-restore that move is
+    /// the best there is.
+    fn follow_default(&mut self, n: &Node) -> AudioResult<()> {
+        let forgotten = match &n.restore_rule {
+            Some(rule) => self.run("clear stream device", |b, ok| {
+                let rule = RestoreRule {
+                    name: Some(rule.into()),
+                    channel_map: n.channel_map,
+                    volume: n.volume,
+                    device: None,
+                    mute: n.mute,
+                };
+                b.context
+                    .stream_restore()
+                    .write(UpdateMode::Replace, &[&rule], true, move |s| ok.set(s))
+            }),
+            None => Err("pulse: no stream-restore rule".into()),
+        };
+        if forgotten.is_ok() {
+            return Ok(());
+        }
+        self.run("move sink-input", |b, ok| {
+            b.context
+                .introspect()
+                .move_sink_input_by_name(n.index, DEFAULT_SINK, Some(report(ok)))
+        })
+    }
+
+    fn default_names(&mut self) -> AudioResult<(String, String)> {
+        let out = Rc::new(RefCell::new((String::new(), String::new())));
+        let sink = out.clone();
+        let op = self.context.introspect().get_server_info(move |i| {
+            *sink.borrow_mut() = (
+                i.default_sink_name
+                    .as_deref()
+                    .unwrap_or_default()
+                    .to_string(),
+                i.default_source_name
+                    .as_deref()
+                    .unwrap_or_default()
+                    .to_string(),
+            );
+        });
+        self.wait(op)?;
+        Ok(out.take())
+    }
+
+    fn default_sink(&mut self) -> AudioResult<Node> {
+        let (name, _) = self.default_names()?;
+        self.sinks()?
+            .into_iter()
+            .find(|n| n.name == name)
+            .ok_or_else(|| ControlError::DeviceNotFound("pulse: no default sink".into()))
+    }
+
+    fn default_source(&mut self) -> AudioResult<Node> {
+        let (_, name) = self.default_names()?;
+        self.sources()?
+            .into_iter()
+            .find(|n| n.name == name)
+            .ok_or_else(|| ControlError::DeviceNotFound("pulse: no default source".into()))
+    }
+
+    fn set_sink_volume(&mut self, vol: f32) -> AudioResult<()> {
+        let cv = scaled(&self.default_sink()?.volume, vol);
+        self.run("set sink mute", |b, ok| {
+            b.context
+                .introspect()
+                .set_sink_mute_by_name(DEFAULT_SINK, false, Some(report(ok)))
+        })?;
+        self.run("set sink volume", |b, ok| {
+            b.context
+                .introspect()
+                .set_sink_volume_by_name(DEFAULT_SINK, &cv, Some(report(ok)))
+        })
+    }
+
+    fn set_source_volume(&mut self, vol: f32) -> AudioResult<()> {
+        let cv = scaled(&self.default_source()?.volume, vol);
+        self.run("set source mute", |b, ok| {
+            b.context
+                .introspect()
+                .set_source_mute_by_name(DEFAULT_SOURCE, false, Some(report(ok)))
+        })?;
+        self.run("set source volume", |b, ok| {
+            b.context
+                .introspect()
+                .set_source_volume_by_name(DEFAULT_SOURCE, &cv, Some(report(ok)))
+        })
+    }
+
+    /// Drop the names of processes that have exited, so `app_names` only
+    /// ever holds live pids.
+    fn forget_exited(&mut self) {
+        self.app_names
+            .retain(|pid, _| Path::new(&format!("/proc/{}", pid)).exists());
+    }
+
+    fn devices(nodes: Vec<Node>, default_name: &str) -> Vec<AudioDevice> {
+        let mut devices: Vec<AudioDevice> = nodes
+            .into_iter()
+            .map(|n| AudioDevice {
+                is_default: n.name == default_name,
+                // A single default serves every role.
+                default_roles: if n.name == default_name {
+                    DeviceRole::ALL.to_vec()
+                } else {
+                    Vec::new()
+                },
+                name: if n.description.is_empty() {
+                    n.name.clone()
+                } else {
+                    n.description
+                },
+                id: n.name,
+            })
+            .collect();
+        devices.sort_by(|a, b| a.name.cmp(&b.name));
+        devices
+    }
+}
+
+fn process_id(props: &Proplist) -> Option<u32> {
+    props
+        .get_str("application.process.id")
+        .and_then(|s| s.trim().parse().ok())
+}
+
+/// Name of the module-stream-restore rule a sink-input's device is saved
+/// under, picked from its properties in the same order the module uses.
+fn restore_rule(props: &Proplist) -> Option<String> {
+    if let Some(id) = props.get_str("module-stream-restore.id") {
+        return Some(id);
+    }
+    [
+        ("media.role", "media-role"),
+        ("application.id", "application-id"),
+        ("application.name", "application-name"),
+        ("media.name", "media-name"),
+    ]
+    .into_iter()
+    .find_map(|(key, by)| {
+        props
+            .get_str(key)
+            .map(|value| format!("sink-input-by-{}:{}", by, value))
+    })
+}
+
+fn report(ok: Rc<Cell<bool>>) -> Box<dyn FnMut(bool)> {
+    Box::new(move |success| ok.set(success))
+}
+
+fn to_scalar(cv: &ChannelVolumes) -> f32 {
+    cv.avg().0 as f32 / Volume::NORMAL.0 as f32
+}
+
+/// Same channel layout as `cv`, every channel set to `vol` (0.0..=1.0).
+fn scaled(cv: &ChannelVolumes, vol: f32) -> ChannelVolumes {
+    let mut out = *cv;
+    let raw = (vol.clamp(0.0, 1.0) * Volume::NORMAL.0 as f32).round() as u32;
+    out.set(cv.len(), Volume(raw));
+    out
+}
+
+impl AudioBackend for PulseBackend {
+    fn master_volume(&mut self) -> AudioResult<(f32, bool)> {
+        let sink = self.default_sink()?;
+        Ok((to_scalar(&sink.volume), sink.mute))
+    }
+
+    fn mic_volume(&mut self) -> AudioResult<(f32, bool)> {
+        let source = self.default_source()?;
+        Ok((to_scalar(&source.volume), source.mute))
+    }
+
+    fn set_master_volume(&mut self, vol: f32) -> AudioResult<()> {
+        self.set_sink_volume(vol)
+    }
+
+    fn set_mic_volume(&mut self, vol: f32) -> AudioResult<()> {
+        self.set_source_volume(vol)
+    }
+
+    fn set_master_mute(&mut self, mute: bool) -> AudioResult<()> {
+        self.run("set sink mute", |b, ok| {
+            b.context
+                .introspect()
+                .set_sink_mute_by_name(DEFAULT_SINK, mute, Some(report(ok)))
+        })
+    }
+
+    fn set_mic_mute(&mut self, mute: bool) -> AudioResult<()> {
+        self.run("set source mute", |b, ok| {
+            b.context
+                .introspect()
+                .set_source_mute_by_name(DEFAULT_SOURCE, mute, Some(report(ok)))
+        })
+    }
+
+    fn app_sessions(&mut self) -> AudioResult<Vec<AppSession>> {
+        let mut sessions = Vec::new();
+        self.forget_exited();
+        for n in self.sink_inputs()? {
+            let pid = match n.pid {
+                Some(pid) if pid != 0 => pid,
+                _ => continue,
+            };
+            if let Some(name) = n.app_name {
+                self.app_names.insert(pid, name);
+            }
+            sessions.push(AppSession {
+                id: format!("sink-input:{}", n.index),
+                pid,
+                volume: to_scalar(&n.volume),
+                is_muted: n.mute,
+                is_active: !n.corked,
+                ..Default::default()
+            });
+        }
+        Ok(sessions)
+    }
+
+    fn set_app_volume(&mut self, pid: u32, vol: f32) -> AudioResult<()> {
+        for n in self.inputs_of(pid)? {
+            let cv = scaled(&n.volume, vol);
+            self.run("set sink-input volume", |b, ok| {
+                b.context
+                    .introspect()
+                    .set_sink_input_volume(n.index, &cv, Some(report(ok)))
+            })?;
+        }
+        Ok(())
+    }
+
+    fn set_app_mute(&mut self, pid: u32, mute: bool) -> AudioResult<()> {
+        for n in self.inputs_of(pid)? {
+            self.run("set sink-input mute", |b, ok| {
+                b.context
+                    .introspect()
+                    .set_sink_input_mute(n.index, mute, Some(report(ok)))
+            })?;
+        }
+        Ok(())
+    }
+
+    fn capture_sessions(&mut self) -> AudioResult<Vec<AppSession>> {
+        let mut sessions = Vec::new();
+        self.forget_exited();
+        for n in self.source_outputs()? {
+            let pid = match n.pid {
+                Some(pid) if pid != 0 => pid,
+                _ => continue,
+            };
+            if let Some(name) = n.app_name {
+                self.app_names.insert(pid, name);
+            }
+            sessions.push(AppSession {
+                id: format!("source-output:{}", n.index),
+                pid,
+                volume: to_scalar(&n.volume),
+                is_muted: n.mute,
+                is_active: !n.corked,
+                ..Default::default()
+            });
+        }
+        Ok(sessions)
+    }
+
+    fn set_capture_app_volume(&mut self, pid: u32, vol: f32) -> AudioResult<()> {
+        for n in self.outputs_of(pid)? {
+            let cv = scaled(&n.volume, vol);
+            self.run("set source-output volume", |b, ok| {
+                b.context
+                    .introspect()
+                    .set_source_output_volume(n.index, &cv, Some(report(ok)))
+            })?;
+        }
+        Ok(())
+    }
+
+    fn set_capture_app_mute(&mut self, pid: u32, mute: bool) -> AudioResult<()> {
+        for n in self.outputs_of(pid)? {
+            self.run("set source-output mute", |b, ok| {
+                b.context
+                    .introspect()
+                    .set_source_output_mute(n.index, mute, Some(report(ok)))
+            })?;
+        }
+        Ok(())
+    }
+
+    fn playback_devices(&mut self) -> AudioResult<Vec<AudioDevice>> {
+        let (default_sink, _) = self.default_names()?;
+        Ok(Self::devices(self.sinks()?, &default_sink))
+    }
+
+    fn capture_devices(&mut self) -> AudioResult<Vec<AudioDevice>> {
+        let (_, default_source) = self.default_names()?;
+        Ok(Self::devices(self.sources()?, &default_source))
+    }
+
+    /// PulseAudio has a single default per direction, which serves every
+    /// role; asking for a separate communications device is an error.
+    fn set_default_device(&mut self, id: &str, roles: &[DeviceRole]) -> AudioResult<()> {
+        if roles.is_empty() {
+            return Ok(());
+        }
+        if roles == [DeviceRole::Communications] {
+            return Err(ControlError::InvalidArgument(
+                "pulse: no separate communications device".into(),
+            ));
+        }
+        if self.sinks()?.iter().any(|n| n.name == id) {
+            return self.run("set default sink", |b, ok| {
+                b.context.set_default_sink(id, move |s| ok.set(s))
+            });
+        }
+        if self.sources()?.iter().any(|n| n.name == id) {
+            return self.run("set default source", |b, ok| {
+                b.context.set_default_source(id, move |s| ok.set(s))
+            });
+        }
+        Err(ControlError::DeviceNotFound(format!("pulse: {}", id)))
+    }
+
+    fn set_app_output_device(&mut self, pid: u32, device_id: Option<&str>) -> AudioResult<()> {
+        let target = match device_id {
+            Some(id) if !self.sinks()?.iter().any(|n| n.name == id) => {
+                return Err(ControlError::DeviceNotFound(format!("pulse: {}", id)));
+            }
+            Some(id) => id,
+            None => {
+                for n in self.inputs_of(pid)? {
+                    self.follow_default(&n)?;
+                }
+                return Ok(());
+            }
+        };
+        for n in self.inputs_of(pid)? {
+            self.run("move sink-input", |b, ok| {
+                b.context
+                    .introspect()
+                    .move_sink_input_by_name(n.index, target, Some(report(ok)))
+            })?;
+        }
+        Ok(())
+    }
+
+    fn app_output_devices(&mut self, pids: &[u32]) -> HashMap<u32, String> {
+        let (Ok((default_sink, _)), Ok(sinks), Ok(inputs)) =
+            (self.default_names(), self.sinks(), self.sink_inputs())
+        else {
+            return HashMap::new();
+        };
+        inputs
+            .into_iter()
+            .filter_map(|n| {
+                let pid = n.pid.filter(|pid| pids.contains(pid))?;
+                let sink = sinks.iter().find(|s| Some(s.index) == n.sink)?;
+                (sink.name != default_sink).then(|| (pid, sink.name.clone()))
+            })
+            .collect()
+    }
+
+    /// Field 22 of `/proc/<pid>/stat`, in clock ticks since boot. Zombies
+    /// count as gone.
+    fn process_start_time(&mut self, pid: u32) -> Option<u64> {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        // The command name may contain spaces and parentheses; fields are
+        // counted from the last `)`, which ends it.
+        let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+        if fields.next()? == "Z" {
+            return None;
+        }
+        fields.nth(18)?.parse().ok()
+    }
+
+    fn parent_pid(&mut self, pid: u32) -> Option<u32> {
+        self.apps.parent(pid)
+    }
+
+    /// Desktop entry name first, then the stream's `application.name`, then
+    /// the kernel's process name.
+    fn describe_processes(&mut self, pids: &[u32]) -> HashMap<u32, ProcessInfo> {
+        let mut found = HashMap::new();
+        for &pid in pids {
+            let app = self.apps.process(pid).unwrap_or_default();
+            let Some(name) = app
+                .name
+                .or_else(|| self.app_names.get(&pid).cloned())
+                .or((!app.comm.is_empty()).then_some(app.comm))
+            else {
+                continue;
+            };
+            if let (Some(icon), false) = (app.icon, app.exe_path.is_empty()) {
+                self.icon_names.insert(app.exe_path.clone(), icon);
+            }
+            found.insert(
+                pid,
+                ProcessInfo {
+                    name,
+                    icon_path: String::new(),
+                    exe_path: app.exe_path,
+                },
+            );
+        }
+        found
+    }
+
+    fn render_icon(&mut self, exe_path: &str, size: u32) -> Option<IconImage> {
+        let icon = self.icon_names.get(exe_path)?.clone();
+        self.apps.icon_image(&icon, size)
+    }
+
+    fn subscribe(&mut self, notifier: Notifier) -> AudioResult<()> {
+        if self.watcher.is_some() {
+            return Ok(());
+        }
+        // The worker's mainloop only runs while it serves a request, so change
+        // events get their own connection on a dedicated thread.
+        let server = self.server.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        let handle = {
+            let stop = stop.clone();
+            thread::spawn(move || watch(server, notifier, &stop, ready_tx))
+        };
+        let watcher = Watcher {
+            stop,
+            handle: Some(handle),
+        };
+        ready_rx
+            .recv()
+            .map_err(|e| ControlError::BackendUnavailable(e.to_string()))??;
+        self.watcher = Some(watcher);
+        Ok(())
+    }
+}
+
+/// Body of the watcher thread. Reports whether the first connection could
+/// subscribe through `ready`, then forwards events until `stop` is set,
+/// reconnecting whenever the daemon goes away.
+fn watch(
+    server: Option<String>,
+    notifier: Notifier,
+    stop: &AtomicBool,
+    ready: Sender<AudioResult<()>>,
+) {
+    let mut ready = Some(ready);
+    while !stop.load(Ordering::Relaxed) {
+        let connected = PulseBackend::connect(server.as_deref()).and_then(|mut watcher| {
+            watcher.forward_events(notifier.clone())?;
+            Ok(watcher)
+        });
+        match (connected, ready.take()) {
+            (Ok(mut watcher), ready) => {
+                match ready {
+                    Some(ready) => {
+                        let _ = ready.send(Ok(()));
+                    }
+                    // Back after a daemon restart; anything may have changed.
+                    None => notifier.notify(BackendEvent::Devices),
+                }
+                watcher.dispatch_until(stop);
+            }
+            (Err(e), Some(ready)) => {
+                let _ = ready.send(Err(e));
+                return;
+            }
+            (Err(_), None) => {}
+        }
+        let retry = Instant::now() + RECONNECT_DELAY;
+        while !stop.load(Ordering::Relaxed) && Instant::now() < retry {
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+impl PulseBackend {
+    /// Subscribe to changes on this connection and pass them to `notifier`.
+    fn forward_events(&mut self, notifier: Notifier) -> AudioResult<()> {
+        self.context
+            .set_subscribe_callback(Some(Box::new(move |facility, op, _index| {
+                let added_or_removed = matches!(
+                    op,
+                    Some(SubscribeOperation::New) | Some(SubscribeOperation::Removed)
+                );
+                match facility {
+                    Some(Facility::Sink | Facility::Source) if added_or_removed => {
+                        notifier.notify(BackendEvent::Devices)
+                    }
+                    Some(Facility::Sink) => notifier.notify(BackendEvent::Master),
+                    Some(Facility::Source) => notifier.notify(BackendEvent::Mic),
+                    Some(Facility::SinkInput) => notifier.notify(BackendEvent::Sessions),
+                    Some(Facility::SourceOutput) => notifier.notify(BackendEvent::CaptureSessions),
+                    // Default sink or source changed.
+                    Some(Facility::Server) => notifier.notify(BackendEvent::Devices),
+                    _ => {}
+                }
+            })));
+        let mask = InterestMaskSet::SINK
+            | InterestMaskSet::SOURCE
+            | InterestMaskSet::SINK_INPUT
+            | InterestMaskSet::SOURCE_OUTPUT
+            | InterestMaskSet::SERVER;
+        self.run("subscribe", |b, ok| {
+            b.context.subscribe(mask, move |s| ok.set(s))
+        })
+    }
+
+    /// Run the mainloop until `stop` is set or the connection is lost.
+    fn dispatch_until(&mut self, stop: &AtomicBool) {
+        while !stop.load(Ordering::Relaxed) {
+            let ml = &mut self.mainloop;
+            if ml.prepare(Some(WATCH_POLL)).is_err() || ml.poll().is_err() || ml.dispatch().is_err()
+            {
+                return;
+            }
+            if self.context.get_state() != ContextState::Ready {
+                return;
+            }
+        }
+    }
+}
+
+/// These need a PulseAudio (or pipewire-pulse) server with module-stream-restore
+/// loaded, which the stock configuration does. They add two null sinks and
+/// remove them again. To keep away from a desktop session, start a
+/// throwaway daemon and point `PULSE_TEST_SERVER` at it:
+///
+/// ```text
+/// pulseaudio -n --daemonize=no --exit-idle-time=-1 \
+///     -L "module-native-protocol-unix socket=/tmp/pulse-test/native" \
+///     -L module-stream-restore &
+/// PULSE_TEST_SERVER=unix:/tmp/pulse-test/native cargo test pulse -- --ignored
+/// ```
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulse::sample::{Format, Spec};
+    use pulse::stream::{FlagSet as StreamFlagSet, State as StreamState, Stream};
+
+    const SINK_A: &str = "wcc_test_a";
+    const SINK_B: &str = "wcc_test_b";
+
+    /// A connection with two null sinks, `SINK_A` the default.
+    struct Fixture {
+        backend: PulseBackend,
+        modules: Vec<u32>,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let server = std::env::var("PULSE_TEST_SERVER").ok();
+            let backend = PulseBackend::connect(server.as_deref()).expect("pulse server");
+            let mut fixture = Self {
+                backend,
+                modules: Vec::new(),
+            };
+            for sink in [SINK_A, SINK_B] {
+                let index = Rc::new(Cell::new(u32::MAX));
+                let out = index.clone();
+                let op = fixture.backend.context.introspect().load_module(
+                    "module-null-sink",
+                    &format!("sink_name={}", sink),
+                    move |i| out.set(i),
+                );
+                fixture.backend.wait(op).unwrap();
+                assert_ne!(index.get(), u32::MAX, "loading {} failed", sink);
+                fixture.modules.push(index.get());
+            }
+            fixture
+                .backend
+                .set_default_device(SINK_A, &DeviceRole::ALL)
+                .unwrap();
+            fixture
+        }
+
+        /// Start a silent playback stream on the default sink. Its sink-input
+        /// carries this process's pid.
+        fn play(&mut self) -> Stream {
+            let spec = Spec {
+                format: Format::S16le,
+                rate: 44100,
+                channels: 2,
+            };
+            let mut stream =
+                Stream::new(&mut self.backend.context, "test tone", &spec, None).expect("stream");
+            stream
+                .connect_playback(None, None, StreamFlagSet::START_CORKED, None, None)
+                .unwrap();
+            while stream.get_state() != StreamState::Ready {
+                assert!(!matches!(
+                    stream.get_state(),
+                    StreamState::Failed | StreamState::Terminated
+                ));
+                self.backend.mainloop.iterate(true);
+            }
+            stream
+        }
+
+        /// Name of the sink this process's stream is on.
+        fn sink_of_stream(&mut self) -> String {
+            let pid = std::process::id();
+            let input = self.backend.inputs_of(pid).unwrap().remove(0);
+            let sinks = self.backend.sinks().unwrap();
+            let sink = sinks.iter().find(|s| Some(s.index) == input.sink).unwrap();
+            sink.name.clone()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            for index in std::mem::take(&mut self.modules) {
+                let op = self
+                    .backend
+                    .context
+                    .introspect()
+                    .unload_module(index, |_| {});
+                let _ = self.backend.wait(op);
+            }
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.01
+    }
+
+    #[test]
+    #[ignore = "needs a pulse server"]
+    fn master_volume_and_mute() {
+        let mut f = Fixture::new();
+        let b = &mut f.backend;
+        b.set_master_volume(0.3).unwrap();
+        let (vol, muted) = b.master_volume().unwrap();
+        assert!(close(vol, 0.3) && !muted, "{} {}", vol, muted);
+        b.set_master_mute(true).unwrap();
+        assert!(b.master_volume().unwrap().1);
+    }
+
+    #[test]
+    #[ignore = "needs a pulse server"]
+    fn default_device_switch() {
+        let mut f = Fixture::new();
+        let b = &mut f.backend;
+        b.set_default_device(SINK_B, &DeviceRole::ALL).unwrap();
+        let devices = b.playback_devices().unwrap();
+        let default: Vec<_> = devices.iter().filter(|d| d.is_default).collect();
+        assert_eq!(default.len(), 1);
+        assert_eq!(default[0].id, SINK_B);
+        assert!(matches!(
+            b.set_default_device(SINK_A, &[DeviceRole::Communications]),
+            Err(ControlError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            b.set_default_device("wcc_no_such_sink", &DeviceRole::ALL),
+            Err(ControlError::DeviceNotFound(_))
+        ));
+    }
+
+    #[test]
+    #[ignore = "needs a pulse server"]
+    fn app_volume_and_mute() {
+        let mut f = Fixture::new();
+        let _stream = f.play();
+        let pid = std::process::id();
+        let b = &mut f.backend;
+        b.set_app_volume(pid, 0.4).unwrap();
+        b.set_app_mute(pid, true).unwrap();
+        let sessions = b.app_sessions().unwrap();
+        let ours = sessions.iter().find(|s| s.pid == pid).expect("our stream");
+        assert!(close(ours.volume, 0.4) && ours.is_muted);
+        assert!(matches!(
+            b.set_app_volume(u32::MAX, 0.4),
+            Err(ControlError::SessionGone(_))
+        ));
+    }
+
+    #[test]
+    #[ignore = "needs a pulse server"]
+    fn names_of_exited_processes_are_forgotten() {
+        let mut f = Fixture::new();
+        let _stream = f.play();
+        let pid = std::process::id();
+        let b = &mut f.backend;
+        b.app_names.insert(u32::MAX, "gone".into());
+        b.app_sessions().unwrap();
+        assert!(!b.app_names.contains_key(&u32::MAX));
+        assert!(b.app_names.contains_key(&pid));
+    }
+
+    #[test]
+    #[ignore = "needs a pulse server"]
+    fn stream_is_moved_and_reset_to_follow_the_default() {
+        let mut f = Fixture::new();
+        let _stream = f.play();
+        let pid = std::process::id();
+        assert_eq!(f.sink_of_stream(), SINK_A);
+
+        f.backend.set_app_output_device(pid, Some(SINK_B)).unwrap();
+        assert_eq!(f.sink_of_stream(), SINK_B);
+        let routed = f.backend.app_output_devices(&[pid]);
+        assert_eq!(routed.get(&pid).map(String::as_str), Some(SINK_B));
+
+        f.backend.set_app_output_device(pid, None).unwrap();
+        assert_eq!(f.sink_of_stream(), SINK_A);
+        assert!(f.backend.app_output_devices(&[pid]).is_empty());
+
+        // No longer pinned: the stream goes wherever the default goes.
+        f.backend
+            .set_default_device(SINK_B, &DeviceRole::ALL)
+            .unwrap();
+        assert_eq!(f.sink_of_stream(), SINK_B);
+    }
+}
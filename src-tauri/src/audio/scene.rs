@@ -0,0 +1,187 @@
+//! Named snapshots of the whole mixer ("meeting", "gaming", ...), stored as
+//! versioned JSON next to the rest of the app configuration.
+
+use super::AudioResult;
+use crate::error::ControlError;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Bump when the file layout changes. Files of this version or older are
+/// read as they are; newer ones are rejected rather than misread.
+pub const SCENE_FILE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Level {
+    pub volume: f32,
+    pub is_muted: bool,
+}
+
+impl From<(f32, bool)> for Level {
+    fn from((volume, is_muted): (f32, bool)) -> Self {
+        Self { volume, is_muted }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SceneApp {
+    /// [`super::AppVolume::group`], normally the executable path.
+    pub group: String,
+    /// Display name at the time of capture, for the UI only.
+    pub name: String,
+    pub volume: f32,
+    pub is_muted: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Scene {
+    pub name: String,
+    pub master: Level,
+    /// Absent on machines without a microphone.
+    pub mic: Option<Level>,
+    pub apps: Vec<SceneApp>,
+    pub playback_device: Option<String>,
+    pub capture_device: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SceneFile {
+    version: u32,
+    scenes: Vec<Scene>,
+}
+
+/// The scene file on disk. All access goes through one lock so concurrent
+/// save/delete commands cannot drop each other's changes.
+pub struct SceneStore {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl SceneStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn list(&self) -> AudioResult<Vec<Scene>> {
+        let _guard = self.lock.lock().map_err(|e| e.to_string())?;
+        self.read()
+    }
+
+    pub fn get(&self, name: &str) -> AudioResult<Scene> {
+        self.list()?
+            .into_iter()
+            .find(|s| s.name == name)
+            .ok_or_else(|| ControlError::InvalidArgument(format!("scene not found: {}", name)))
+    }
+
+    /// Insert `scene`, replacing any scene with the same name.
+    pub fn save(&self, scene: Scene) -> AudioResult<()> {
+        let _guard = self.lock.lock().map_err(|e| e.to_string())?;
+        let mut scenes = self.read()?;
+        match scenes.iter_mut().find(|s| s.name == scene.name) {
+            Some(existing) => *existing = scene,
+            None => scenes.push(scene),
+        }
+        self.write(scenes)
+    }
+
+    pub fn delete(&self, name: &str) -> AudioResult<()> {
+        let _guard = self.lock.lock().map_err(|e| e.to_string())?;
+        let mut scenes = self.read()?;
+        let before = scenes.len();
+        scenes.retain(|s| s.name != name);
+        if scenes.len() == before {
+            return Err(ControlError::InvalidArgument(format!(
+                "scene not found: {}",
+                name
+            )));
+        }
+        self.write(scenes)
+    }
+
+    fn read(&self) -> AudioResult<Vec<Scene>> {
+        let text = match std::fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(ControlError::Failed(format!(
+                    "failed to read {}: {}",
+                    self.path.display(),
+                    e
+                )))
+            }
+        };
+        let file: SceneFile = serde_json::from_str(&text)
+            .map_err(|e| format!("invalid scene file {}: {}", self.path.display(), e))?;
+        if file.version > SCENE_FILE_VERSION {
+            return Err(ControlError::Failed(format!(
+                "scene file version {} is newer than supported version {}",
+                file.version, SCENE_FILE_VERSION
+            )));
+        }
+        Ok(file.scenes)
+    }
+
+    /// Write to a temporary file first so a crash never leaves half a file.
+    fn write(&self, scenes: Vec<Scene>) -> AudioResult<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = SceneFile {
+            version: SCENE_FILE_VERSION,
+            scenes,
+        };
+        let text = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, text)?;
+        Ok(std::fs::rename(&tmp, &self.path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(name: &str) -> SceneStore {
+        let path =
+            std::env::temp_dir().join(format!("wcc-scenes-{}-{}.json", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        SceneStore::new(path)
+    }
+
+    fn scene(name: &str, volume: f32) -> Scene {
+        Scene {
+            name: name.into(),
+            master: (volume, false).into(),
+            mic: None,
+            apps: Vec::new(),
+            playback_device: None,
+            capture_device: None,
+        }
+    }
+
+    #[test]
+    fn save_replaces_a_scene_of_the_same_name() {
+        let store = store("replace");
+        store.save(scene("a", 0.1)).unwrap();
+        store.save(scene("b", 0.2)).unwrap();
+        store.save(scene("a", 0.3)).unwrap();
+        assert_eq!(store.list().unwrap(), [scene("a", 0.3), scene("b", 0.2)]);
+        store.delete("a").unwrap();
+        assert!(store.get("a").is_err());
+        assert!(store.delete("a").is_err());
+        let _ = std::fs::remove_file(&store.path);
+    }
+
+    #[test]
+    fn newer_files_are_rejected() {
+        let store = store("newer");
+        let text = format!(r#"{{"version": {}, "scenes": []}}"#, SCENE_FILE_VERSION + 1);
+        std::fs::write(&store.path, text).unwrap();
+        assert!(store.list().is_err());
+        let _ = std::fs::remove_file(&store.path);
+    }
+}
@@ -0,0 +1,299 @@
+//! How the mixer orders and filters its rows: a sort mode, pinned groups and
+//! an optional cut-off for rows that have gone quiet. Saved to disk so the
+//! layout survives restarts.
+
+use super::{AppVolume, AudioResult, SessionState};
+use crate::error::ControlError;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+pub const VIEW_FILE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortMode {
+    /// Active rows first, each part by name.
+    PlayingFirst,
+    /// Active rows first, then the most recently active.
+    RecentlyActive,
+    #[default]
+    Alphabetical,
+    /// Pinned rows first, in the order they were pinned, then by name.
+    PinnedFirst,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct MixerView {
+    pub sort: SortMode,
+    /// Hide rows that have been inactive for longer than this; `None`
+    /// shows every row. Pinned rows are never hidden.
+    pub hide_inactive_after_mins: Option<u32>,
+    /// Keyed by [`AppVolume::group`].
+    pub pinned: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ViewFile {
+    version: u32,
+    #[serde(flatten)]
+    view: MixerView,
+}
+
+impl MixerView {
+    /// Drop hidden rows and sort the rest. `now` is in Unix milliseconds,
+    /// like [`AppVolume::last_active`].
+    pub fn arrange(&self, apps: &mut Vec<AppVolume>, now: u64) {
+        if let Some(mins) = self.hide_inactive_after_mins {
+            let cutoff = now.saturating_sub(mins as u64 * 60_000);
+            apps.retain(|a| {
+                a.state == SessionState::Active
+                    || self.pinned.contains(&a.group)
+                    || a.last_active.is_some_and(|t| t >= cutoff)
+            });
+        }
+        let pin = |a: &AppVolume| {
+            self.pinned
+                .iter()
+                .position(|g| *g == a.group)
+                .unwrap_or(usize::MAX)
+        };
+        let inactive = |a: &AppVolume| a.state != SessionState::Active;
+        apps.sort_by(|a, b| {
+            let first = match self.sort {
+                SortMode::PlayingFirst => inactive(a).cmp(&inactive(b)),
+                SortMode::RecentlyActive => inactive(a)
+                    .cmp(&inactive(b))
+                    .then_with(|| b.last_active.cmp(&a.last_active)),
+                SortMode::Alphabetical => std::cmp::Ordering::Equal,
+                SortMode::PinnedFirst => pin(a).cmp(&pin(b)),
+            };
+            first
+                .then_with(|| a.name.cmp(&b.name))
+                .then_with(|| a.group.cmp(&b.group))
+        });
+    }
+}
+
+/// Owned by the audio worker; changes are written through to disk.
+pub struct ViewStore {
+    path: Option<PathBuf>,
+    view: MixerView,
+}
+
+impl ViewStore {
+    /// Load from `path`, starting with the defaults when the file does not
+    /// exist yet.
+    pub fn load(path: PathBuf) -> AudioResult<Self> {
+        let view = match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                let file: ViewFile = serde_json::from_str(&text)
+                    .map_err(|e| format!("invalid mixer view {}: {}", path.display(), e))?;
+                if file.version > VIEW_FILE_VERSION {
+                    return Err(ControlError::Failed(format!(
+                        "mixer view version {} is newer than supported version {}",
+                        file.version, VIEW_FILE_VERSION
+                    )));
+                }
+                file.view
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => MixerView::default(),
+            Err(e) => {
+                return Err(ControlError::Failed(format!(
+                    "failed to read {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        Ok(Self {
+            path: Some(path),
+            view,
+        })
+    }
+
+    /// A view that is never written to disk.
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            view: MixerView::default(),
+        }
+    }
+
+    pub fn get(&self) -> &MixerView {
+        &self.view
+    }
+
+    /// Write `view` to disk and keep it. A view that could not be written
+    /// is not kept either.
+    pub fn set(&mut self, view: MixerView) -> AudioResult<()> {
+        if let Some(path) = &self.path {
+            let file = ViewFile {
+                version: VIEW_FILE_VERSION,
+                view: view.clone(),
+            };
+            let text = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            // Through a temporary file, so a crash never leaves half a view.
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, text)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        self.view = view;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: u64 = 60_000;
+    const NOW: u64 = 1_000 * MIN;
+
+    fn app(name: &str, state: SessionState, last_active: Option<u64>) -> AppVolume {
+        AppVolume {
+            pid: 0,
+            name: name.to_string(),
+            volume: 1.0,
+            is_muted: false,
+            icon_path: String::new(),
+            group: format!("/apps/{}", name),
+            pids: Vec::new(),
+            output_device_id: None,
+            peak: 0.0,
+            is_system: false,
+            state,
+            last_active,
+        }
+    }
+
+    /// Rows in no particular order.
+    fn rows() -> Vec<AppVolume> {
+        use SessionState::{Active, Inactive};
+        vec![
+            app("echo", Inactive, None),
+            app("cello", Inactive, Some(NOW - 30 * MIN)),
+            app("flute", Inactive, Some(NOW - 60 * MIN)),
+            app("drum", Active, None),
+            app("bass", Active, Some(NOW)),
+            app("alto", Inactive, Some(NOW - 2 * MIN)),
+        ]
+    }
+
+    fn view(sort: SortMode, hide: Option<u32>, pinned: &[&str]) -> MixerView {
+        MixerView {
+            sort,
+            hide_inactive_after_mins: hide,
+            pinned: pinned
+                .iter()
+                .map(|name| format!("/apps/{}", name))
+                .collect(),
+        }
+    }
+
+    fn names(apps: &[AppVolume]) -> Vec<&str> {
+        apps.iter().map(|a| a.name.as_str()).collect()
+    }
+
+    #[test]
+    fn rows_are_sorted_and_hidden_by_the_view() {
+        use SortMode::*;
+        let cases = [
+            (
+                view(Alphabetical, None, &[]),
+                vec!["alto", "bass", "cello", "drum", "echo", "flute"],
+            ),
+            (
+                view(PlayingFirst, None, &[]),
+                vec!["bass", "drum", "alto", "cello", "echo", "flute"],
+            ),
+            // Never active goes last.
+            (
+                view(RecentlyActive, None, &[]),
+                vec!["bass", "drum", "alto", "cello", "flute", "echo"],
+            ),
+            // In pin order, not by name.
+            (
+                view(PinnedFirst, None, &["flute", "cello"]),
+                vec!["flute", "cello", "alto", "bass", "drum", "echo"],
+            ),
+            // Pins only matter to PinnedFirst.
+            (
+                view(Alphabetical, None, &["flute"]),
+                vec!["alto", "bass", "cello", "drum", "echo", "flute"],
+            ),
+            (
+                view(Alphabetical, Some(10), &[]),
+                vec!["alto", "bass", "drum"],
+            ),
+            // Pinned and playing rows stay however long they were quiet.
+            (
+                view(Alphabetical, Some(10), &["flute"]),
+                vec!["alto", "bass", "drum", "flute"],
+            ),
+            // The cutoff itself still counts as recent.
+            (
+                view(PlayingFirst, Some(2), &[]),
+                vec!["bass", "drum", "alto"],
+            ),
+            (view(PlayingFirst, Some(0), &[]), vec!["bass", "drum"]),
+        ];
+        for (view, expected) in cases {
+            let mut apps = rows();
+            view.arrange(&mut apps, NOW);
+            assert_eq!(names(&apps), expected, "{:?}", view);
+        }
+    }
+
+    #[test]
+    fn equal_rows_are_ordered_by_name_then_group() {
+        let same = |group: &str| AppVolume {
+            group: group.to_string(),
+            ..app("bass", SessionState::Active, Some(NOW))
+        };
+        let other = app("alto", SessionState::Active, Some(NOW));
+        for sort in [
+            SortMode::PlayingFirst,
+            SortMode::RecentlyActive,
+            SortMode::Alphabetical,
+            SortMode::PinnedFirst,
+        ] {
+            for mut apps in [
+                vec![same("/opt/bass"), same("/apps/bass"), other.clone()],
+                vec![other.clone(), same("/apps/bass"), same("/opt/bass")],
+            ] {
+                view(sort, None, &[]).arrange(&mut apps, NOW);
+                let groups: Vec<&str> = apps.iter().map(|a| a.group.as_str()).collect();
+                assert_eq!(
+                    groups,
+                    ["/apps/alto", "/apps/bass", "/opt/bass"],
+                    "{:?}",
+                    sort
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn view_is_kept_only_once_written() {
+        let dir = std::env::temp_dir().join(format!("wcc-view-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("view.json");
+        let mut store = ViewStore::load(path.clone()).unwrap();
+        let pinned = view(SortMode::PinnedFirst, Some(5), &["bass"]);
+        store.set(pinned.clone()).unwrap();
+        assert_eq!(ViewStore::load(path.clone()).unwrap().get(), &pinned);
+        assert!(!path.with_extension("json.tmp").exists());
+
+        // A directory where the file should go makes the write fail.
+        let mut store = ViewStore::load(path.clone()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::create_dir(&path).unwrap();
+        assert!(store.set(MixerView::default()).is_err());
+        assert_eq!(store.get(), &pinned);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,827 @@
+#![allow(non_snake_case)]
+#![allow(non_camel_case_types)]
+#![allow(unused_imports)]
+#![allow(dead_code)]
+
+mod events;
+mod routing;
+mod sessions;
+mod switcher;
+
+use super::{
+    AppSession, AudioBackend, AudioDevice, AudioResult, BackendEvent, DeviceRole, Notifier, Peaks,
+    ProcessInfo, Queue,
+};
+use crate::error::ControlError;
+use crate::icons::{self, IconImage};
+use crate::pe;
+use events::{DeviceWatch, Subscriptions};
+use routing::AppRouting;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::path::Path;
+pub use switcher::helper_main;
+use windows::core::{interface, ComInterface, IUnknown, Result, GUID, HRESULT, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{CloseHandle, E_NOINTERFACE, S_OK};
+
+use windows::Win32::Devices::FunctionDiscovery::PKEY_Device_FriendlyName;
+use windows::Win32::Graphics::Gdi::{
+    CreateCompatibleDC, DeleteDC, DeleteObject, GetDIBits, GetObjectW, SelectObject, BITMAP,
+    BITMAPINFO, BITMAPINFOHEADER, DIB_RGB_COLORS,
+};
+use windows::Win32::Media::Audio::Endpoints::{IAudioEndpointVolume, IAudioMeterInformation};
+use windows::Win32::Media::Audio::*;
+use windows::Win32::System::Com::StructuredStorage::{PropVariantClear, PROPVARIANT};
+use windows::Win32::System::Com::*;
+use windows::Win32::System::Diagnostics::ToolHelp::*;
+use windows::Win32::System::ProcessStatus::GetModuleBaseNameW;
+use windows::Win32::System::Threading::{
+    GetExitCodeProcess, GetProcessTimes, OpenProcess, QueryFullProcessImageNameW,
+    PROCESS_NAME_WIN32, PROCESS_QUERY_INFORMATION, PROCESS_QUERY_LIMITED_INFORMATION,
+    PROCESS_VM_READ,
+};
+use windows::Win32::UI::Shell::{
+    ExtractIconExW, SHGetFileInfoW, SHFILEINFOW, SHGFI_ICON, SHGFI_LARGEICON,
+    SHGFI_USEFILEATTRIBUTES,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    DestroyIcon, GetIconInfo, PrivateExtractIconsW, HICON, ICONINFO,
+};
+
+/// WASAPI implementation of [`AudioBackend`]. Must be created and used on the
+/// audio worker thread, since the COM objects it holds are apartment-bound.
+pub struct WasapiBackend {
+    enumerator: IMMDeviceEnumerator,
+    subs: Option<Subscriptions>,
+    devices: Option<DeviceWatch>,
+    /// Missing on Windows builds older than 10 21H2.
+    routing: Option<AppRouting>,
+}
+
+impl WasapiBackend {
+    pub fn new() -> Result<Self> {
+        unsafe {
+            let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+            let enumerator: IMMDeviceEnumerator =
+                CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+            Ok(Self {
+                enumerator,
+                subs: None,
+                devices: None,
+                routing: AppRouting::new().ok(),
+            })
+        }
+    }
+
+    unsafe fn get_sys(&self) -> Result<IAudioEndpointVolume> {
+        let device = self
+            .enumerator
+            .GetDefaultAudioEndpoint(eRender, eMultimedia)?;
+        device.Activate(CLSCTX_ALL, None::<*const PROPVARIANT>)
+    }
+
+    unsafe fn get_mic(&self) -> Result<IAudioEndpointVolume> {
+        let device = self
+            .enumerator
+            .GetDefaultAudioEndpoint(eCapture, eMultimedia)?;
+        device.Activate(CLSCTX_ALL, None::<*const PROPVARIANT>)
+    }
+}
+
+fn err(e: windows::core::Error) -> ControlError {
+    e.into()
+}
+
+/// `found` is whether any session of `pid` was touched.
+fn session_gone(pid: u32, found: bool) -> AudioResult<()> {
+    if found {
+        Ok(())
+    } else {
+        Err(ControlError::SessionGone(format!(
+            "no audio session for pid {}",
+            pid
+        )))
+    }
+}
+
+impl AudioBackend for WasapiBackend {
+    fn master_volume(&mut self) -> AudioResult<(f32, bool)> {
+        unsafe {
+            let v = self.get_sys().map_err(err)?;
+            let vol = v.GetMasterVolumeLevelScalar().map_err(err)?;
+            let mute = v.GetMute().map_err(err)?.as_bool();
+            Ok((vol, mute))
+        }
+    }
+
+    fn mic_volume(&mut self) -> AudioResult<(f32, bool)> {
+        unsafe {
+            let v = self.get_mic().map_err(err)?;
+            let vol = v.GetMasterVolumeLevelScalar().map_err(err)?;
+            let mute = v.GetMute().map_err(err)?.as_bool();
+            Ok((vol, mute))
+        }
+    }
+
+    fn set_master_volume(&mut self, vol: f32) -> AudioResult<()> {
+        unsafe {
+            let v = self.get_sys().map_err(err)?;
+            let _ = v.SetMute(false, std::ptr::null());
+            v.SetMasterVolumeLevelScalar(vol, std::ptr::null())
+                .map_err(err)
+        }
+    }
+
+    fn set_mic_volume(&mut self, vol: f32) -> AudioResult<()> {
+        unsafe {
+            let v = self.get_mic().map_err(err)?;
+            let _ = v.SetMute(false, std::ptr::null());
+            v.SetMasterVolumeLevelScalar(vol, std::ptr::null())
+                .map_err(err)
+        }
+    }
+
+    fn set_master_mute(&mut self, mute: bool) -> AudioResult<()> {
+        unsafe {
+            let v = self.get_sys().map_err(err)?;
+            v.SetMute(mute, std::ptr::null()).map_err(err)
+        }
+    }
+
+    fn set_mic_mute(&mut self, mute: bool) -> AudioResult<()> {
+        unsafe {
+            let v = self.get_mic().map_err(err)?;
+            v.SetMute(mute, std::ptr::null()).map_err(err)
+        }
+    }
+
+    fn app_sessions(&mut self) -> AudioResult<Vec<AppSession>> {
+        // Without notifications the table would go stale; enumerate instead.
+        unsafe {
+            match &mut self.subs {
+                Some(subs) => Ok(subs.table.sessions()),
+                None => internal_get_app_sessions(&self.enumerator, eRender).map_err(err),
+            }
+        }
+    }
+
+    fn set_app_volume(&mut self, pid: u32, vol: f32) -> AudioResult<()> {
+        let found = unsafe {
+            match &mut self.subs {
+                Some(subs) => subs.table.set_volume(pid, vol),
+                None => internal_set_app_vol(&self.enumerator, eRender, pid, vol),
+            }
+            .map_err(err)?
+        };
+        session_gone(pid, found)
+    }
+
+    fn set_app_mute(&mut self, pid: u32, mute: bool) -> AudioResult<()> {
+        let found = unsafe {
+            match &mut self.subs {
+                Some(subs) => subs.table.set_mute(pid, mute),
+                None => internal_set_app_mute(&self.enumerator, eRender, pid, mute),
+            }
+            .map_err(err)?
+        };
+        session_gone(pid, found)
+    }
+
+    fn capture_sessions(&mut self) -> AudioResult<Vec<AppSession>> {
+        unsafe {
+            match &mut self.subs {
+                Some(subs) => Ok(subs.capture.sessions()),
+                None => internal_get_app_sessions(&self.enumerator, eCapture).map_err(err),
+            }
+        }
+    }
+
+    fn set_capture_app_volume(&mut self, pid: u32, vol: f32) -> AudioResult<()> {
+        let found = unsafe {
+            match &mut self.subs {
+                Some(subs) => subs.capture.set_volume(pid, vol),
+                None => internal_set_app_vol(&self.enumerator, eCapture, pid, vol),
+            }
+            .map_err(err)?
+        };
+        session_gone(pid, found)
+    }
+
+    fn set_capture_app_mute(&mut self, pid: u32, mute: bool) -> AudioResult<()> {
+        let found = unsafe {
+            match &mut self.subs {
+                Some(subs) => subs.capture.set_mute(pid, mute),
+                None => internal_set_app_mute(&self.enumerator, eCapture, pid, mute),
+            }
+            .map_err(err)?
+        };
+        session_gone(pid, found)
+    }
+
+    fn playback_devices(&mut self) -> AudioResult<Vec<AudioDevice>> {
+        unsafe { get_audio_endpoints(&self.enumerator, eRender).map_err(err) }
+    }
+
+    fn capture_devices(&mut self) -> AudioResult<Vec<AudioDevice>> {
+        unsafe { get_audio_endpoints(&self.enumerator, eCapture).map_err(err) }
+    }
+
+    fn set_default_device(&mut self, id: &str, roles: &[DeviceRole]) -> AudioResult<()> {
+        switcher::switch_default_device(id, roles)
+    }
+
+    fn set_app_output_device(&mut self, pid: u32, device_id: Option<&str>) -> AudioResult<()> {
+        let routing = self.routing.as_ref().ok_or_else(|| {
+            ControlError::BackendUnavailable(
+                "Per-app output devices need Windows 10 21H2 or later".into(),
+            )
+        })?;
+        // The policy store persists any id it is given, so an unknown one
+        // would silently route the app to nothing.
+        if let Some(id) = device_id {
+            let devices = unsafe { get_audio_endpoints(&self.enumerator, eRender).map_err(err)? };
+            if !devices.iter().any(|d| d.id == id) {
+                return Err(ControlError::DeviceNotFound(id.to_string()));
+            }
+        }
+        routing.set(pid, eRender, device_id).map_err(err)
+    }
+
+    fn app_output_devices(&mut self, pids: &[u32]) -> HashMap<u32, String> {
+        let Some(routing) = &self.routing else {
+            return HashMap::new();
+        };
+        pids.iter()
+            .filter_map(|&pid| routing.get(pid, eRender).map(|id| (pid, id)))
+            .collect()
+    }
+
+    fn describe_processes(&mut self, pids: &[u32]) -> HashMap<u32, ProcessInfo> {
+        describe_processes(pids)
+    }
+
+    fn process_start_time(&mut self, pid: u32) -> Option<u64> {
+        process_start_time(pid)
+    }
+
+    fn parent_pid(&mut self, pid: u32) -> Option<u32> {
+        parent_pid(pid)
+    }
+
+    fn render_icon(&mut self, exe_path: &str, size: u32) -> Option<IconImage> {
+        let (file, index) = icons::split_source(exe_path);
+        let index = index.unwrap_or(0);
+        // GDI only for files whose resources could not be read directly,
+        // such as system DLLs whose icons live in a `.mun` file.
+        pe::Resources::load(Path::new(file))
+            .and_then(|r| r.icon_at(index, size))
+            .or_else(|| icon_image(file, index, size))
+    }
+
+    fn peaks(&mut self) -> AudioResult<Peaks> {
+        unsafe {
+            let sessions = self.subs.as_mut().map(|subs| subs.table.peaks());
+            internal_get_peaks(&self.enumerator, sessions).map_err(err)
+        }
+    }
+
+    fn subscribe(&mut self, notifier: Notifier) -> AudioResult<()> {
+        // Drop (and unregister) the previous set before building a new one.
+        self.subs = None;
+        unsafe {
+            if self.devices.is_none() {
+                self.devices =
+                    Some(DeviceWatch::new(&self.enumerator, notifier.clone()).map_err(err)?);
+            }
+        }
+        let mut subs = Subscriptions::new(notifier);
+        unsafe {
+            let sys = self.get_sys().map_err(err)?;
+            subs.watch_endpoint(sys, BackendEvent::Master)
+                .map_err(err)?;
+            // Machines without a microphone are common; keep the rest working.
+            if let Ok(mic) = self.get_mic() {
+                let _ = subs.watch_endpoint(mic, BackendEvent::Mic);
+            }
+            for manager in session_managers(&self.enumerator, eRender).map_err(err)? {
+                subs.watch_session_manager(manager, eRender).map_err(err)?;
+            }
+            // Without a microphone there is nothing to watch.
+            for manager in session_managers(&self.enumerator, eCapture).unwrap_or_default() {
+                let _ = subs.watch_session_manager(manager, eCapture);
+            }
+        }
+        self.subs = Some(subs);
+        Ok(())
+    }
+}
+
+/// Playback sessions listed both ways, for `benches/sessions.rs`: walking
+/// every endpoint's session enumerator, as before the live table, and reading
+/// the table.
+#[doc(hidden)]
+pub struct SessionBench(WasapiBackend);
+
+impl SessionBench {
+    pub fn new() -> AudioResult<Self> {
+        let mut backend = WasapiBackend::new().map_err(err)?;
+        // Nobody reads the events; the table only has to be kept current.
+        let (queue, _) = Queue::new();
+        backend.subscribe(Notifier(queue))?;
+        Ok(Self(backend))
+    }
+
+    /// Number of sessions found by a fresh enumeration.
+    pub fn enumerate(&self) -> usize {
+        unsafe { internal_get_app_sessions(&self.0.enumerator, eRender).map_or(0, |s| s.len()) }
+    }
+
+    /// Number of sessions in the live table.
+    pub fn table(&mut self) -> usize {
+        self.0.app_sessions().map_or(0, |s| s.len())
+    }
+}
+
+/// Session managers of every active endpoint of `flow`, not just the
+/// default: apps routed to another device keep their sessions there.
+unsafe fn session_managers(
+    enumerator: &IMMDeviceEnumerator,
+    flow: EDataFlow,
+) -> Result<Vec<IAudioSessionManager2>> {
+    let collection = enumerator.EnumAudioEndpoints(flow, DEVICE_STATE_ACTIVE)?;
+    let mut managers = Vec::new();
+    for i in 0..collection.GetCount()? {
+        let device = collection.Item(i)?;
+        managers.push(device.Activate(CLSCTX_ALL, None::<*const PROPVARIANT>)?);
+    }
+    Ok(managers)
+}
+
+unsafe fn internal_get_app_sessions(
+    enumerator: &IMMDeviceEnumerator,
+    flow: EDataFlow,
+) -> Result<Vec<AppSession>> {
+    let mut sessions = Vec::new();
+    for session_manager in session_managers(enumerator, flow)? {
+        let session_enumerator = session_manager.GetSessionEnumerator()?;
+        let count = session_enumerator.GetCount()?;
+        for i in 0..count {
+            if let Ok(session_control) = session_enumerator.GetSession(i) {
+                let state = session_control.GetState()?;
+                if state == AudioSessionStateExpired {
+                    continue;
+                }
+                if let Ok(session_control2) = session_control.cast::<IAudioSessionControl2>() {
+                    let pid = session_control2.GetProcessId()?;
+                    let is_system = session_control2.IsSystemSoundsSession() == S_OK;
+                    if pid == 0 && !is_system {
+                        continue;
+                    }
+                    if let Ok(simple_volume) = session_control.cast::<ISimpleAudioVolume>() {
+                        let volume = simple_volume.GetMasterVolume()?;
+                        let is_muted = simple_volume.GetMute()?.as_bool();
+                        let label = sessions::Label::read(&session_control);
+                        sessions.push(AppSession {
+                            id: events::instance_id(&session_control).unwrap_or_default(),
+                            pid,
+                            volume,
+                            is_muted,
+                            display_name: label.display_name,
+                            icon_path: label.icon_path,
+                            grouping: label.grouping,
+                            is_system,
+                            is_active: state == AudioSessionStateActive,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    Ok(sessions)
+}
+
+/// `sessions` are the table's session peaks; without a table every
+/// endpoint's sessions are enumerated.
+unsafe fn internal_get_peaks(
+    enumerator: &IMMDeviceEnumerator,
+    sessions: Option<HashMap<u32, f32>>,
+) -> Result<Peaks> {
+    let endpoint_peak = |flow| -> Result<f32> {
+        let device = enumerator.GetDefaultAudioEndpoint(flow, eMultimedia)?;
+        let meter: IAudioMeterInformation =
+            device.Activate(CLSCTX_ALL, None::<*const PROPVARIANT>)?;
+        meter.GetPeakValue()
+    };
+    let mut peaks = Peaks {
+        master: endpoint_peak(eRender)?,
+        mic: endpoint_peak(eCapture).ok(),
+        sessions: HashMap::new(),
+    };
+    if let Some(sessions) = sessions {
+        peaks.sessions = sessions;
+        return Ok(peaks);
+    }
+    for session_manager in session_managers(enumerator, eRender)? {
+        let session_enumerator = session_manager.GetSessionEnumerator()?;
+        for i in 0..session_enumerator.GetCount()? {
+            let Ok(session_control) = session_enumerator.GetSession(i) else {
+                continue;
+            };
+            let (Ok(sc2), Ok(meter)) = (
+                session_control.cast::<IAudioSessionControl2>(),
+                session_control.cast::<IAudioMeterInformation>(),
+            ) else {
+                continue;
+            };
+            let (Ok(pid), Ok(peak)) = (sc2.GetProcessId(), meter.GetPeakValue()) else {
+                continue;
+            };
+            let entry = peaks.sessions.entry(pid).or_default();
+            *entry = entry.max(peak);
+        }
+    }
+    Ok(peaks)
+}
+
+unsafe fn internal_set_app_vol(
+    enumerator: &IMMDeviceEnumerator,
+    flow: EDataFlow,
+    target_pid: u32,
+    vol: f32,
+) -> Result<bool> {
+    let mut found = false;
+    for session_manager in session_managers(enumerator, flow)? {
+        let session_enumerator = session_manager.GetSessionEnumerator()?;
+        for i in 0..session_enumerator.GetCount()? {
+            if let Ok(session_control) = session_enumerator.GetSession(i) {
+                if let Ok(sc2) = session_control.cast::<IAudioSessionControl2>() {
+                    if sc2.GetProcessId()? == target_pid {
+                        if let Ok(sv) = session_control.cast::<ISimpleAudioVolume>() {
+                            sv.SetMasterVolume(vol, std::ptr::null())?;
+                            found = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(found)
+}
+
+unsafe fn internal_set_app_mute(
+    enumerator: &IMMDeviceEnumerator,
+    flow: EDataFlow,
+    target_pid: u32,
+    mute: bool,
+) -> Result<bool> {
+    let mut found = false;
+    for session_manager in session_managers(enumerator, flow)? {
+        let session_enumerator = session_manager.GetSessionEnumerator()?;
+        for i in 0..session_enumerator.GetCount()? {
+            if let Ok(session_control) = session_enumerator.GetSession(i) {
+                if let Ok(sc2) = session_control.cast::<IAudioSessionControl2>() {
+                    if sc2.GetProcessId()? == target_pid {
+                        if let Ok(sv) = session_control.cast::<ISimpleAudioVolume>() {
+                            sv.SetMute(mute, std::ptr::null())?;
+                            found = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(found)
+}
+
+pub unsafe fn get_audio_endpoints(
+    enumerator: &IMMDeviceEnumerator,
+    data_flow: EDataFlow,
+) -> Result<Vec<AudioDevice>> {
+    let mut devices = Vec::new();
+    let collection = enumerator.EnumAudioEndpoints(data_flow, DEVICE_STATE_ACTIVE)?;
+    let count = collection.GetCount()?;
+    for i in 0..count {
+        let device = collection.Item(i)?;
+        if let Ok(id_ptr) = device.GetId() {
+            let id = id_ptr.to_string().unwrap_or_default();
+            CoTaskMemFree(Some(id_ptr.as_ptr() as *const c_void));
+            let props = device.OpenPropertyStore(STGM_READ)?;
+            let mut name = String::new();
+            if let Ok(mut val) = props.GetValue(&PKEY_Device_FriendlyName) {
+                if !val.Anonymous.Anonymous.Anonymous.pwszVal.is_null() {
+                    name = val
+                        .Anonymous
+                        .Anonymous
+                        .Anonymous
+                        .pwszVal
+                        .to_string()
+                        .unwrap_or_default();
+                }
+                let _ = PropVariantClear(&mut val as *mut _);
+            }
+            devices.push(AudioDevice {
+                id: id.clone(),
+                name: if name.is_empty() {
+                    id.clone()
+                } else {
+                    name.clone()
+                },
+                is_default: false,
+                default_roles: Vec::new(),
+            });
+        }
+    }
+    for role in DeviceRole::ALL {
+        if let Ok(def_dev) = enumerator.GetDefaultAudioEndpoint(data_flow, switcher::erole(role)) {
+            if let Ok(def_id) = def_dev.GetId() {
+                let s = def_id.to_string().unwrap_or_default();
+                for d in &mut devices {
+                    if d.id == s {
+                        d.default_roles.push(role);
+                        d.is_default |= role == DeviceRole::Multimedia;
+                    }
+                }
+                CoTaskMemFree(Some(def_id.as_ptr() as *const c_void));
+            }
+        }
+    }
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(devices)
+}
+
+/// Creation time as a FILETIME tick count. A process that has exited but
+/// is still held open somewhere counts as gone.
+fn process_start_time(pid: u32) -> Option<u64> {
+    const STILL_ACTIVE: u32 = 259;
+    unsafe {
+        let handle = OpenProcess(
+            PROCESS_QUERY_LIMITED_INFORMATION,
+            windows::Win32::Foundation::FALSE,
+            pid,
+        )
+        .ok()?;
+        let mut exit_code = 0;
+        let mut times = [windows::Win32::Foundation::FILETIME::default(); 4];
+        let [created, exited, kernel, user] = &mut times;
+        let ok = GetExitCodeProcess(handle, &mut exit_code).is_ok()
+            && exit_code == STILL_ACTIVE
+            && GetProcessTimes(handle, created, exited, kernel, user).is_ok();
+        let _ = CloseHandle(handle);
+        ok.then(|| (times[0].dwHighDateTime as u64) << 32 | times[0].dwLowDateTime as u64)
+    }
+}
+
+/// Parent pid as recorded at creation. Windows does not clear it when the
+/// parent exits, so it may since have been reused by another process.
+fn parent_pid(pid: u32) -> Option<u32> {
+    unsafe {
+        let handle = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0).ok()?;
+        let mut pe = PROCESSENTRY32W {
+            dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+            ..Default::default()
+        };
+        let mut parent = None;
+        if Process32FirstW(handle, &mut pe).is_ok() {
+            loop {
+                if pe.th32ProcessID == pid {
+                    parent = Some(pe.th32ParentProcessID);
+                    break;
+                }
+                if Process32NextW(handle, &mut pe).is_err() {
+                    break;
+                }
+            }
+        }
+        let _ = CloseHandle(handle);
+        parent
+    }
+}
+
+/// The version resource's description, e.g. "Google Chrome" for
+/// `chrome.exe`, else the image name.
+fn display_name(path: &str, image_name: String) -> String {
+    if path.is_empty() {
+        return image_name;
+    }
+    pe::Resources::load(Path::new(path))
+        .and_then(|r| r.version_info())
+        .and_then(|v| v.display_name().map(str::to_string))
+        .unwrap_or(image_name)
+}
+
+fn describe_processes(missing_pids: &[u32]) -> HashMap<u32, ProcessInfo> {
+    let mut found_names = HashMap::new();
+    unsafe {
+        if let Ok(handle) = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) {
+            let mut pe = PROCESSENTRY32W {
+                dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+                ..Default::default()
+            };
+            if Process32FirstW(handle, &mut pe).is_ok() {
+                loop {
+                    if missing_pids.contains(&pe.th32ProcessID) {
+                        let name = String::from_utf16_lossy(&pe.szExeFile)
+                            .trim_matches('\0')
+                            .to_string();
+                        let mut path = String::new();
+                        if let Ok(h_proc) = OpenProcess(
+                            PROCESS_QUERY_LIMITED_INFORMATION,
+                            windows::Win32::Foundation::FALSE,
+                            pe.th32ProcessID,
+                        ) {
+                            let mut buffer = [0u16; 1024];
+                            let mut size = buffer.len() as u32;
+                            if QueryFullProcessImageNameW(
+                                h_proc,
+                                PROCESS_NAME_WIN32,
+                                PWSTR(buffer.as_mut_ptr()),
+                                &mut size,
+                            )
+                            .is_ok()
+                            {
+                                path = String::from_utf16_lossy(&buffer[..size as usize]);
+                            }
+                            let _ = CloseHandle(h_proc);
+                        }
+                        found_names.insert(
+                            pe.th32ProcessID,
+                            ProcessInfo {
+                                name: display_name(&path, name),
+                                icon_path: String::new(),
+                                exe_path: path,
+                            },
+                        );
+                    }
+                    if Process32NextW(handle, &mut pe).is_err() {
+                        break;
+                    }
+                }
+            }
+            let _ = CloseHandle(handle);
+        }
+    }
+    for &pid in missing_pids {
+        if found_names.contains_key(&pid) {
+            continue;
+        }
+        unsafe {
+            if let Ok(handle) = OpenProcess(
+                PROCESS_QUERY_INFORMATION | PROCESS_VM_READ,
+                windows::Win32::Foundation::FALSE,
+                pid,
+            ) {
+                let mut buf = [0u16; 1024];
+                let len = GetModuleBaseNameW(handle, None, &mut buf);
+                if len > 0 {
+                    let name = String::from_utf16_lossy(&buf[..len as usize]);
+                    let mut path = String::new();
+                    if let Ok(h_proc) = OpenProcess(
+                        PROCESS_QUERY_LIMITED_INFORMATION,
+                        windows::Win32::Foundation::FALSE,
+                        pid,
+                    ) {
+                        let mut buffer = [0u16; 1024];
+                        let mut size = buffer.len() as u32;
+                        if QueryFullProcessImageNameW(
+                            h_proc,
+                            PROCESS_NAME_WIN32,
+                            PWSTR(buffer.as_mut_ptr()),
+                            &mut size,
+                        )
+                        .is_ok()
+                        {
+                            path = String::from_utf16_lossy(&buffer[..size as usize]);
+                        }
+                        let _ = CloseHandle(h_proc);
+                    }
+                    found_names.insert(
+                        pid,
+                        ProcessInfo {
+                            name: display_name(&path, name),
+                            icon_path: String::new(),
+                            exe_path: path,
+                        },
+                    );
+                }
+                let _ = CloseHandle(handle);
+            }
+        }
+    }
+    found_names
+}
+
+/// The file's icon at `index` (as `ExtractIconEx` counts) and `size`
+/// pixels, or whatever the fallbacks produce when no icon of that size can
+/// be extracted.
+fn icon_image(path: &str, index: i32, size: u32) -> Option<IconImage> {
+    unsafe {
+        let path_v16: Vec<u16> = path.encode_utf16().chain(std::iter::once(0)).collect();
+        let mut h_icons = [HICON::default(); 1];
+        let mut path_fixed = [0u16; 260];
+        let copy_len = path_v16.len().min(260);
+        path_fixed[..copy_len].copy_from_slice(&path_v16[..copy_len]);
+        let size = size as i32;
+        let count =
+            PrivateExtractIconsW(&path_fixed, index, size, size, Some(&mut h_icons), None, 0);
+        if count == 0 || h_icons[0].0 == 0 {
+            // Fallback 1: ExtractIconExW
+            let mut h_large = [HICON::default(); 1];
+            if ExtractIconExW(
+                windows::core::PCWSTR(path_v16.as_ptr()),
+                index,
+                Some(h_large.as_mut_ptr()),
+                None,
+                1,
+            ) > 0
+            {
+                h_icons[0] = h_large[0];
+            }
+        }
+
+        if h_icons[0].0 == 0 {
+            // Fallback 2: SHGetFileInfoW (Shell Icon - most robust for system apps)
+            let mut shfi = SHFILEINFOW::default();
+            if SHGetFileInfoW(
+                windows::core::PCWSTR(path_v16.as_ptr()),
+                windows::Win32::Storage::FileSystem::FILE_FLAGS_AND_ATTRIBUTES(0),
+                Some(&mut shfi),
+                std::mem::size_of::<SHFILEINFOW>() as u32,
+                SHGFI_ICON | SHGFI_LARGEICON,
+            ) != 0
+            {
+                h_icons[0] = shfi.hIcon;
+            }
+        }
+
+        let mut image = None;
+        if h_icons[0].0 != 0 {
+            let h_icon = h_icons[0];
+            let mut icon_info = ICONINFO::default();
+            if GetIconInfo(h_icon, &mut icon_info).is_ok() {
+                let h_bm = if icon_info.hbmColor.0 != 0 {
+                    icon_info.hbmColor
+                } else {
+                    icon_info.hbmMask
+                };
+                let mut bm = BITMAP::default();
+                if GetObjectW(
+                    h_bm,
+                    std::mem::size_of::<BITMAP>() as i32,
+                    Some(&mut bm as *mut _ as *mut _),
+                ) > 0
+                {
+                    let width = bm.bmWidth;
+                    let height = bm.bmHeight;
+                    let hdc_screen = windows::Win32::Graphics::Gdi::GetDC(None);
+                    let hdc_mem = CreateCompatibleDC(hdc_screen);
+                    let old_bm = SelectObject(hdc_mem, h_bm);
+                    let mut bmi = BITMAPINFO {
+                        bmiHeader: BITMAPINFOHEADER {
+                            biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
+                            biWidth: width,
+                            biHeight: -height,
+                            biPlanes: 1,
+                            biBitCount: 32,
+                            biCompression: 0,
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    };
+                    let mut buffer: Vec<u8> = vec![0; (width * height * 4) as usize];
+                    let ret = GetDIBits(
+                        hdc_mem,
+                        h_bm,
+                        0,
+                        height as u32,
+                        Some(buffer.as_mut_ptr() as *mut _),
+                        &mut bmi,
+                        DIB_RGB_COLORS,
+                    );
+                    if !old_bm.is_invalid() {
+                        SelectObject(hdc_mem, old_bm);
+                    }
+                    let _ = DeleteDC(hdc_mem);
+                    let _ = windows::Win32::Graphics::Gdi::ReleaseDC(None, hdc_screen);
+                    if ret > 0 {
+                        for chunk in buffer.chunks_exact_mut(4) {
+                            chunk.swap(0, 2);
+                        }
+                        image = Some(IconImage {
+                            width: width as u32,
+                            height: height as u32,
+                            rgba: buffer,
+                        });
+                    }
+                }
+                if icon_info.hbmColor.0 != 0 {
+                    let _ = DeleteObject(icon_info.hbmColor);
+                }
+                if icon_info.hbmMask.0 != 0 {
+                    let _ = DeleteObject(icon_info.hbmMask);
+                }
+            }
+            let _ = DestroyIcon(h_icon);
+        }
+        image
+    }
+}
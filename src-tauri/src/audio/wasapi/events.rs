@@ -0,0 +1,176 @@
+//! COM callbacks that turn WASAPI change notifications into [`BackendEvent`]s.
+//! The system invokes them on its own threads; they only post to the worker.
+
+use super::super::{BackendEvent, Notifier};
+use super::sessions::SessionTable;
+use std::ffi::c_void;
+use windows::core::{implement, Result, GUID, PCWSTR};
+use windows::Win32::Foundation::BOOL;
+use windows::Win32::Media::Audio::Endpoints::{
+    IAudioEndpointVolume, IAudioEndpointVolumeCallback, IAudioEndpointVolumeCallback_Impl,
+};
+use windows::Win32::Media::Audio::*;
+use windows::Win32::System::Com::CoTaskMemFree;
+use windows::Win32::UI::Shell::PropertiesSystem::PROPERTYKEY;
+
+#[implement(IAudioEndpointVolumeCallback)]
+struct EndpointVolumeWatcher {
+    notifier: Notifier,
+    event: BackendEvent,
+}
+
+impl IAudioEndpointVolumeCallback_Impl for EndpointVolumeWatcher {
+    fn OnNotify(&self, _data: *mut AUDIO_VOLUME_NOTIFICATION_DATA) -> Result<()> {
+        self.notifier.notify(self.event);
+        Ok(())
+    }
+}
+
+#[implement(IMMNotificationClient)]
+struct DeviceWatcher {
+    notifier: Notifier,
+}
+
+impl IMMNotificationClient_Impl for DeviceWatcher {
+    fn OnDeviceStateChanged(&self, _id: &PCWSTR, _state: u32) -> Result<()> {
+        self.notifier.notify(BackendEvent::Devices);
+        Ok(())
+    }
+
+    fn OnDeviceAdded(&self, _id: &PCWSTR) -> Result<()> {
+        self.notifier.notify(BackendEvent::Devices);
+        Ok(())
+    }
+
+    fn OnDeviceRemoved(&self, _id: &PCWSTR) -> Result<()> {
+        self.notifier.notify(BackendEvent::Devices);
+        Ok(())
+    }
+
+    fn OnDefaultDeviceChanged(&self, _flow: EDataFlow, _role: ERole, _id: &PCWSTR) -> Result<()> {
+        self.notifier.notify(BackendEvent::Devices);
+        Ok(())
+    }
+
+    // Fires constantly (e.g. on every volume change); nothing we show depends on it.
+    fn OnPropertyValueChanged(&self, _id: &PCWSTR, _key: &PROPERTYKEY) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Endpoint hot-plug and default-device registration. Unlike
+/// [`Subscriptions`] it lives on the enumerator, so it survives device changes.
+pub struct DeviceWatch {
+    enumerator: IMMDeviceEnumerator,
+    client: IMMNotificationClient,
+}
+
+impl DeviceWatch {
+    pub unsafe fn new(enumerator: &IMMDeviceEnumerator, notifier: Notifier) -> Result<Self> {
+        let client: IMMNotificationClient = DeviceWatcher { notifier }.into();
+        enumerator.RegisterEndpointNotificationCallback(&client)?;
+        Ok(Self {
+            enumerator: enumerator.clone(),
+            client,
+        })
+    }
+}
+
+impl Drop for DeviceWatch {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self
+                .enumerator
+                .UnregisterEndpointNotificationCallback(&self.client);
+        }
+    }
+}
+
+/// Everything registered for change notifications. Dropping it unregisters
+/// all callbacks, so it is rebuilt whenever the default endpoints change.
+pub struct Subscriptions {
+    notifier: Notifier,
+    endpoints: Vec<(IAudioEndpointVolume, IAudioEndpointVolumeCallback)>,
+    sessions: Vec<(IAudioSessionManager2, IAudioSessionNotification)>,
+    /// Fed by the session managers' callbacks; dropped after them.
+    pub table: SessionTable,
+    /// Like `table`, for the sessions of capture endpoints.
+    pub capture: SessionTable,
+}
+
+impl Subscriptions {
+    pub fn new(notifier: Notifier) -> Self {
+        Self {
+            table: SessionTable::new(notifier.clone(), BackendEvent::Sessions),
+            capture: SessionTable::new(notifier.clone(), BackendEvent::CaptureSessions),
+            notifier,
+            endpoints: Vec::new(),
+            sessions: Vec::new(),
+        }
+    }
+
+    pub unsafe fn watch_endpoint(
+        &mut self,
+        volume: IAudioEndpointVolume,
+        event: BackendEvent,
+    ) -> Result<()> {
+        let cb: IAudioEndpointVolumeCallback = EndpointVolumeWatcher {
+            notifier: self.notifier.clone(),
+            event,
+        }
+        .into();
+        volume.RegisterControlChangeNotify(&cb)?;
+        self.endpoints.push((volume, cb));
+        Ok(())
+    }
+
+    /// Register for new sessions on `manager`, an endpoint's of `flow`, then
+    /// add the ones it already has to the matching table.
+    pub unsafe fn watch_session_manager(
+        &mut self,
+        manager: IAudioSessionManager2,
+        flow: EDataFlow,
+    ) -> Result<()> {
+        let table = if flow == eCapture {
+            &mut self.capture
+        } else {
+            &mut self.table
+        };
+        // Sessions created before the first enumeration are not reported.
+        let _ = manager.GetSessionEnumerator()?;
+        let cb = table.creation_watcher();
+        manager.RegisterSessionNotification(&cb)?;
+        // Enumerate again so sessions created in between are not missed;
+        // the table ignores ones it already has.
+        let sessions = manager.GetSessionEnumerator()?;
+        for i in 0..sessions.GetCount()? {
+            if let Ok(control) = sessions.GetSession(i) {
+                let _ = table.insert(&control);
+            }
+        }
+        self.sessions.push((manager, cb));
+        Ok(())
+    }
+}
+
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        unsafe {
+            for (volume, cb) in &self.endpoints {
+                let _ = volume.UnregisterControlChangeNotify(cb);
+            }
+            for (manager, cb) in &self.sessions {
+                let _ = manager.UnregisterSessionNotification(cb);
+            }
+        }
+    }
+}
+
+pub unsafe fn instance_id(control: &IAudioSessionControl) -> Option<String> {
+    use windows::core::ComInterface;
+    let c2 = control.cast::<IAudioSessionControl2>().ok()?;
+    let id = c2.GetSessionInstanceIdentifier().ok()?;
+    let s = id.to_string().unwrap_or_default();
+    CoTaskMemFree(Some(id.as_ptr() as *const c_void));
+    Some(s)
+}
@@ -0,0 +1,120 @@
+//! Per-process default endpoints through the undocumented audio policy
+//! factory that the Settings app ("App volume and device preferences") uses.
+//! The interface below is the Windows 10 21H2+ / Windows 11 layout.
+
+use std::ffi::c_void;
+use windows::core::{interface, IUnknown, IUnknown_Vtbl, Result, HRESULT, HSTRING};
+use windows::Win32::Media::Audio::{eCommunications, eConsole, eMultimedia, EDataFlow, ERole};
+use windows::Win32::System::WinRT::RoGetActivationFactory;
+
+const MMDEVAPI_PREFIX: &str = r"\\?\SWD#MMDEVAPI#";
+/// DEVINTERFACE_AUDIO_RENDER
+const RENDER_SUFFIX: &str = "#{e6327cad-dcec-4949-ae8a-991e976a79d2}";
+/// DEVINTERFACE_AUDIO_CAPTURE
+const CAPTURE_SUFFIX: &str = "#{2eef81be-33fa-4800-9670-1cd474972c3f}";
+
+#[interface("ab3d4648-e242-459f-b02f-541c70306324")]
+unsafe trait IAudioPolicyConfigFactory: IUnknown {
+    // IInspectable
+    fn GetIids(&self) -> HRESULT;
+    fn GetRuntimeClassName(&self) -> HRESULT;
+    fn GetTrustLevel(&self) -> HRESULT;
+    // Volume groups, ringer and chat-app slots we never call.
+    fn add_CtxVolumeChange(&self) -> HRESULT;
+    fn remove_CtxVolumeChanged(&self) -> HRESULT;
+    fn add_RingerVibrateStateChanged(&self) -> HRESULT;
+    fn remove_RingerVibrateStateChange(&self) -> HRESULT;
+    fn SetVolumeGroupGainForId(&self) -> HRESULT;
+    fn GetVolumeGroupGainForId(&self) -> HRESULT;
+    fn GetActiveVolumeGroupForEndpointId(&self) -> HRESULT;
+    fn GetVolumeGroupsForEndpoint(&self) -> HRESULT;
+    fn GetCurrentVolumeContext(&self) -> HRESULT;
+    fn SetVolumeGroupMuteForId(&self) -> HRESULT;
+    fn GetVolumeGroupMuteForId(&self) -> HRESULT;
+    fn SetRingerVibrateState(&self) -> HRESULT;
+    fn GetRingerVibrateState(&self) -> HRESULT;
+    fn SetPreferredChatApplication(&self) -> HRESULT;
+    fn ResetPreferredChatApplication(&self) -> HRESULT;
+    fn GetPreferredChatApplication(&self) -> HRESULT;
+    fn GetCurrentChatApplications(&self) -> HRESULT;
+    fn add_ChatContextChanged(&self) -> HRESULT;
+    fn remove_ChatContextChanged(&self) -> HRESULT;
+    /// `device_id` is an HSTRING; null resets the process to the default.
+    fn SetPersistedDefaultAudioEndpoint(
+        &self,
+        pid: u32,
+        flow: EDataFlow,
+        role: ERole,
+        device_id: *mut c_void,
+    ) -> HRESULT;
+    fn GetPersistedDefaultAudioEndpoint(
+        &self,
+        pid: u32,
+        flow: EDataFlow,
+        role: ERole,
+        device_id: *mut *mut c_void,
+    ) -> HRESULT;
+    fn ClearAllPersistedApplicationDefaultEndpoints(&self) -> HRESULT;
+}
+
+pub struct AppRouting {
+    factory: IAudioPolicyConfigFactory,
+}
+
+impl AppRouting {
+    pub fn new() -> Result<Self> {
+        let class = HSTRING::from("Windows.Media.Internal.AudioPolicyConfig");
+        let factory = unsafe { RoGetActivationFactory(&class)? };
+        Ok(Self { factory })
+    }
+
+    /// Point `pid` at the endpoint `device_id` (an MMDevice id as returned by
+    /// the enumerator), or back at the system default with `None`.
+    pub fn set(&self, pid: u32, flow: EDataFlow, device_id: Option<&str>) -> Result<()> {
+        let suffix = suffix(flow);
+        let path =
+            device_id.map(|id| HSTRING::from(format!("{}{}{}", MMDEVAPI_PREFIX, id, suffix)));
+        // An HSTRING is a single (possibly null) pointer.
+        let raw = path.as_ref().map_or(std::ptr::null_mut(), |h| unsafe {
+            std::mem::transmute_copy::<HSTRING, *mut c_void>(h)
+        });
+        for role in [eConsole, eMultimedia, eCommunications] {
+            unsafe {
+                self.factory
+                    .SetPersistedDefaultAudioEndpoint(pid, flow, role, raw)
+                    .ok()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The endpoint `pid` is routed to, or `None` if it follows the default.
+    pub fn get(&self, pid: u32, flow: EDataFlow) -> Option<String> {
+        let mut raw: *mut c_void = std::ptr::null_mut();
+        unsafe {
+            self.factory
+                .GetPersistedDefaultAudioEndpoint(pid, flow, eMultimedia, &mut raw)
+                .ok()
+                .ok()?;
+            if raw.is_null() {
+                return None;
+            }
+            // Take ownership so the string is released.
+            let path: HSTRING = std::mem::transmute(raw);
+            let path = path.to_string_lossy();
+            let id = path
+                .strip_prefix(MMDEVAPI_PREFIX)
+                .and_then(|rest| rest.strip_suffix(suffix(flow)))
+                .unwrap_or(&path);
+            (!id.is_empty()).then(|| id.to_string())
+        }
+    }
+}
+
+fn suffix(flow: EDataFlow) -> &'static str {
+    if flow == windows::Win32::Media::Audio::eCapture {
+        CAPTURE_SUFFIX
+    } else {
+        RENDER_SUFFIX
+    }
+}
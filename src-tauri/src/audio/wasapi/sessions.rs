@@ -0,0 +1,458 @@
+//! Live table of playback sessions keyed by session instance id. It is
+//! filled by one enumeration when notifications are set up and kept current
+//! by them afterwards, so listing the mixer or setting one app's volume is a
+//! lookup rather than a walk over every endpoint's session enumerator.
+
+use super::super::{AppSession, BackendEvent, Notifier};
+use super::events::instance_id;
+use std::collections::{HashMap, HashSet};
+use std::ffi::c_void;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use windows::core::{implement, AgileReference, ComInterface, Result, GUID, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{BOOL, S_OK};
+use windows::Win32::Media::Audio::Endpoints::IAudioMeterInformation;
+use windows::Win32::Media::Audio::*;
+use windows::Win32::System::Com::CoTaskMemFree;
+use windows::Win32::UI::Shell::SHLoadIndirectString;
+
+/// Name, icon and grouping a session declares for itself.
+#[derive(Clone, Default)]
+pub struct Label {
+    pub display_name: Option<String>,
+    pub icon_path: Option<String>,
+    pub grouping: Option<String>,
+}
+
+impl Label {
+    pub unsafe fn read(control: &IAudioSessionControl) -> Self {
+        Self {
+            display_name: control
+                .GetDisplayName()
+                .ok()
+                .and_then(|s| display_name(&take(s))),
+            icon_path: control.GetIconPath().ok().and_then(|s| icon_path(&take(s))),
+            grouping: control.GetGroupingParam().ok().and_then(grouping),
+        }
+    }
+}
+
+/// Copy and free a string the session allocated.
+unsafe fn take(s: PWSTR) -> String {
+    let text = s.to_string().unwrap_or_default();
+    CoTaskMemFree(Some(s.as_ptr() as *const c_void));
+    text
+}
+
+/// The name to show, with `@<file>,-<id>` string resources resolved.
+/// `None` when the session leaves it empty.
+fn display_name(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    if !raw.starts_with('@') {
+        return (!raw.is_empty()).then(|| raw.to_string());
+    }
+    let source: Vec<u16> = raw.encode_utf16().chain(std::iter::once(0)).collect();
+    let mut buf = [0u16; 512];
+    unsafe { SHLoadIndirectString(PCWSTR(source.as_ptr()), &mut buf, None).ok()? };
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    let name = String::from_utf16_lossy(&buf[..len]);
+    (!name.is_empty()).then_some(name)
+}
+
+/// `@%SystemRoot%\System32\AudioSrv.Dll,-203` as a plain `<file>,<index>`
+/// icon source.
+fn icon_path(raw: &str) -> Option<String> {
+    let raw = raw.trim().trim_start_matches('@');
+    (!raw.is_empty()).then(|| expand_env(raw))
+}
+
+/// Replace `%NAME%` with the variable's value; unknown names stay as they
+/// are.
+fn expand_env(s: &str) -> String {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('%') {
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('%') else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        match std::env::var(&after[..end]) {
+            Ok(value) => out.push_str(&value),
+            Err(_) => out.push_str(&rest[start..start + end + 2]),
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// `None` for `GUID_NULL`, which sessions use when they do not group.
+fn grouping(param: GUID) -> Option<String> {
+    (param != GUID::zeroed()).then(|| format!("{:?}", param))
+}
+
+/// What the notification threads leave for the worker.
+#[derive(Default)]
+struct Inbox {
+    /// Sessions in the table. Callbacks can still arrive for a session after
+    /// it was removed; their updates are dropped rather than kept forever.
+    tracked: HashSet<String>,
+    /// Volume and mute as last reported by each session.
+    levels: HashMap<String, (f32, bool)>,
+    /// Name, icon and grouping as last reported by each session.
+    labels: HashMap<String, Label>,
+    /// Whether each session was last reported active.
+    active: HashMap<String, bool>,
+    /// Sessions announced by `OnSessionCreated`, not yet in the table.
+    created: Vec<AgileReference<IAudioSessionControl>>,
+    /// Sessions that expired or were disconnected.
+    ended: Vec<String>,
+}
+
+/// Stop tracking `id` and drop what its callbacks reported.
+fn forget(inbox: &mut Inbox, id: &str) {
+    inbox.tracked.remove(id);
+    inbox.levels.remove(id);
+    inbox.labels.remove(id);
+    inbox.active.remove(id);
+}
+
+fn lock(inbox: &Mutex<Inbox>) -> MutexGuard<'_, Inbox> {
+    inbox.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[implement(IAudioSessionNotification)]
+struct SessionCreatedWatcher {
+    notifier: Notifier,
+    event: BackendEvent,
+    inbox: Arc<Mutex<Inbox>>,
+}
+
+impl IAudioSessionNotification_Impl for SessionCreatedWatcher {
+    fn OnSessionCreated(&self, session: Option<&IAudioSessionControl>) -> Result<()> {
+        // The control belongs to this notification thread; the worker
+        // resolves the agile reference to a proxy it may use.
+        if let Some(session) = session {
+            lock(&self.inbox)
+                .created
+                .push(AgileReference::new(session)?);
+        }
+        self.notifier.notify(self.event);
+        Ok(())
+    }
+}
+
+#[implement(IAudioSessionEvents)]
+struct SessionWatcher {
+    id: String,
+    notifier: Notifier,
+    event: BackendEvent,
+    inbox: Arc<Mutex<Inbox>>,
+}
+
+impl SessionWatcher {
+    fn ended(&self) {
+        lock(&self.inbox).ended.push(self.id.clone());
+        self.notifier.notify(self.event);
+    }
+
+    /// Apply a reported change, unless the session has left the table.
+    fn update(&self, f: impl FnOnce(&mut Inbox, String)) {
+        let mut inbox = lock(&self.inbox);
+        if !inbox.tracked.contains(&self.id) {
+            return;
+        }
+        f(&mut inbox, self.id.clone());
+        drop(inbox);
+        self.notifier.notify(self.event);
+    }
+
+    fn relabel(&self, f: impl FnOnce(&mut Label)) {
+        self.update(|inbox, id| f(inbox.labels.entry(id).or_default()));
+    }
+}
+
+impl IAudioSessionEvents_Impl for SessionWatcher {
+    fn OnDisplayNameChanged(&self, name: &PCWSTR, _ctx: *const GUID) -> Result<()> {
+        let name = unsafe { name.to_string() }.unwrap_or_default();
+        self.relabel(|label| label.display_name = display_name(&name));
+        Ok(())
+    }
+
+    fn OnIconPathChanged(&self, path: &PCWSTR, _ctx: *const GUID) -> Result<()> {
+        let path = unsafe { path.to_string() }.unwrap_or_default();
+        self.relabel(|label| label.icon_path = icon_path(&path));
+        Ok(())
+    }
+
+    fn OnSimpleVolumeChanged(&self, vol: f32, mute: BOOL, _ctx: *const GUID) -> Result<()> {
+        self.update(|inbox, id| {
+            inbox.levels.insert(id, (vol, mute.as_bool()));
+        });
+        Ok(())
+    }
+
+    fn OnChannelVolumeChanged(
+        &self,
+        _count: u32,
+        _volumes: *const f32,
+        _changed: u32,
+        _ctx: *const GUID,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn OnGroupingParamChanged(&self, param: *const GUID, _ctx: *const GUID) -> Result<()> {
+        let param = unsafe { param.as_ref() }.copied();
+        self.relabel(|label| label.grouping = param.and_then(grouping));
+        Ok(())
+    }
+
+    fn OnStateChanged(&self, state: AudioSessionState) -> Result<()> {
+        if state == AudioSessionStateExpired {
+            self.ended();
+        } else {
+            self.update(|inbox, id| {
+                inbox.active.insert(id, state == AudioSessionStateActive);
+            });
+        }
+        Ok(())
+    }
+
+    fn OnSessionDisconnected(&self, _reason: AudioSessionDisconnectReason) -> Result<()> {
+        self.ended();
+        Ok(())
+    }
+}
+
+struct Entry {
+    pid: u32,
+    is_system: bool,
+    control: IAudioSessionControl,
+    volume: ISimpleAudioVolume,
+    meter: Option<IAudioMeterInformation>,
+    events: IAudioSessionEvents,
+}
+
+/// Lives on the worker thread; only [`Inbox`] is shared with callbacks.
+pub struct SessionTable {
+    notifier: Notifier,
+    /// Posted for every change, telling playback and capture tables apart.
+    event: BackendEvent,
+    inbox: Arc<Mutex<Inbox>>,
+    entries: HashMap<String, Entry>,
+    by_pid: HashMap<u32, Vec<String>>,
+}
+
+impl SessionTable {
+    pub fn new(notifier: Notifier, event: BackendEvent) -> Self {
+        Self {
+            notifier,
+            event,
+            inbox: Arc::default(),
+            entries: HashMap::new(),
+            by_pid: HashMap::new(),
+        }
+    }
+
+    /// Callback to register on a session manager, feeding its new sessions
+    /// into this table.
+    pub fn creation_watcher(&self) -> IAudioSessionNotification {
+        SessionCreatedWatcher {
+            notifier: self.notifier.clone(),
+            event: self.event,
+            inbox: self.inbox.clone(),
+        }
+        .into()
+    }
+
+    /// Start tracking a session. Expired sessions, sessions already in the
+    /// table and ones without a process, other than system sounds, are
+    /// ignored.
+    pub unsafe fn insert(&mut self, control: &IAudioSessionControl) -> Result<()> {
+        let Some(id) = instance_id(control) else {
+            return Ok(());
+        };
+        let state = control.GetState()?;
+        if self.entries.contains_key(&id) || state == AudioSessionStateExpired {
+            return Ok(());
+        }
+        let control2 = control.cast::<IAudioSessionControl2>()?;
+        let pid = control2.GetProcessId()?;
+        let is_system = control2.IsSystemSoundsSession() == S_OK;
+        if pid == 0 && !is_system {
+            return Ok(());
+        }
+        let volume: ISimpleAudioVolume = control.cast()?;
+        let events: IAudioSessionEvents = SessionWatcher {
+            id: id.clone(),
+            notifier: self.notifier.clone(),
+            event: self.event,
+            inbox: self.inbox.clone(),
+        }
+        .into();
+        // Track and register before reading so no change falls in between; a
+        // level reported meanwhile is newer than the one read here.
+        lock(&self.inbox).tracked.insert(id.clone());
+        let levels = control
+            .RegisterAudioSessionNotification(&events)
+            .and_then(|()| Ok((volume.GetMasterVolume()?, volume.GetMute()?.as_bool())));
+        let levels = match levels {
+            Ok(levels) => levels,
+            Err(e) => {
+                let _ = control.UnregisterAudioSessionNotification(&events);
+                forget(&mut lock(&self.inbox), &id);
+                return Err(e);
+            }
+        };
+        let label = Label::read(control);
+        {
+            let mut inbox = lock(&self.inbox);
+            inbox.levels.entry(id.clone()).or_insert(levels);
+            inbox.labels.entry(id.clone()).or_insert(label);
+            inbox
+                .active
+                .entry(id.clone())
+                .or_insert(state == AudioSessionStateActive);
+        }
+        self.by_pid.entry(pid).or_default().push(id.clone());
+        self.entries.insert(
+            id,
+            Entry {
+                pid,
+                is_system,
+                control: control.clone(),
+                meter: control.cast().ok(),
+                volume,
+                events,
+            },
+        );
+        Ok(())
+    }
+
+    fn remove(&mut self, id: &str) {
+        let Some(entry) = self.entries.remove(id) else {
+            return;
+        };
+        unsafe {
+            let _ = entry
+                .control
+                .UnregisterAudioSessionNotification(&entry.events);
+        }
+        if let Some(ids) = self.by_pid.get_mut(&entry.pid) {
+            ids.retain(|i| i != id);
+            if ids.is_empty() {
+                self.by_pid.remove(&entry.pid);
+            }
+        }
+        forget(&mut lock(&self.inbox), id);
+    }
+
+    /// Apply what the callbacks queued since the last call.
+    unsafe fn sync(&mut self) {
+        let (created, ended) = {
+            let mut inbox = lock(&self.inbox);
+            (
+                std::mem::take(&mut inbox.created),
+                std::mem::take(&mut inbox.ended),
+            )
+        };
+        for id in ended {
+            self.remove(&id);
+        }
+        for reference in created {
+            if let Ok(control) = reference.resolve() {
+                let _ = self.insert(&control);
+            }
+        }
+    }
+
+    pub unsafe fn sessions(&mut self) -> Vec<AppSession> {
+        self.sync();
+        let inbox = lock(&self.inbox);
+        self.entries
+            .iter()
+            .map(|(id, entry)| {
+                let (volume, is_muted) = inbox.levels.get(id).copied().unwrap_or((1.0, false));
+                let label = inbox.labels.get(id).cloned().unwrap_or_default();
+                AppSession {
+                    id: id.clone(),
+                    pid: entry.pid,
+                    volume,
+                    is_muted,
+                    display_name: label.display_name,
+                    icon_path: label.icon_path,
+                    grouping: label.grouping,
+                    is_system: entry.is_system,
+                    is_active: inbox.active.get(id).copied().unwrap_or(false),
+                }
+            })
+            .collect()
+    }
+
+    /// Simple volumes of every session of `pid` by session id, empty when
+    /// it has none. The inbox is not held while the caller talks to them,
+    /// since a write notifies the callbacks that take it.
+    unsafe fn volumes_of(&mut self, pid: u32) -> Vec<(String, ISimpleAudioVolume)> {
+        self.sync();
+        self.by_pid
+            .get(&pid)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| Some((id.clone(), self.entries.get(id)?.volume.clone())))
+            .collect()
+    }
+
+    /// Record a level this side just set, so listings show it without
+    /// waiting for the session's own callback.
+    fn set_levels(&self, id: &str, f: impl FnOnce(&mut (f32, bool))) {
+        if let Some(levels) = lock(&self.inbox).levels.get_mut(id) {
+            f(levels);
+        }
+    }
+
+    /// Whether `pid` had any session to set.
+    pub unsafe fn set_volume(&mut self, pid: u32, vol: f32) -> Result<bool> {
+        let volumes = self.volumes_of(pid);
+        for (id, v) in &volumes {
+            v.SetMasterVolume(vol, std::ptr::null())?;
+            self.set_levels(id, |levels| levels.0 = vol);
+        }
+        Ok(!volumes.is_empty())
+    }
+
+    pub unsafe fn set_mute(&mut self, pid: u32, mute: bool) -> Result<bool> {
+        let volumes = self.volumes_of(pid);
+        for (id, v) in &volumes {
+            v.SetMute(mute, std::ptr::null())?;
+            self.set_levels(id, |levels| levels.1 = mute);
+        }
+        Ok(!volumes.is_empty())
+    }
+
+    /// Loudest session peak of each process.
+    pub unsafe fn peaks(&mut self) -> HashMap<u32, f32> {
+        self.sync();
+        let mut peaks: HashMap<u32, f32> = HashMap::new();
+        for entry in self.entries.values() {
+            let Some(Ok(peak)) = entry.meter.as_ref().map(|m| m.GetPeakValue()) else {
+                continue;
+            };
+            let p = peaks.entry(entry.pid).or_default();
+            *p = p.max(peak);
+        }
+        peaks
+    }
+}
+
+impl Drop for SessionTable {
+    fn drop(&mut self) {
+        unsafe {
+            for entry in self.entries.values() {
+                let _ = entry
+                    .control
+                    .UnregisterAudioSessionNotification(&entry.events);
+            }
+        }
+    }
+}
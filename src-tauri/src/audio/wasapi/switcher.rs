@@ -0,0 +1,267 @@
+//! Default-device switching through the undocumented `IPolicyConfig`
+//! interface. The call runs in a child copy of our own executable started
+//! with `--switch-audio-device <id> --role <roles>`: a misbehaving audio
+//! driver can then only take the helper down, and the outcome (including
+//! verification) comes back as the helper's exit code.
+
+use super::super::{AudioResult, DeviceRole};
+use crate::error::ControlError;
+use std::ffi::c_void;
+use std::os::windows::process::CommandExt;
+use std::process::Command;
+use std::time::{Duration, Instant};
+use windows::core::{
+    interface, ComInterface, IUnknown, IUnknown_Vtbl, GUID, HRESULT, HSTRING, PCWSTR,
+};
+use windows::Win32::Media::Audio::*;
+use windows::Win32::System::Com::*;
+
+pub const SWITCH_FLAG: &str = "--switch-audio-device";
+pub const ROLE_FLAG: &str = "--role";
+
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+const HELPER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Modern Client (Win 10/11)
+const CLSID_POLICY_CONFIG_CLIENT: GUID = GUID {
+    data1: 0x870af99c,
+    data2: 0x171d,
+    data3: 0x4f9e,
+    data4: [0xaf, 0x0d, 0xe6, 0x3d, 0xf4, 0x0c, 0x2b, 0xc9],
+};
+
+#[interface("f8679f50-850a-41cf-9c72-430f290290c8")]
+unsafe trait IPolicyConfig: IUnknown {
+    fn GetMixFormat(&self) -> HRESULT;
+    fn GetDeviceFormat(&self) -> HRESULT;
+    fn ResetDeviceFormat(&self) -> HRESULT;
+    fn SetDeviceFormat(&self) -> HRESULT;
+    fn GetProcessingPeriod(&self) -> HRESULT;
+    fn SetProcessingPeriod(&self) -> HRESULT;
+    fn GetShareMode(&self) -> HRESULT;
+    fn SetShareMode(&self) -> HRESULT;
+    fn GetPropertyValue(&self) -> HRESULT;
+    fn SetPropertyValue(&self) -> HRESULT;
+    fn SetDefaultEndpoint(&self, id: PCWSTR, role: ERole) -> HRESULT;
+    fn SetEndpointVisibility(&self, id: PCWSTR, visible: i32) -> HRESULT;
+}
+
+/// Exit status of the helper process.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(i32)]
+pub enum SwitchStatus {
+    Switched = 0,
+    BadArguments = 2,
+    PolicyConfigUnavailable = 3,
+    DeviceNotFound = 4,
+    SetDefaultFailed = 5,
+    /// The call succeeded but the device did not become the default.
+    VerifyFailed = 6,
+}
+
+impl SwitchStatus {
+    fn from_code(code: i32) -> Option<Self> {
+        [
+            Self::Switched,
+            Self::BadArguments,
+            Self::PolicyConfigUnavailable,
+            Self::DeviceNotFound,
+            Self::SetDefaultFailed,
+            Self::VerifyFailed,
+        ]
+        .into_iter()
+        .find(|s| *s as i32 == code)
+    }
+
+    fn into_result(self, id: &str) -> AudioResult<()> {
+        Err(match self {
+            Self::Switched => return Ok(()),
+            Self::BadArguments => {
+                ControlError::InvalidArgument("audio switch helper rejected its arguments".into())
+            }
+            Self::PolicyConfigUnavailable => {
+                ControlError::BackendUnavailable("IPolicyConfig is not available".into())
+            }
+            Self::DeviceNotFound => ControlError::DeviceNotFound(id.to_string()),
+            Self::SetDefaultFailed => {
+                ControlError::Failed(format!("Windows refused to make {} the default", id))
+            }
+            Self::VerifyFailed => {
+                ControlError::Failed(format!("{} did not become the default device", id))
+            }
+        })
+    }
+}
+
+pub fn erole(role: DeviceRole) -> ERole {
+    match role {
+        DeviceRole::Console => eConsole,
+        DeviceRole::Multimedia => eMultimedia,
+        DeviceRole::Communications => eCommunications,
+    }
+}
+
+/// `--role` takes a comma-separated list of [`DeviceRole`] names, or `all`
+/// to set every role like the Sound control panel does.
+pub fn parse_roles(roles: &str) -> Option<Vec<ERole>> {
+    if roles == "all" {
+        return Some(DeviceRole::ALL.into_iter().map(erole).collect());
+    }
+    roles
+        .split(',')
+        .map(|name| {
+            DeviceRole::ALL
+                .into_iter()
+                .find(|r| r.as_str() == name)
+                .map(erole)
+        })
+        .collect()
+}
+
+/// Make `id` the default for `roles` by running the helper, and wait for it.
+pub fn switch_default_device(id: &str, roles: &[DeviceRole]) -> AudioResult<()> {
+    if roles.is_empty() {
+        return Ok(());
+    }
+    let roles: Vec<&str> = roles.iter().map(|r| r.as_str()).collect();
+    let exe = std::env::current_exe()?;
+    let mut child = Command::new(exe)
+        .creation_flags(CREATE_NO_WINDOW)
+        .args([SWITCH_FLAG, id, ROLE_FLAG, &roles.join(",")])
+        .spawn()
+        .map_err(|e| {
+            ControlError::BackendUnavailable(format!("failed to start audio switch helper: {}", e))
+        })?;
+    let deadline = Instant::now() + HELPER_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(ControlError::Timeout(
+                "audio switch helper timed out".into(),
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    match status.code().and_then(SwitchStatus::from_code) {
+        Some(s) => s.into_result(id),
+        None => Err(ControlError::Failed(format!(
+            "audio switch helper crashed ({})",
+            status
+        ))),
+    }
+}
+
+/// Entry point of the helper mode: returns the exit code when `args` ask for
+/// a switch, `None` for a normal start.
+pub fn helper_main(args: &[String]) -> Option<i32> {
+    let flag = args.iter().position(|a| a == SWITCH_FLAG)?;
+    let id = args.get(flag + 1);
+    let role = args
+        .iter()
+        .position(|a| a == ROLE_FLAG)
+        .and_then(|i| args.get(i + 1))
+        .map_or("all", String::as_str);
+    let status = match (id, parse_roles(role)) {
+        (Some(id), Some(roles)) => run_helper(id, &roles),
+        _ => SwitchStatus::BadArguments,
+    };
+    Some(status as i32)
+}
+
+/// Body of the helper process.
+pub fn run_helper(id: &str, roles: &[ERole]) -> SwitchStatus {
+    unsafe {
+        let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+        let Ok(enumerator) =
+            CoCreateInstance::<_, IMMDeviceEnumerator>(&MMDeviceEnumerator, None, CLSCTX_ALL)
+        else {
+            return SwitchStatus::PolicyConfigUnavailable;
+        };
+        let Some(flow) = data_flow(&enumerator, id) else {
+            return SwitchStatus::DeviceNotFound;
+        };
+        let Ok(policy) =
+            CoCreateInstance::<_, IPolicyConfig>(&CLSID_POLICY_CONFIG_CLIENT, None, CLSCTX_ALL)
+        else {
+            return SwitchStatus::PolicyConfigUnavailable;
+        };
+        let wide = HSTRING::from(id);
+        for &role in roles {
+            if policy
+                .SetDefaultEndpoint(PCWSTR(wide.as_ptr()), role)
+                .is_err()
+            {
+                return SwitchStatus::SetDefaultFailed;
+            }
+        }
+        // The audio service applies the change asynchronously.
+        for _ in 0..10 {
+            if roles
+                .iter()
+                .all(|&role| verify_default_device(&enumerator, id, flow, role))
+            {
+                return SwitchStatus::Switched;
+            }
+            std::thread::sleep(Duration::from_millis(30));
+        }
+        SwitchStatus::VerifyFailed
+    }
+}
+
+unsafe fn data_flow(enumerator: &IMMDeviceEnumerator, id: &str) -> Option<EDataFlow> {
+    let wide = HSTRING::from(id);
+    let device = enumerator.GetDevice(PCWSTR(wide.as_ptr())).ok()?;
+    device.cast::<IMMEndpoint>().ok()?.GetDataFlow().ok()
+}
+
+unsafe fn verify_default_device(
+    enumerator: &IMMDeviceEnumerator,
+    target_id: &str,
+    flow: EDataFlow,
+    role: ERole,
+) -> bool {
+    let Ok(def_dev) = enumerator.GetDefaultAudioEndpoint(flow, role) else {
+        return false;
+    };
+    let Ok(def_id) = def_dev.GetId() else {
+        return false;
+    };
+    let s = def_id.to_string().unwrap_or_default();
+    CoTaskMemFree(Some(def_id.as_ptr() as *const c_void));
+    s.eq_ignore_ascii_case(target_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn exit_codes_round_trip() {
+        for code in 0..10 {
+            if let Some(status) = SwitchStatus::from_code(code) {
+                assert_eq!(status as i32, code);
+            }
+        }
+        assert_eq!(SwitchStatus::from_code(1), None);
+    }
+
+    #[test]
+    fn helper_mode_needs_the_flag_and_valid_arguments() {
+        assert_eq!(helper_main(&args(&["app.exe"])), None);
+        let bad = Some(SwitchStatus::BadArguments as i32);
+        assert_eq!(helper_main(&args(&["app.exe", SWITCH_FLAG])), bad);
+        assert_eq!(
+            helper_main(&args(&["app.exe", SWITCH_FLAG, "id", ROLE_FLAG, "nope"])),
+            bad
+        );
+        assert_eq!(parse_roles("all").map(|r| r.len()), Some(3));
+    }
+}
@@ -0,0 +1,647 @@
+//! Resolves Linux processes to applications: `/proc/<pid>` gives the binary,
+//! its freedesktop `.desktop` entry gives a proper name and icon name, and
+//! the active icon theme turns that into a file. Every root is configurable,
+//! so a fake procfs and XDG tree in a temporary directory works as well.
+
+use crate::icons::IconImage;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Desktop entries are re-read at most this often when a binary has none.
+const RESCAN_AFTER: Duration = Duration::from_secs(30);
+
+/// Where the resolver looks.
+#[derive(Clone, Debug)]
+pub struct Roots {
+    /// `/proc`.
+    pub proc: PathBuf,
+    /// XDG data directories, most important first; `applications/` and
+    /// `icons/` below each are searched.
+    pub data_dirs: Vec<PathBuf>,
+    /// Extra icon locations: `~/.icons` before the data dirs, `pixmaps`
+    /// after them.
+    pub icons_home: Option<PathBuf>,
+    pub pixmaps: Vec<PathBuf>,
+    /// `XDG_CONFIG_HOME`, for the GTK or KDE icon theme setting.
+    pub config: Option<PathBuf>,
+}
+
+impl Roots {
+    pub fn system() -> Self {
+        let home = std::env::var_os("HOME").map(PathBuf::from);
+        let env_path = |var: &str, fallback: Option<PathBuf>| {
+            std::env::var_os(var)
+                .filter(|v| !v.is_empty())
+                .map(PathBuf::from)
+                .or(fallback)
+        };
+        let data_home = env_path(
+            "XDG_DATA_HOME",
+            home.as_ref().map(|h| h.join(".local/share")),
+        );
+        let data_dirs = std::env::var("XDG_DATA_DIRS")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "/usr/local/share:/usr/share".into());
+        Self {
+            proc: PathBuf::from("/proc"),
+            data_dirs: data_home
+                .into_iter()
+                .chain(data_dirs.split(':').map(PathBuf::from))
+                .collect(),
+            icons_home: home.as_ref().map(|h| h.join(".icons")),
+            pixmaps: vec![PathBuf::from("/usr/share/pixmaps")],
+            config: env_path("XDG_CONFIG_HOME", home.map(|h| h.join(".config"))),
+        }
+    }
+}
+
+/// What `/proc` and the desktop entries say about a process.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcessApp {
+    /// Target of `/proc/<pid>/exe`, empty when unreadable.
+    pub exe_path: String,
+    /// `Name:` of `/proc/<pid>/status`, the kernel's 15 character name.
+    pub comm: String,
+    pub cmdline: Vec<String>,
+    /// `Name` of the matching desktop entry.
+    pub name: Option<String>,
+    /// `Icon` of the matching desktop entry: a theme icon name or a path.
+    pub icon: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct DesktopEntry {
+    /// File name without `.desktop`, e.g. `org.mozilla.firefox`.
+    id: String,
+    name: String,
+    icon: Option<String>,
+    /// Program of `Exec`, and `TryExec`.
+    programs: Vec<String>,
+    wm_class: Option<String>,
+}
+
+/// Groups of an INI-style file (desktop entries, `index.theme`, GTK
+/// `settings.ini`, `kdeglobals`). Later duplicate keys are ignored.
+fn parse_ini(text: &str) -> HashMap<String, HashMap<String, String>> {
+    let mut groups: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut current = None;
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(group) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = Some(group.to_string());
+            groups.entry(group.to_string()).or_default();
+            continue;
+        }
+        let (Some(group), Some((key, value))) = (&current, line.split_once('=')) else {
+            continue;
+        };
+        groups
+            .entry(group.clone())
+            .or_default()
+            .entry(key.trim().to_string())
+            .or_insert_with(|| value.trim().to_string());
+    }
+    groups
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// The program an `Exec` line runs, skipping an `env VAR=value` prefix.
+fn exec_program(exec: &str) -> Option<String> {
+    exec.split_whitespace()
+        .map(|t| t.trim_matches('"'))
+        .find(|t| *t != "env" && (!t.contains('=') || t.starts_with('/')))
+        .map(str::to_string)
+}
+
+fn parse_desktop_entry(id: String, text: &str) -> Option<DesktopEntry> {
+    let groups = parse_ini(text);
+    let entry = groups.get("Desktop Entry")?;
+    let hidden = |key: &str| entry.get(key).is_some_and(|v| v == "true");
+    if entry.get("Type").is_some_and(|t| t != "Application") || hidden("Hidden") {
+        return None;
+    }
+    let programs = [entry.get("Exec"), entry.get("TryExec")]
+        .into_iter()
+        .flatten()
+        .filter_map(|e| exec_program(e))
+        .collect();
+    Some(DesktopEntry {
+        id,
+        name: entry.get("Name")?.clone(),
+        icon: entry.get("Icon").filter(|i| !i.is_empty()).cloned(),
+        programs,
+        wm_class: entry.get("StartupWMClass").cloned(),
+    })
+}
+
+/// `.desktop` files below `dir`; ids of nested ones join the
+/// subdirectories with `-`, as the spec says.
+fn collect_entries(dir: &Path, prefix: &str, seen: &mut Vec<String>, out: &mut Vec<DesktopEntry>) {
+    let Ok(read) = fs::read_dir(dir) else {
+        return;
+    };
+    for item in read.flatten() {
+        let path = item.path();
+        let name = item.file_name().to_string_lossy().into_owned();
+        if path.is_dir() {
+            collect_entries(&path, &format!("{}{}-", prefix, name), seen, out);
+            continue;
+        }
+        let Some(stem) = name.strip_suffix(".desktop") else {
+            continue;
+        };
+        let id = format!("{}{}", prefix, stem);
+        // An id found earlier in the data dirs shadows later ones.
+        if seen.contains(&id) {
+            continue;
+        }
+        seen.push(id.clone());
+        if let Some(entry) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| parse_desktop_entry(id, &text))
+        {
+            out.push(entry);
+        }
+    }
+}
+
+/// One icon theme directory as declared in `index.theme`.
+#[derive(Clone, Debug)]
+struct ThemeDir {
+    path: String,
+    size: u32,
+    scalable: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Theme {
+    dirs: Vec<ThemeDir>,
+    inherits: Vec<String>,
+}
+
+pub struct Resolver {
+    roots: Roots,
+    entries: Vec<DesktopEntry>,
+    scanned: Option<Instant>,
+    themes: HashMap<String, Option<Theme>>,
+}
+
+impl Resolver {
+    pub fn new(roots: Roots) -> Self {
+        Self {
+            roots,
+            entries: Vec::new(),
+            scanned: None,
+            themes: HashMap::new(),
+        }
+    }
+
+    fn rescan(&mut self) {
+        let mut seen = Vec::new();
+        let mut entries = Vec::new();
+        for dir in &self.roots.data_dirs {
+            collect_entries(&dir.join("applications"), "", &mut seen, &mut entries);
+        }
+        self.entries = entries;
+        self.scanned = Some(Instant::now());
+    }
+
+    /// Field 4 of `/proc/<pid>/stat`. Orphans report their subreaper or
+    /// init here rather than the parent that started them.
+    pub fn parent(&self, pid: u32) -> Option<u32> {
+        let stat = fs::read_to_string(self.roots.proc.join(pid.to_string()).join("stat")).ok()?;
+        // Counted from the last `)`, which ends the command name.
+        let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+        fields.nth(1)?.parse().ok()
+    }
+
+    /// Read `/proc/<pid>` and match the process to a desktop entry. `None`
+    /// when the process is gone.
+    pub fn process(&mut self, pid: u32) -> Option<ProcessApp> {
+        let dir = self.roots.proc.join(pid.to_string());
+        let status = fs::read_to_string(dir.join("status")).ok()?;
+        let comm = status
+            .lines()
+            .find_map(|l| l.strip_prefix("Name:"))
+            .map(|n| n.trim().to_string())
+            .unwrap_or_default();
+        let cmdline = fs::read(dir.join("cmdline"))
+            .map(|raw| {
+                raw.split(|&b| b == 0)
+                    .filter(|a| !a.is_empty())
+                    .map(|a| String::from_utf8_lossy(a).into_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        // Unreadable for other users' processes.
+        let exe_path = fs::read_link(dir.join("exe"))
+            .map(|p| {
+                let p = p.to_string_lossy();
+                p.strip_suffix(" (deleted)").unwrap_or(&p).to_string()
+            })
+            .unwrap_or_default();
+        let mut app = ProcessApp {
+            exe_path,
+            comm,
+            cmdline,
+            ..Default::default()
+        };
+        if self.scanned.is_none() {
+            self.rescan();
+        }
+        let mut entry = self.best_entry(&app);
+        if entry.is_none() && self.scanned.is_some_and(|t| t.elapsed() >= RESCAN_AFTER) {
+            // Possibly installed since the last scan.
+            self.rescan();
+            entry = self.best_entry(&app);
+        }
+        if let Some(entry) = entry {
+            app.name = Some(entry.name.clone());
+            app.icon = entry.icon.clone();
+        }
+        Some(app)
+    }
+
+    /// Best desktop entry for a process: one whose program is the binary's
+    /// full path, then one naming the same file, then a window class or
+    /// entry id equal to the binary name.
+    fn best_entry(&self, app: &ProcessApp) -> Option<&DesktopEntry> {
+        let mut names: Vec<&str> = Vec::new();
+        if !app.exe_path.is_empty() {
+            names.push(file_name(&app.exe_path));
+        }
+        if let Some(arg0) = app.cmdline.first() {
+            names.push(file_name(arg0));
+        }
+        if !app.comm.is_empty() {
+            names.push(&app.comm);
+        }
+        let by_path = (!app.exe_path.is_empty())
+            .then(|| {
+                self.entries
+                    .iter()
+                    .find(|e| e.programs.contains(&app.exe_path))
+            })
+            .flatten();
+        let by_program = || {
+            self.entries
+                .iter()
+                .find(|e| e.programs.iter().any(|p| names.contains(&file_name(p))))
+        };
+        let by_class = || {
+            self.entries.iter().find(|e| {
+                let id = e.id.rsplit('.').next().unwrap_or(&e.id);
+                names.iter().any(|n| {
+                    e.wm_class
+                        .as_deref()
+                        .is_some_and(|c| c.eq_ignore_ascii_case(n))
+                        || id.eq_ignore_ascii_case(n)
+                })
+            })
+        };
+        by_path.or_else(by_program).or_else(by_class)
+    }
+
+    /// Icon theme chosen in GTK's or KDE's settings, else `hicolor`.
+    fn active_theme(&self) -> String {
+        let config = self.roots.config.as_deref();
+        let read = |file: &str, group: &str, key: &str| {
+            let text = fs::read_to_string(config?.join(file)).ok()?;
+            parse_ini(&text).get(group)?.get(key).cloned()
+        };
+        read("gtk-3.0/settings.ini", "Settings", "gtk-icon-theme-name")
+            .or_else(|| read("gtk-4.0/settings.ini", "Settings", "gtk-icon-theme-name"))
+            .or_else(|| read("kdeglobals", "Icons", "Theme"))
+            .unwrap_or_else(|| "hicolor".into())
+    }
+
+    fn icon_bases(&self) -> Vec<PathBuf> {
+        self.roots
+            .icons_home
+            .iter()
+            .cloned()
+            .chain(self.roots.data_dirs.iter().map(|d| d.join("icons")))
+            .collect()
+    }
+
+    fn theme(&mut self, name: &str) -> Option<Theme> {
+        if let Some(theme) = self.themes.get(name) {
+            return theme.clone();
+        }
+        let theme = self.icon_bases().iter().find_map(|base| {
+            let text = fs::read_to_string(base.join(name).join("index.theme")).ok()?;
+            let groups = parse_ini(&text);
+            let index = groups.get("Icon Theme")?;
+            let list = |key: &str| -> Vec<String> {
+                index
+                    .get(key)
+                    .map(|v| {
+                        v.split(',')
+                            .map(|s| s.trim().to_string())
+                            .filter(|s| !s.is_empty())
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            };
+            let dirs = list("Directories")
+                .into_iter()
+                .filter_map(|path| {
+                    let group = groups.get(&path)?;
+                    let size = group.get("Size")?.parse().ok()?;
+                    let scalable = group.get("Type").is_some_and(|t| t == "Scalable");
+                    Some(ThemeDir {
+                        path,
+                        size,
+                        scalable,
+                    })
+                })
+                .collect();
+            Some(Theme {
+                dirs,
+                inherits: list("Inherits"),
+            })
+        });
+        self.themes.insert(name.to_string(), theme.clone());
+        theme
+    }
+
+    /// File of a desktop entry's `Icon` at about `size` pixels: an absolute
+    /// path as is, else a PNG from the active theme, the themes it inherits,
+    /// `hicolor`, and finally the pixmaps directories.
+    pub fn find_icon(&mut self, icon: &str, size: u32) -> Option<PathBuf> {
+        if icon.starts_with('/') {
+            return Path::new(icon).is_file().then(|| PathBuf::from(icon));
+        }
+        let mut queue = VecDeque::from([self.active_theme()]);
+        let mut visited: Vec<String> = Vec::new();
+        loop {
+            let name = match queue.pop_front() {
+                Some(name) => name,
+                // Every theme falls back to hicolor, declared or not.
+                None if !visited.iter().any(|v| v == "hicolor") => "hicolor".into(),
+                None => break,
+            };
+            if visited.contains(&name) {
+                continue;
+            }
+            visited.push(name.clone());
+            let Some(theme) = self.theme(&name) else {
+                continue;
+            };
+            if let Some(path) = self.lookup_in(&name, &theme, icon, size) {
+                return Some(path);
+            }
+            queue.extend(theme.inherits);
+        }
+        self.roots
+            .pixmaps
+            .iter()
+            .map(|dir| dir.join(format!("{}.png", icon)))
+            .find(|path| path.is_file())
+    }
+
+    /// Only PNGs: there is nothing here to rasterize SVG with. Directories
+    /// closest to `size` are tried first, larger before smaller.
+    fn lookup_in(&self, name: &str, theme: &Theme, icon: &str, size: u32) -> Option<PathBuf> {
+        let mut dirs: Vec<&ThemeDir> = theme.dirs.iter().filter(|d| !d.scalable).collect();
+        dirs.sort_by_key(|d| (d.size < size, d.size.abs_diff(size)));
+        let file = format!("{}.png", icon);
+        let bases = self.icon_bases();
+        dirs.iter().find_map(|dir| {
+            bases
+                .iter()
+                .map(|base| base.join(name).join(&dir.path).join(&file))
+                .find(|path| path.is_file())
+        })
+    }
+
+    /// Decoded icon of a desktop entry's `Icon` value.
+    pub fn icon_image(&mut self, icon: &str, size: u32) -> Option<IconImage> {
+        let path = self.find_icon(icon, size)?;
+        let img = image::open(path).ok()?.into_rgba8();
+        Some(IconImage {
+            width: img.width(),
+            height: img.height(),
+            rgba: img.into_raw(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A procfs, two data dirs, an icon home, pixmaps and a config dir in a
+    /// temporary directory, removed again on drop.
+    struct Tree(PathBuf);
+
+    impl Tree {
+        fn new(name: &str) -> Self {
+            let root =
+                std::env::temp_dir().join(format!("wcc-desktop-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            Self(root)
+        }
+
+        fn file(&self, path: &str, text: &str) -> PathBuf {
+            let path = self.0.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, text).unwrap();
+            path
+        }
+
+        fn process(&self, pid: u32, comm: &str, cmdline: &[&str], exe: &str, ppid: u32) {
+            let dir = format!("proc/{}", pid);
+            self.file(
+                &format!("{}/status", dir),
+                &format!("Name:\t{}\nState:\tS\n", comm),
+            );
+            self.file(&format!("{}/cmdline", dir), &(cmdline.join("\0") + "\0"));
+            self.file(
+                &format!("{}/stat", dir),
+                &format!("{} ({}) S {} 1 1 0 -1", pid, comm, ppid),
+            );
+            std::os::unix::fs::symlink(exe, self.0.join(dir).join("exe")).unwrap();
+        }
+
+        fn roots(&self) -> Roots {
+            Roots {
+                proc: self.0.join("proc"),
+                data_dirs: vec![self.0.join("local"), self.0.join("usr")],
+                icons_home: Some(self.0.join("home-icons")),
+                pixmaps: vec![self.0.join("pixmaps")],
+                config: Some(self.0.join("config")),
+            }
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn app(name: &str, exec: &str, icon: &str) -> String {
+        format!(
+            "[Desktop Entry]\nType=Application\nName={}\nExec={}\nIcon={}\n",
+            name, exec, icon
+        )
+    }
+
+    #[test]
+    fn process_is_read_from_proc_and_matched_by_path() {
+        let t = Tree::new("path");
+        t.file(
+            "usr/applications/firefox.desktop",
+            &app("Firefox", "/usr/lib/firefox/firefox %u", "firefox"),
+        );
+        t.process(
+            100,
+            "firefox",
+            &["/usr/lib/firefox/firefox", "-P"],
+            "/usr/lib/firefox/firefox",
+            1,
+        );
+        let mut r = Resolver::new(t.roots());
+        assert_eq!(
+            r.process(100),
+            Some(ProcessApp {
+                exe_path: "/usr/lib/firefox/firefox".into(),
+                comm: "firefox".into(),
+                cmdline: vec!["/usr/lib/firefox/firefox".into(), "-P".into()],
+                name: Some("Firefox".into()),
+                icon: Some("firefox".into()),
+            })
+        );
+        assert_eq!(r.process(101), None);
+    }
+
+    #[test]
+    fn env_prefixed_exec_lines_name_the_real_program() {
+        let t = Tree::new("env");
+        t.file(
+            "usr/applications/tool.desktop",
+            &app(
+                "Tool",
+                "env GDK_BACKEND=x11 /opt/tool/bin/tool --flag",
+                "tool",
+            ),
+        );
+        t.process(
+            200,
+            "tool",
+            &["/opt/tool/bin/tool"],
+            "/opt/tool/bin/tool",
+            1,
+        );
+        let app = Resolver::new(t.roots()).process(200).unwrap();
+        assert_eq!(app.name.as_deref(), Some("Tool"));
+    }
+
+    #[test]
+    fn nested_ids_match_the_binary_name() {
+        let t = Tree::new("nested");
+        t.file(
+            "usr/applications/kde/org.kde.dolphin.desktop",
+            &app("Dolphin", "/usr/bin/launch-dolphin", "system-file-manager"),
+        );
+        t.process(300, "dolphin", &["dolphin"], "/usr/bin/dolphin", 1);
+        let mut r = Resolver::new(t.roots());
+        assert_eq!(r.process(300).unwrap().name.as_deref(), Some("Dolphin"));
+        assert!(r.entries.iter().any(|e| e.id == "kde-org.kde.dolphin"));
+    }
+
+    #[test]
+    fn earlier_data_dirs_shadow_later_ones_and_hidden_entries_are_skipped() {
+        let t = Tree::new("shadow");
+        t.file(
+            "local/applications/player.desktop",
+            &app("My Player", "/usr/bin/player", "player-custom"),
+        );
+        t.file(
+            "usr/applications/player.desktop",
+            &app("Player", "/usr/bin/player", "player"),
+        );
+        t.file(
+            "usr/applications/gone.desktop",
+            &(app("Gone", "/usr/bin/gone", "gone") + "Hidden=true\n"),
+        );
+        t.process(400, "player", &["/usr/bin/player"], "/usr/bin/player", 1);
+        t.process(401, "gone", &["/usr/bin/gone"], "/usr/bin/gone", 1);
+        let mut r = Resolver::new(t.roots());
+        let player = r.process(400).unwrap();
+        assert_eq!(player.name.as_deref(), Some("My Player"));
+        assert_eq!(player.icon.as_deref(), Some("player-custom"));
+        let gone = r.process(401).unwrap();
+        assert_eq!((gone.name, gone.comm.as_str()), (None, "gone"));
+    }
+
+    #[test]
+    fn parent_is_read_past_the_command_name() {
+        let t = Tree::new("parent");
+        t.process(500, "web (content) 1", &["web"], "/usr/bin/web", 42);
+        let r = Resolver::new(t.roots());
+        assert_eq!(r.parent(500), Some(42));
+        assert_eq!(r.parent(501), None);
+    }
+
+    #[test]
+    fn icons_come_from_the_theme_its_parents_hicolor_then_pixmaps() {
+        let t = Tree::new("icons");
+        t.file(
+            "config/gtk-3.0/settings.ini",
+            "[Settings]\ngtk-icon-theme-name=Custom\n",
+        );
+        t.file(
+            "usr/icons/Custom/index.theme",
+            "[Icon Theme]\nInherits=Base\nDirectories=48x48/apps,scalable/apps\n\n\
+             [48x48/apps]\nSize=48\n\n[scalable/apps]\nSize=48\nType=Scalable\n",
+        );
+        t.file(
+            "usr/icons/Base/index.theme",
+            "[Icon Theme]\nDirectories=32x32/apps,64x64/apps\n\n\
+             [32x32/apps]\nSize=32\n\n[64x64/apps]\nSize=64\n",
+        );
+        t.file(
+            "usr/icons/hicolor/index.theme",
+            "[Icon Theme]\nDirectories=16x16/apps\n\n[16x16/apps]\nSize=16\n",
+        );
+        t.file("usr/icons/Custom/48x48/apps/custom.png", "");
+        let home = t.file("home-icons/Custom/48x48/apps/custom.png", "");
+        t.file("usr/icons/Custom/scalable/apps/vector.png", "");
+        t.file("usr/icons/Base/32x32/apps/base.png", "");
+        let large = t.file("usr/icons/Base/64x64/apps/base.png", "");
+        let hicolor = t.file("usr/icons/hicolor/16x16/apps/old.png", "");
+        let pixmap = t.file("pixmaps/pix.png", "");
+        let absolute = t.file("elsewhere/logo.png", "");
+
+        let mut r = Resolver::new(t.roots());
+        // The icon home comes before the data dirs.
+        assert_eq!(r.find_icon("custom", 48), Some(home));
+        // Inherited; the closest size at or above the one asked for wins.
+        assert_eq!(r.find_icon("base", 48), Some(large));
+        assert_eq!(
+            r.find_icon("base", 32),
+            Some(t.0.join("usr/icons/Base/32x32/apps/base.png"))
+        );
+        // hicolor is searched even though nothing inherits it.
+        assert_eq!(r.find_icon("old", 32), Some(hicolor));
+        assert_eq!(r.find_icon("pix", 32), Some(pixmap));
+        assert_eq!(
+            r.find_icon(absolute.to_str().unwrap(), 32),
+            Some(absolute.clone())
+        );
+        // Scalable directories hold SVGs, which cannot be drawn here.
+        assert_eq!(r.find_icon("vector", 48), None);
+        assert_eq!(r.find_icon("missing", 48), None);
+    }
+}
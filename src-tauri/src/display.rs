@@ -1,29 +1,37 @@
+use crate::error::{ControlError, ControlResult};
 use brightness::Brightness;
 use futures::stream::TryStreamExt;
+#[cfg(target_os = "windows")]
 use std::os::windows::process::CommandExt;
+#[cfg(target_os = "windows")]
 use std::process::Command;
 use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
+#[cfg(target_os = "windows")]
 const CREATE_NO_WINDOW: u32 = 0x08000000;
 
-// Global cache to prevent PowerShell spamming and main thread blocking
-static BRIGHTNESS_CACHE: Mutex<(f32, Option<Instant>)> = Mutex::new((0.5, None));
+// Global cache to prevent PowerShell spamming and main thread blocking.
+// `None` records a failed read, so it is not retried for the cache period.
+static BRIGHTNESS_CACHE: Mutex<(Option<f32>, Option<Instant>)> = Mutex::new((None, None));
 
-pub async fn get_brightness() -> Result<f32, String> {
+fn no_brightness_device() -> ControlError {
+    ControlError::BackendUnavailable("No brightness device could be read".into())
+}
+
+pub async fn get_brightness() -> ControlResult<f32> {
     {
         let cache = BRIGHTNESS_CACHE.lock().unwrap();
         if let Some(last_time) = cache.1 {
             if last_time.elapsed() < Duration::from_millis(5000) {
-                return Ok(cache.0);
+                return cache.0.ok_or_else(no_brightness_device);
             }
         }
     }
 
     println!("DEBUG: Fetching brightness devices via Crate...");
 
-    let mut result_val = 0.5;
-    let mut success = false;
+    let mut result_val = None;
 
     // 1. Try Crate (DDC/CI or generic WMI)
     let devices = brightness::brightness_devices()
@@ -50,62 +58,33 @@ pub async fn get_brightness() -> Result<f32, String> {
                 }
             }
             if success_read && count > 0 {
-                result_val = (total / count as f32) / 100.0;
-                success = true;
+                result_val = Some((total / count as f32) / 100.0);
             }
         }
     }
 
-    if !success {
-        println!("DEBUG: Crate failed to read. Trying PowerShell WMI fallback...");
-
-        let output = tokio::task::spawn_blocking(move || {
-            Command::new("powershell")
-                .args(&[
-                    "-NoProfile",
-                    "-Command",
-                    "(Get-CimInstance -Namespace root/wmi -ClassName WmiMonitorBrightness).CurrentBrightness"
-                ])
-                .creation_flags(CREATE_NO_WINDOW)
-                .output()
-        }).await
-        .map_err(|e| format!("JoinError: {}", e))?
-        .map_err(|e| format!("PowerShell exec failed: {}", e))?;
-
-        if output.status.success() {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let mut total = 0.0;
-            let mut count = 0;
-            for line in stdout.lines() {
-                if let Ok(val) = line.trim().parse::<f32>() {
-                    total += val;
-                    count += 1;
-                }
-            }
-            if count > 0 {
-                result_val = (total / count as f32) / 100.0;
-            }
-        }
+    if result_val.is_none() {
+        result_val = wmi_get_brightness().await?;
     }
 
     // Update cache regardless of success to prevent spamming
-    // If failed, we cache the default (or old) value for the duration too
     {
         let mut cache = BRIGHTNESS_CACHE.lock().unwrap();
         *cache = (result_val, Some(Instant::now()));
     }
 
-    Ok(result_val)
+    result_val.ok_or_else(no_brightness_device)
 }
 
-pub async fn set_brightness(val: f32) -> Result<(), String> {
+/// `val` is in 0..=1; see [`crate::validate::brightness`].
+pub async fn set_brightness(val: f32) -> ControlResult<()> {
     println!("DEBUG: Setting brightness to {} via Crate...", val);
-    let target_val = (val * 100.0) as u32;
+    let target_val = (val * 100.0).round() as u32;
 
     // Update cache immediately to prevent "jump back" on UI
     {
         let mut cache = BRIGHTNESS_CACHE.lock().unwrap();
-        *cache = (val, Some(Instant::now()));
+        *cache = (Some(val), Some(Instant::now()));
     }
 
     let devices = brightness::brightness_devices()
@@ -125,6 +104,51 @@ pub async fn set_brightness(val: f32) -> Result<(), String> {
         return Ok(());
     }
 
+    wmi_set_brightness(target_val).await
+}
+
+// PowerShell WMI fallbacks for panels the brightness crate cannot drive.
+#[cfg(target_os = "windows")]
+async fn wmi_get_brightness() -> ControlResult<Option<f32>> {
+    println!("DEBUG: Crate failed to read. Trying PowerShell WMI fallback...");
+
+    let output = tokio::task::spawn_blocking(move || {
+        Command::new("powershell")
+            .args([
+                "-NoProfile",
+                "-Command",
+                "(Get-CimInstance -Namespace root/wmi -ClassName WmiMonitorBrightness).CurrentBrightness"
+            ])
+            .creation_flags(CREATE_NO_WINDOW)
+            .output()
+    }).await
+    .map_err(|e| format!("JoinError: {}", e))?
+    .map_err(|e| ControlError::BackendUnavailable(format!("PowerShell exec failed: {}", e)))?;
+
+    if output.status.success() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut total = 0.0;
+        let mut count = 0;
+        for line in stdout.lines() {
+            if let Ok(val) = line.trim().parse::<f32>() {
+                total += val;
+                count += 1;
+            }
+        }
+        if count > 0 {
+            return Ok(Some((total / count as f32) / 100.0));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(not(target_os = "windows"))]
+async fn wmi_get_brightness() -> ControlResult<Option<f32>> {
+    Ok(None)
+}
+
+#[cfg(target_os = "windows")]
+async fn wmi_set_brightness(target_val: u32) -> ControlResult<()> {
     let cmd = format!(
         "(Get-WmiObject -Namespace root/wmi -Class WmiMonitorBrightnessMethods).WmiSetBrightness(1, {})",
         target_val
@@ -132,17 +156,24 @@ pub async fn set_brightness(val: f32) -> Result<(), String> {
 
     let output = tokio::task::spawn_blocking(move || {
         Command::new("powershell")
-            .args(&["-NoProfile", "-Command", &cmd])
+            .args(["-NoProfile", "-Command", &cmd])
             .creation_flags(CREATE_NO_WINDOW)
             .output()
     })
     .await
     .map_err(|e| format!("JoinError: {}", e))?
-    .map_err(|e| format!("PowerShell exec failed: {}", e))?;
+    .map_err(|e| ControlError::BackendUnavailable(format!("PowerShell exec failed: {}", e)))?;
 
     if output.status.success() {
         Ok(())
     } else {
-        Err("PowerShell WMI Set failed".into())
+        Err(ControlError::Failed("PowerShell WMI Set failed".into()))
     }
 }
+
+#[cfg(not(target_os = "windows"))]
+async fn wmi_set_brightness(_target_val: u32) -> ControlResult<()> {
+    Err(ControlError::BackendUnavailable(
+        "No brightness device accepted the new value".into(),
+    ))
+}
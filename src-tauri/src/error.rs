@@ -0,0 +1,110 @@
+//! The error every Tauri command returns. It reaches the frontend as
+//! `{ "kind": "session_gone", "message": "..." }`, so the UI can tell a
+//! vanished app from a missing driver instead of showing a stale value.
+
+use serde::Serialize;
+use std::fmt;
+
+pub type ControlResult<T> = std::result::Result<T, ControlError>;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum ControlError {
+    /// The platform API could not be reached, or does not exist here.
+    BackendUnavailable(String),
+    DeviceNotFound(String),
+    /// The app's audio session ended before the request reached it.
+    SessionGone(String),
+    PermissionDenied(String),
+    Timeout(String),
+    InvalidArgument(String),
+    /// The platform rejected the call for any other reason.
+    Failed(String),
+}
+
+impl ControlError {
+    pub fn message(&self) -> &str {
+        match self {
+            ControlError::BackendUnavailable(m)
+            | ControlError::DeviceNotFound(m)
+            | ControlError::SessionGone(m)
+            | ControlError::PermissionDenied(m)
+            | ControlError::Timeout(m)
+            | ControlError::InvalidArgument(m)
+            | ControlError::Failed(m) => m,
+        }
+    }
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+/// Untyped errors from lower layers are plain failures.
+impl From<String> for ControlError {
+    fn from(message: String) -> Self {
+        ControlError::Failed(message)
+    }
+}
+
+impl From<&str> for ControlError {
+    fn from(message: &str) -> Self {
+        ControlError::Failed(message.to_string())
+    }
+}
+
+impl From<std::io::Error> for ControlError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::PermissionDenied => ControlError::PermissionDenied(e.to_string()),
+            std::io::ErrorKind::TimedOut => ControlError::Timeout(e.to_string()),
+            _ => ControlError::Failed(e.to_string()),
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl From<windows::core::Error> for ControlError {
+    fn from(e: windows::core::Error) -> Self {
+        use windows::Win32::Foundation::{ERROR_NOT_FOUND, E_ACCESSDENIED};
+        use windows::Win32::Media::Audio::AUDCLNT_E_DEVICE_INVALIDATED;
+        let code = e.code();
+        let message = e.message().to_string_lossy();
+        if code == E_ACCESSDENIED {
+            ControlError::PermissionDenied(message)
+        } else if code == AUDCLNT_E_DEVICE_INVALIDATED || code == ERROR_NOT_FOUND.to_hresult() {
+            ControlError::DeviceNotFound(message)
+        } else {
+            ControlError::Failed(e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_as_kind_and_message() {
+        let json = serde_json::to_value(ControlError::SessionGone("player".into())).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "kind": "session_gone", "message": "player" })
+        );
+    }
+
+    #[test]
+    fn io_errors_keep_their_kind() {
+        let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert!(matches!(
+            ControlError::from(denied),
+            ControlError::PermissionDenied(_)
+        ));
+        let other = std::io::Error::from(std::io::ErrorKind::InvalidData);
+        assert!(matches!(ControlError::from(other), ControlError::Failed(_)));
+    }
+}
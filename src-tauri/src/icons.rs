@@ -0,0 +1,273 @@
+//! On-disk cache of application icons, served to the webview through the
+//! `icon` URI scheme. Rows carry an `icon://<key>` reference instead of the
+//! image, and the key changes whenever the executable does, so cached files
+//! never need invalidating.
+
+use image::imageops::FilterType;
+use image::{ImageEncoder, RgbaImage};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::UNIX_EPOCH;
+
+/// Sizes written for every icon, in pixels.
+pub const SIZES: [u32; 4] = [16, 32, 48, 256];
+
+/// Prefix of the references stored in `ProcessInfo::icon_path`.
+pub const SCHEME_PREFIX: &str = "icon://";
+
+/// Square RGBA pixels as produced by a backend.
+pub struct IconImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+pub struct IconStore {
+    dir: PathBuf,
+    /// References already stored, by icon source and modification time, so
+    /// a known icon costs one `stat` rather than a look at every file.
+    stored: Mutex<HashMap<(String, u128), String>>,
+}
+
+/// FNV-1a, chosen over `DefaultHasher` because its output must stay the
+/// same across builds for the files to be found again.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Split an icon source, a path or a `<path>,<index>` icon resource, into
+/// the file and the index. Negative indices are resource ids.
+pub fn split_source(source: &str) -> (&str, Option<i32>) {
+    match source.rsplit_once(',') {
+        Some((file, index)) => match index.trim().parse() {
+            Ok(index) => (file, Some(index)),
+            Err(_) => (source, None),
+        },
+        None => (source, None),
+    }
+}
+
+/// Modification time of the icon source's file, in nanoseconds.
+fn modified(source: &str) -> Option<u128> {
+    let modified = fs::metadata(split_source(source).0).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos())
+}
+
+/// Hash of the icon source and its file's modification time.
+fn key_of(source: &str, modified: u128) -> String {
+    let mut bytes = source.as_bytes().to_vec();
+    bytes.extend_from_slice(&modified.to_le_bytes());
+    format!("{:016x}", fnv1a(&bytes))
+}
+
+fn encode_png(img: &RgbaImage) -> Option<Vec<u8>> {
+    let mut png = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut png)
+        .write_image(
+            img.as_raw(),
+            img.width(),
+            img.height(),
+            image::ColorType::Rgba8.into(),
+        )
+        .ok()?;
+    Some(png)
+}
+
+impl IconStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            stored: Mutex::default(),
+        }
+    }
+
+    fn file(&self, key: &str, size: u32) -> PathBuf {
+        self.dir.join(format!("{}-{}.png", key, size))
+    }
+
+    /// Make sure the icon of `exe_path` (or of a `<path>,<index>` icon
+    /// resource) is on disk and return its `icon://` reference. `render` is
+    /// asked for each missing size; images smaller than the size asked for
+    /// are not scaled up. `None` when no size could be stored. Once stored,
+    /// an icon is not looked for on disk again until its file changes.
+    pub fn ensure(
+        &self,
+        exe_path: &str,
+        mut render: impl FnMut(u32) -> Option<IconImage>,
+    ) -> Option<String> {
+        let modified = modified(exe_path)?;
+        let known = (exe_path.to_string(), modified);
+        let mut cache = self.stored.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(reference) = cache.get(&known) {
+            return Some(reference.clone());
+        }
+        let key = key_of(exe_path, modified);
+        let mut stored = false;
+        for size in SIZES {
+            let path = self.file(&key, size);
+            if path.exists() {
+                stored = true;
+                continue;
+            }
+            let Some(icon) = render(size) else {
+                continue;
+            };
+            let Some(img) = RgbaImage::from_raw(icon.width, icon.height, icon.rgba) else {
+                continue;
+            };
+            if img.width() < size || img.height() < size {
+                continue;
+            }
+            let img = if img.width() == size && img.height() == size {
+                img
+            } else {
+                image::imageops::resize(&img, size, size, FilterType::Lanczos3)
+            };
+            match encode_png(&img).map(|png| self.write(&path, &png)) {
+                Some(Ok(())) => stored = true,
+                Some(Err(e)) => println!("Could not cache icon {}: {}", path.display(), e),
+                None => {}
+            }
+        }
+        let reference = format!("{}{}", SCHEME_PREFIX, key);
+        if stored {
+            cache.insert(known, reference.clone());
+        }
+        stored.then_some(reference)
+    }
+
+    /// Write through a temporary file so a reader never sees half a PNG.
+    fn write(&self, path: &Path, png: &[u8]) -> std::io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, png)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// PNG bytes for a request path of `<key>` or `<key>-<size>`. The closest
+    /// stored size at or above the one asked for wins, then the largest
+    /// below it.
+    pub fn load(&self, name: &str) -> Option<Vec<u8>> {
+        let (key, size) = match name.split_once('-') {
+            Some((key, size)) => (key, size.parse().ok()?),
+            None => (name, 32),
+        };
+        // The key ends up in a file name; accept nothing but our own hashes.
+        if key.len() != 16 || !key.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let above = SIZES.iter().filter(|&&s| s >= size);
+        let below = SIZES.iter().rev().filter(|&&s| s < size);
+        above
+            .chain(below)
+            .find_map(|&s| fs::read(self.file(key, s)).ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn red(size: u32) -> Option<IconImage> {
+        Some(IconImage {
+            width: size,
+            height: size,
+            rgba: [255, 0, 0, 255].repeat((size * size) as usize),
+        })
+    }
+
+    fn png_size(png: &[u8]) -> (u32, u32) {
+        let img = image::load_from_memory(png).unwrap();
+        (img.width(), img.height())
+    }
+
+    #[test]
+    fn known_icons_are_not_rendered_or_looked_up_again() {
+        let dir = std::env::temp_dir().join(format!("wcc-icons-{}", std::process::id()));
+        let exe = dir.join("app.exe");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&exe, b"MZ").unwrap();
+        let exe = exe.to_str().unwrap();
+        let store = IconStore::new(dir.join("cache"));
+
+        let renders = Cell::new(0);
+        let render = |size| {
+            renders.set(renders.get() + 1);
+            red(size)
+        };
+        let first = store.ensure(exe, render).unwrap();
+        assert_eq!(renders.get(), SIZES.len());
+        let key = first.strip_prefix(SCHEME_PREFIX).unwrap();
+        assert!(store.load(key).is_some());
+
+        // Served from memory: the files being gone goes unnoticed.
+        fs::remove_dir_all(dir.join("cache")).unwrap();
+        assert_eq!(store.ensure(exe, render), Some(first.clone()));
+        assert_eq!(renders.get(), SIZES.len());
+
+        // A new build of the executable gets a new key.
+        let later = UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        fs::File::options()
+            .write(true)
+            .open(exe)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let second = store.ensure(exe, render).unwrap();
+        assert_ne!(second, first);
+        assert_eq!(renders.get(), 2 * SIZES.len());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sizes_are_stored_without_scaling_up() {
+        let dir = std::env::temp_dir().join(format!("wcc-icon-sizes-{}", std::process::id()));
+        let exe = dir.join("app.exe");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&exe, b"MZ").unwrap();
+        let store = IconStore::new(dir.join("cache"));
+
+        // Only a 48 px image: 16 and 32 are scaled down, 256 is skipped.
+        let icon = store.ensure(exe.to_str().unwrap(), |_| red(48)).unwrap();
+        let key = icon.strip_prefix(SCHEME_PREFIX).unwrap();
+        assert_eq!(png_size(&store.load(key).unwrap()), (32, 32));
+        assert_eq!(
+            png_size(&store.load(&format!("{}-20", key)).unwrap()),
+            (32, 32)
+        );
+        // Nothing at or above 256: the largest below it.
+        assert_eq!(
+            png_size(&store.load(&format!("{}-256", key)).unwrap()),
+            (48, 48)
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn only_our_own_keys_are_loaded() {
+        let store = IconStore::new(std::env::temp_dir());
+        assert_eq!(store.load("../../etc/passwd"), None);
+        assert_eq!(store.load("0123456789abcdef-x"), None);
+        assert_eq!(store.load("not-a-key"), None);
+    }
+
+    #[test]
+    fn nothing_is_stored_without_an_image() {
+        let store = IconStore::new(std::env::temp_dir().join("wcc-icon-none"));
+        assert_eq!(store.ensure("/no/such/app.exe", red), None);
+        let dir = std::env::temp_dir().join(format!("wcc-icon-empty-{}", std::process::id()));
+        let exe = dir.join("app.exe");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&exe, b"MZ").unwrap();
+        let store = IconStore::new(dir.join("cache"));
+        assert_eq!(store.ensure(exe.to_str().unwrap(), |_| None), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,11 +1,16 @@
+#[cfg(not(target_os = "windows"))]
+use crate::error::ControlError;
+use crate::error::ControlResult;
+#[cfg(target_os = "windows")]
 use std::ffi::c_void;
-use windows::core::Result;
+#[cfg(target_os = "windows")]
 use windows::Win32::UI::WindowsAndMessaging::{
     SystemParametersInfoA, SPIF_SENDCHANGE, SPIF_UPDATEINIFILE, SPI_GETMOUSESPEED,
     SPI_SETMOUSESPEED,
 };
 
-pub fn get_mouse_sensitivity() -> Result<u32> {
+#[cfg(target_os = "windows")]
+pub fn get_mouse_sensitivity() -> ControlResult<u32> {
     unsafe {
         let mut speed: u32 = 0;
         SystemParametersInfoA(
@@ -18,15 +23,30 @@ pub fn get_mouse_sensitivity() -> Result<u32> {
     }
 }
 
-pub fn set_mouse_sensitivity(val: u32) -> Result<()> {
-    let val = val.clamp(1, 20);
+#[cfg(target_os = "windows")]
+/// `val` is in 1..=20; see [`crate::validate::mouse_speed`].
+pub fn set_mouse_sensitivity(val: u32) -> ControlResult<()> {
     unsafe {
-        let _ = SystemParametersInfoA(
+        SystemParametersInfoA(
             SPI_SETMOUSESPEED,
             0,
             Some(val as *mut c_void),
             SPIF_UPDATEINIFILE | SPIF_SENDCHANGE,
-        );
+        )?;
         Ok(())
     }
 }
+
+#[cfg(not(target_os = "windows"))]
+pub fn get_mouse_sensitivity() -> ControlResult<u32> {
+    Err(ControlError::BackendUnavailable(
+        "Mouse speed is only supported on Windows".into(),
+    ))
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn set_mouse_sensitivity(_val: u32) -> ControlResult<()> {
+    Err(ControlError::BackendUnavailable(
+        "Mouse speed is only supported on Windows".into(),
+    ))
+}
@@ -1,7 +1,19 @@
 mod audio;
+#[cfg(target_os = "linux")]
+mod desktop;
 mod display;
+mod error;
+mod icons;
 mod input;
+// Only WASAPI reads executables' resources.
+#[cfg(any(target_os = "windows", test))]
+mod pe;
+mod validate;
 
+#[cfg(target_os = "windows")]
+#[doc(hidden)]
+pub use audio::SessionBench;
+use error::{ControlError, ControlResult};
 #[cfg(target_os = "windows")]
 use raw_window_handle::{HasWindowHandle, RawWindowHandle};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
@@ -10,9 +22,9 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use tauri::window::Color;
 use tauri::{
     image::Image,
-    menu::{CheckMenuItem, Menu, MenuItem, Submenu},
+    menu::{CheckMenuItem, Menu, MenuItem, PredefinedMenuItem, Submenu},
     tray::{MouseButton, TrayIconBuilder, TrayIconEvent},
-    Manager, Theme, WebviewWindow,
+    Emitter, Manager, Theme, WebviewWindow,
 };
 #[cfg(target_os = "windows")]
 use window_vibrancy::{apply_acrylic, apply_blur, apply_mica};
@@ -22,6 +34,7 @@ use windows::Win32::Foundation::HWND;
 use windows::Win32::Graphics::Dwm::{
     DwmSetWindowAttribute, DWMSBT_TABBEDWINDOW, DWMWA_SYSTEMBACKDROP_TYPE,
 };
+#[cfg(target_os = "windows")]
 use winreg::{enums::*, RegKey};
 
 #[cfg(not(target_os = "windows"))]
@@ -31,6 +44,12 @@ fn apply_window_effect(_window: &WebviewWindow) {}
 const ICON_WHITE_BYTES: &[u8] = include_bytes!("../icons/icon_white.png");
 const ICON_BLACK_BYTES: &[u8] = include_bytes!("../icons/icon_black.png");
 
+#[cfg(not(target_os = "windows"))]
+fn is_light_mode_registry() -> bool {
+    false
+}
+
+#[cfg(target_os = "windows")]
 fn is_light_mode_registry() -> bool {
     let hkcu = RegKey::predef(HKEY_CURRENT_USER);
     if let Ok(key) =
@@ -60,6 +79,7 @@ struct LastTrayState {
     in_devs: Vec<audio::AudioDevice>,
     autostart: bool,
     blur_style: BlurStyle,
+    scenes: Vec<String>,
 }
 
 pub struct AppState {
@@ -71,84 +91,353 @@ pub struct AppState {
 
     last_tray_state: Mutex<Option<LastTrayState>>,
     tray: Mutex<Option<tauri::tray::TrayIcon>>,
+    tray_theme: Mutex<Theme>,
+    /// Apps using the microphone; the tray icon is badged while any is.
+    recording: Mutex<Vec<String>>,
 }
 
 // --- Async Setter Commands (Non-blocking) ---
 
 #[tauri::command]
-fn set_system_volume(state: tauri::State<audio::AudioState>, vol: f32) {
-    let _ = state.tx.send(audio::AudioRequest::SetMasterVolume(vol));
+async fn set_system_volume(
+    state: tauri::State<'_, audio::AudioState>,
+    vol: f32,
+) -> ControlResult<()> {
+    state
+        .request_volume(vol, audio::AudioRequest::SetMasterVolume)
+        .await
 }
 
 #[tauri::command]
-fn set_mic_volume(state: tauri::State<audio::AudioState>, vol: f32) {
-    let _ = state.tx.send(audio::AudioRequest::SetMicVolume(vol));
+async fn set_mic_volume(state: tauri::State<'_, audio::AudioState>, vol: f32) -> ControlResult<()> {
+    state
+        .request_volume(vol, audio::AudioRequest::SetMicVolume)
+        .await
 }
 
 #[tauri::command]
-fn set_app_volume(state: tauri::State<audio::AudioState>, pid: u32, vol: f32) {
-    let _ = state.tx.send(audio::AudioRequest::SetAppVolume(pid, vol));
+async fn set_app_volume(
+    state: tauri::State<'_, audio::AudioState>,
+    pid: u32,
+    vol: f32,
+) -> ControlResult<()> {
+    state
+        .request_volume(vol, |vol, tx| {
+            audio::AudioRequest::SetAppVolume(pid, vol, tx)
+        })
+        .await
 }
 
 #[tauri::command]
-fn set_app_mute(state: tauri::State<audio::AudioState>, pid: u32, mute: bool) {
-    let _ = state.tx.send(audio::AudioRequest::SetAppMute(pid, mute));
+async fn set_app_mute(
+    state: tauri::State<'_, audio::AudioState>,
+    pid: u32,
+    mute: bool,
+) -> ControlResult<()> {
+    state
+        .request(|tx| audio::AudioRequest::SetAppMute(pid, mute, tx))
+        .await
 }
 
 #[tauri::command]
-fn set_system_mute(state: tauri::State<audio::AudioState>, mute: bool) {
-    let _ = state.tx.send(audio::AudioRequest::SetMasterMute(mute));
+async fn set_capture_app_volume(
+    state: tauri::State<'_, audio::AudioState>,
+    pid: u32,
+    vol: f32,
+) -> ControlResult<()> {
+    state
+        .request_volume(vol, |vol, tx| {
+            audio::AudioRequest::SetCaptureAppVolume(pid, vol, tx)
+        })
+        .await
 }
 
 #[tauri::command]
-fn set_mic_mute(state: tauri::State<audio::AudioState>, mute: bool) {
-    let _ = state.tx.send(audio::AudioRequest::SetMicMute(mute));
+async fn set_capture_app_mute(
+    state: tauri::State<'_, audio::AudioState>,
+    pid: u32,
+    mute: bool,
+) -> ControlResult<()> {
+    state
+        .request(|tx| audio::AudioRequest::SetCaptureAppMute(pid, mute, tx))
+        .await
 }
 
-// --- Getter Commands (Using Request/Response) ---
+#[tauri::command]
+async fn set_app_group_volume(
+    state: tauri::State<'_, audio::AudioState>,
+    group: String,
+    vol: f32,
+) -> ControlResult<()> {
+    let group = validate::non_empty("group", group)?;
+    state
+        .request_volume(vol, |vol, tx| {
+            audio::AudioRequest::SetGroupVolume(group, vol, tx)
+        })
+        .await
+}
 
 #[tauri::command]
-async fn get_system_volume(
+async fn set_app_group_mute(
     state: tauri::State<'_, audio::AudioState>,
-) -> Result<(f32, bool), String> {
-    let (tx, rx) = tokio::sync::oneshot::channel();
+    group: String,
+    mute: bool,
+) -> ControlResult<()> {
+    let group = validate::non_empty("group", group)?;
     state
-        .tx
-        .send(audio::AudioRequest::GetMasterVolume(tx))
-        .map_err(|e| e.to_string())?;
-    rx.await
-        .map_err(|e| e.to_string())?
-        .map_err(|e| e.to_string())
+        .request(|tx| audio::AudioRequest::SetGroupMute(group, mute, tx))
+        .await
 }
 
 #[tauri::command]
-async fn get_mic_volume(state: tauri::State<'_, audio::AudioState>) -> Result<(f32, bool), String> {
-    let (tx, rx) = tokio::sync::oneshot::channel();
+async fn get_volume_memory_exclusions(
+    state: tauri::State<'_, audio::AudioState>,
+) -> ControlResult<Vec<String>> {
     state
-        .tx
-        .send(audio::AudioRequest::GetMicVolume(tx))
-        .map_err(|e| e.to_string())?;
-    rx.await
-        .map_err(|e| e.to_string())?
-        .map_err(|e| e.to_string())
+        .request(audio::AudioRequest::GetVolumeMemoryExclusions)
+        .await
 }
 
 #[tauri::command]
-async fn get_app_volumes(
+async fn set_volume_memory_excluded(
     state: tauri::State<'_, audio::AudioState>,
-) -> Result<Vec<audio::AppVolume>, String> {
-    let (tx, rx) = tokio::sync::oneshot::channel();
+    group: String,
+    excluded: bool,
+) -> ControlResult<()> {
+    let group = validate::non_empty("group", group)?;
     state
-        .tx
-        .send(audio::AudioRequest::GetAppVolumes(tx))
-        .map_err(|e| e.to_string())?;
-    rx.await
-        .map_err(|e| e.to_string())?
-        .map_err(|e| e.to_string())
+        .request(|tx| audio::AudioRequest::SetVolumeMemoryExcluded(group, excluded, tx))
+        .await
+}
+
+#[tauri::command]
+async fn get_mixer_view(
+    state: tauri::State<'_, audio::AudioState>,
+) -> ControlResult<audio::view::MixerView> {
+    state.request(audio::AudioRequest::GetMixerView).await
+}
+
+/// Sort mode, pinned groups and inactivity cut-off of the mixer rows.
+#[tauri::command]
+async fn set_mixer_view(
+    state: tauri::State<'_, audio::AudioState>,
+    view: audio::view::MixerView,
+) -> ControlResult<()> {
+    validate::mixer_view(&view)?;
+    state
+        .request(|tx| audio::AudioRequest::SetMixerView(view, tx))
+        .await
+}
+
+#[tauri::command]
+async fn set_app_output_device(
+    state: tauri::State<'_, audio::AudioState>,
+    pid: u32,
+    device_id: String,
+) -> ControlResult<()> {
+    let device_id = validate::non_empty("device id", device_id)?;
+    state
+        .request(|tx| audio::AudioRequest::SetAppOutputDevice(pid, Some(device_id), tx))
+        .await
+}
+
+#[tauri::command]
+async fn reset_app_output_device(
+    state: tauri::State<'_, audio::AudioState>,
+    pid: u32,
+) -> ControlResult<()> {
+    state
+        .request(|tx| audio::AudioRequest::SetAppOutputDevice(pid, None, tx))
+        .await
+}
+
+/// Depth and coalescing counters of the audio worker queue.
+#[tauri::command]
+fn get_audio_queue_stats(
+    state: tauri::State<audio::AudioState>,
+) -> ControlResult<audio::QueueStats> {
+    Ok(state.queue_stats())
+}
+
+/// Size, hit rate and evictions of the process name and icon cache.
+#[tauri::command]
+fn get_cache_stats(cache: tauri::State<Arc<audio::AppCache>>) -> ControlResult<audio::CacheStats> {
+    Ok(cache.stats())
+}
+
+/// Executable name patterns whose sessions belong to their parent app.
+#[tauri::command]
+fn get_helper_rules(cache: tauri::State<Arc<audio::AppCache>>) -> ControlResult<Vec<String>> {
+    Ok(cache.helper_rules())
+}
+
+#[tauri::command]
+fn set_helper_rules(
+    cache: tauri::State<Arc<audio::AppCache>>,
+    rules: Vec<String>,
+) -> ControlResult<()> {
+    let rules = rules
+        .into_iter()
+        .map(|rule| validate::non_empty("helper rule", rule))
+        .collect::<ControlResult<Vec<_>>>()?;
+    cache.set_helper_rules(rules)
+}
+
+/// Uptime, restart count and last error of the audio worker.
+#[tauri::command]
+fn get_backend_health(
+    state: tauri::State<audio::AudioState>,
+) -> ControlResult<audio::BackendHealth> {
+    Ok(state.health())
+}
+
+/// Start (`hz` > 0) or stop (`hz` = 0) the `audio://levels` peak meter events.
+#[tauri::command]
+async fn set_level_meter_rate(
+    state: tauri::State<'_, audio::AudioState>,
+    hz: u32,
+) -> ControlResult<()> {
+    state.request_meter_rate(hz).await
+}
+
+#[tauri::command]
+async fn set_default_device(
+    state: tauri::State<'_, audio::AudioState>,
+    id: String,
+    roles: Vec<audio::DeviceRole>,
+) -> ControlResult<audio::DeviceSwitch> {
+    let id = validate::non_empty("device id", id)?;
+    let roles = validate::non_empty_list("roles", roles)?;
+    state
+        .request(|tx| audio::AudioRequest::SetDefaultDevice(id, roles, tx))
+        .await
+}
+
+/// Switch from a tray click. Clicking a check item already toggled its mark,
+/// so the menu is rebuilt from the real state whatever the outcome.
+fn spawn_tray_switch(app: &tauri::AppHandle, id: &str, roles: Vec<audio::DeviceRole>) {
+    let h = app.clone();
+    let id = id.to_string();
+    tauri::async_runtime::spawn(async move {
+        let res = h
+            .state::<audio::AudioState>()
+            .request(|tx| audio::AudioRequest::SetDefaultDevice(id, roles, tx))
+            .await;
+        if let Err(e) = res {
+            println!("Device switch failed: {}", e);
+        }
+        *h.state::<AppState>().last_tray_state.lock().unwrap() = None;
+        update_tray_menu(&h).await;
+    });
+}
+
+fn notify_device_switch(app: &tauri::AppHandle, switch: &audio::DeviceSwitch) {
+    use tauri_plugin_notification::NotificationExt;
+    let (title, body) = match &switch.error {
+        None => ("已切换音频设备".to_string(), switch.name.clone()),
+        Some(e) => ("切换音频设备失败".to_string(), e.to_string()),
+    };
+    if let Err(e) = app.notification().builder().title(title).body(body).show() {
+        println!("Failed to show notification: {}", e);
+    }
+}
+
+// --- Audio Scenes ---
+
+#[tauri::command]
+fn get_scenes(
+    store: tauri::State<audio::scene::SceneStore>,
+) -> ControlResult<Vec<audio::scene::Scene>> {
+    store.list()
+}
+
+#[tauri::command]
+async fn save_scene(app: tauri::AppHandle, name: String) -> ControlResult<audio::scene::Scene> {
+    let scene = save_current_scene(&app, name).await?;
+    update_tray_menu(&app).await;
+    Ok(scene)
+}
+
+#[tauri::command]
+async fn apply_scene(app: tauri::AppHandle, name: String) -> ControlResult<()> {
+    let scene = app.state::<audio::scene::SceneStore>().get(&name)?;
+    validate::scene(&scene)?;
+    app.state::<audio::AudioState>()
+        .request(|tx| audio::AudioRequest::ApplyScene(scene, tx))
+        .await
+}
+
+#[tauri::command]
+async fn delete_scene(app: tauri::AppHandle, name: String) -> ControlResult<()> {
+    app.state::<audio::scene::SceneStore>().delete(&name)?;
+    update_tray_menu(&app).await;
+    Ok(())
+}
+
+async fn save_current_scene(
+    app: &tauri::AppHandle,
+    name: String,
+) -> ControlResult<audio::scene::Scene> {
+    let name = validate::non_empty("scene name", name)?.trim().to_string();
+    let scene = app
+        .state::<audio::AudioState>()
+        .request(|tx| audio::AudioRequest::CaptureScene(name, tx))
+        .await?;
+    app.state::<audio::scene::SceneStore>()
+        .save(scene.clone())?;
+    Ok(scene)
+}
+
+#[tauri::command]
+async fn set_system_mute(
+    state: tauri::State<'_, audio::AudioState>,
+    mute: bool,
+) -> ControlResult<()> {
+    state
+        .request(|tx| audio::AudioRequest::SetMasterMute(mute, tx))
+        .await
+}
+
+#[tauri::command]
+async fn set_mic_mute(state: tauri::State<'_, audio::AudioState>, mute: bool) -> ControlResult<()> {
+    state
+        .request(|tx| audio::AudioRequest::SetMicMute(mute, tx))
+        .await
+}
+
+// --- Getter Commands (Using Request/Response) ---
+
+#[tauri::command]
+async fn get_system_volume(
+    state: tauri::State<'_, audio::AudioState>,
+) -> ControlResult<(f32, bool)> {
+    state.request(audio::AudioRequest::GetMasterVolume).await
+}
+
+#[tauri::command]
+async fn get_mic_volume(state: tauri::State<'_, audio::AudioState>) -> ControlResult<(f32, bool)> {
+    state.request(audio::AudioRequest::GetMicVolume).await
+}
+
+#[tauri::command]
+async fn get_app_volumes(
+    state: tauri::State<'_, audio::AudioState>,
+) -> ControlResult<Vec<audio::AppVolume>> {
+    state.request(audio::AudioRequest::GetAppVolumes).await
+}
+
+#[tauri::command]
+async fn get_capture_apps(
+    state: tauri::State<'_, audio::AudioState>,
+) -> ControlResult<Vec<audio::AppVolume>> {
+    state.request(audio::AudioRequest::GetCaptureApps).await
 }
 
 #[tauri::command]
 fn reapply_effects(window: tauri::WebviewWindow) {
+    // The effects are DWM's; elsewhere there is nothing to re-apply.
+    #[cfg(not(target_os = "windows"))]
+    let _ = window;
     #[cfg(target_os = "windows")]
     {
         println!("Manually re-applying effects with DWM Kick...");
@@ -292,7 +581,7 @@ pub struct BrightnessCache {
 }
 
 #[tauri::command]
-async fn get_brightness(cache: tauri::State<'_, BrightnessCache>) -> Result<f32, String> {
+async fn get_brightness(cache: tauri::State<'_, BrightnessCache>) -> ControlResult<f32> {
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
@@ -305,6 +594,11 @@ async fn get_brightness(cache: tauri::State<'_, BrightnessCache>) -> Result<f32,
     }
 
     if cache.is_fetching.swap(true, Ordering::SeqCst) {
+        if last == 0 {
+            return Err(ControlError::Timeout(
+                "Brightness is still being read".into(),
+            ));
+        }
         return Ok(*cache.val.lock().unwrap());
     }
 
@@ -320,7 +614,8 @@ async fn get_brightness(cache: tauri::State<'_, BrightnessCache>) -> Result<f32,
 }
 
 #[tauri::command]
-async fn set_brightness(cache: tauri::State<'_, BrightnessCache>, val: f32) -> Result<(), String> {
+async fn set_brightness(cache: tauri::State<'_, BrightnessCache>, val: f32) -> ControlResult<()> {
+    let val = validate::brightness(val)?;
     display::set_brightness(val).await?;
     if let Ok(mut v) = cache.val.lock() {
         *v = val;
@@ -336,21 +631,20 @@ async fn set_brightness(cache: tauri::State<'_, BrightnessCache>, val: f32) -> R
 }
 
 #[tauri::command]
-fn get_mouse_speed() -> u32 {
-    input::get_mouse_sensitivity().unwrap_or(10)
+fn get_mouse_speed() -> ControlResult<u32> {
+    input::get_mouse_sensitivity()
 }
 
 #[tauri::command]
-fn set_mouse_speed(val: u32) {
-    let _ = input::set_mouse_sensitivity(val);
+fn set_mouse_speed(val: u32) -> ControlResult<()> {
+    input::set_mouse_sensitivity(validate::mouse_speed(val)?)
 }
 
 #[tauri::command]
-async fn resize_window(app: tauri::AppHandle, height: f64) {
+async fn resize_window(app: tauri::AppHandle, height: f64) -> ControlResult<()> {
     let state = app.state::<AppState>();
     let mut cache = state.height_cache.lock().unwrap();
-    let old_cache = *cache;
-    *cache = height;
+    let old_cache = cache_height(&mut cache, height)?;
 
     if let Some(window) = app.get_webview_window("main") {
         let is_visible = window.is_visible().unwrap_or(false);
@@ -387,38 +681,116 @@ async fn resize_window(app: tauri::AppHandle, height: f64) {
             // }));
         }
     }
+    Ok(())
 }
 
-fn update_tray_icon_for_theme(app: &tauri::AppHandle, theme: Theme) {
-    let icon_bytes = match theme {
-        Theme::Light => ICON_BLACK_BYTES,
-        _ => ICON_WHITE_BYTES,
-    };
+/// Check a height asked for by the frontend and keep it as the window's,
+/// returning the height it replaces.
+fn cache_height(cache: &mut f64, height: f64) -> ControlResult<f64> {
+    let height = validate::window_height(height)?;
+    Ok(std::mem::replace(cache, height))
+}
 
+fn update_tray_icon_for_theme(app: &tauri::AppHandle, theme: Theme) {
     println!(
         "System Theme changed to: {:?}, loading from embedded bytes",
         theme
     );
+    *app.state::<AppState>().tray_theme.lock().unwrap() = theme;
+    refresh_tray_icon(app);
+}
 
-    if let Ok(icon) = Image::from_bytes(icon_bytes) {
-        if let Some(tray) = app.tray_by_id("main") {
-            let _ = tray.set_icon(Some(icon));
+/// The tray icon for `theme`, with a red dot in the corner while an app is
+/// recording.
+fn tray_icon(theme: Theme, recording: bool) -> Option<Image<'static>> {
+    let icon_bytes = match theme {
+        Theme::Light => ICON_BLACK_BYTES,
+        _ => ICON_WHITE_BYTES,
+    };
+    if !recording {
+        return Image::from_bytes(icon_bytes).ok();
+    }
+    let mut rgba = image::load_from_memory(icon_bytes).ok()?.to_rgba8();
+    let (w, h) = rgba.dimensions();
+    let r = w.min(h) as f32 * 0.22;
+    let (cx, cy) = (w as f32 - r - 1.0, h as f32 - r - 1.0);
+    for (x, y, px) in rgba.enumerate_pixels_mut() {
+        let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+        if dx * dx + dy * dy <= r * r {
+            *px = image::Rgba([0xe8, 0x11, 0x23, 0xff]);
         }
     }
+    Some(Image::new_owned(rgba.into_raw(), w, h))
+}
+
+/// Redraw the tray icon and tooltip for the current theme and recording apps.
+fn refresh_tray_icon(app: &tauri::AppHandle) {
+    let state = app.state::<AppState>();
+    let theme = *state.tray_theme.lock().unwrap();
+    let recording = state.recording.lock().unwrap().clone();
+    let Some(tray) = app.tray_by_id("main") else {
+        return;
+    };
+    if let Some(icon) = tray_icon(theme, !recording.is_empty()) {
+        let _ = tray.set_icon(Some(icon));
+    }
+    let tooltip =
+        (!recording.is_empty()).then(|| format!("Microphone in use by {}", recording.join(", ")));
+    let _ = tray.set_tooltip(tooltip);
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // Device switching re-runs this executable as a short-lived helper.
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(code) = audio::helper_main(&args) {
+        std::process::exit(code);
+    }
+
     tauri::Builder::default()
+        .plugin(tauri_plugin_notification::init())
+        .register_uri_scheme_protocol("icon", |ctx, request| {
+            let cache = ctx.app_handle().state::<Arc<audio::AppCache>>();
+            let name = request.uri().path().trim_start_matches('/');
+            let response = tauri::http::Response::builder();
+            match cache.icons.load(name) {
+                Some(png) => response
+                    .header("Content-Type", "image/png")
+                    // Keys change with the executable, so a file never does.
+                    .header("Cache-Control", "max-age=31536000, immutable")
+                    .body(png),
+                None => response.status(404).body(Vec::new()),
+            }
+            .unwrap()
+        })
         .setup(|app| {
-            let app_cache = Arc::new(audio::AppCache::new());
-            app.manage(audio::AudioState::new(app_cache.clone()));
-            app.manage(BrightnessCache {
-                val: Mutex::new(0.5),
-                last_fetch: AtomicU64::new(0),
-                is_fetching: AtomicBool::new(false),
-            });
-
+            let config_dir = app.path().app_config_dir()?;
+            let icons = icons::IconStore::new(app.path().app_cache_dir()?.join("icons"));
+            let helpers = audio::helpers::HelperRules::load(config_dir.join("helper_rules.json"))
+                .unwrap_or_else(|e| {
+                    println!("Using default helper rules: {}", e);
+                    audio::helpers::HelperRules::defaults()
+                });
+            let app_cache = Arc::new(audio::AppCache::new(icons, helpers));
+            app.manage(app_cache.clone());
+            let memory = audio::memory::VolumeMemory::load(config_dir.join("app_volumes.json"))
+                .unwrap_or_else(|e| {
+                    // Keep the broken file for the user instead of overwriting it.
+                    println!("Volume memory disabled: {}", e);
+                    audio::memory::VolumeMemory::in_memory()
+                });
+            let view = audio::view::ViewStore::load(config_dir.join("mixer_view.json"))
+                .unwrap_or_else(|e| {
+                    println!("Mixer view not saved: {}", e);
+                    audio::view::ViewStore::in_memory()
+                });
+            // Initial theme from registry (more reliable than window.theme() at start)
+            let theme = if is_light_mode_registry() {
+                Theme::Light
+            } else {
+                Theme::Dark
+            };
+            // Before the audio worker, whose first events already badge the tray.
             app.manage(AppState {
                 is_visible: AtomicBool::new(false),
                 last_blur: AtomicU64::new(0),
@@ -427,15 +799,41 @@ pub fn run() {
                 blur_style: Mutex::new(BlurStyle::MicaAlt), // Default to Mica Alt
                 last_tray_state: Mutex::new(None),
                 tray: Mutex::new(None),
+                tray_theme: Mutex::new(theme),
+                recording: Mutex::new(Vec::new()),
             });
 
-            // Background tray menu updater loop
-            let handle = app.handle().clone();
-            tauri::async_runtime::spawn(async move {
-                loop {
-                    tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-                    update_tray_menu(&handle).await;
-                }
+            let events_handle = app.handle().clone();
+            app.manage(audio::AudioState::new(
+                app_cache.clone(),
+                Arc::new(move |event| {
+                    if let audio::AudioEvent::DeviceSwitch(switch) = &event {
+                        notify_device_switch(&events_handle, switch);
+                    }
+                    if let audio::AudioEvent::Devices(devices) = &event {
+                        let h = events_handle.clone();
+                        let devices = devices.clone();
+                        tauri::async_runtime::spawn(async move {
+                            rebuild_tray_menu(&h, devices.playback, devices.capture);
+                        });
+                    }
+                    if let audio::AudioEvent::Recording(change) = &event {
+                        let state = events_handle.state::<AppState>();
+                        *state.recording.lock().unwrap() = change.recording_now.clone();
+                        refresh_tray_icon(&events_handle);
+                    }
+                    let _ = events_handle.emit(event.name(), &event);
+                }),
+                memory,
+                view,
+            ));
+            app.manage(audio::scene::SceneStore::new(
+                config_dir.join("scenes.json"),
+            ));
+            app.manage(BrightnessCache {
+                val: Mutex::new(0.5),
+                last_fetch: AtomicU64::new(0),
+                is_fetching: AtomicBool::new(false),
             });
 
             // Setup tray
@@ -466,18 +864,8 @@ pub fn run() {
                 });
             }
 
-            // Initial theme from registry (more reliable than window.theme() at start)
-            let theme = if is_light_mode_registry() {
-                Theme::Light
-            } else {
-                Theme::Dark
-            };
-            let icon_bytes = match theme {
-                Theme::Light => ICON_BLACK_BYTES,
-                _ => ICON_WHITE_BYTES,
-            };
-            let initial_icon = Image::from_bytes(icon_bytes)
-                .unwrap_or_else(|_| app.default_window_icon().unwrap().clone());
+            let initial_icon = tray_icon(theme, false)
+                .unwrap_or_else(|| app.default_window_icon().unwrap().clone());
 
             let _tray = TrayIconBuilder::with_id("main")
                 .icon(initial_icon)
@@ -492,17 +880,7 @@ pub fn run() {
                         let _ = set_autostart(!current);
                     } else if let Some(dev_id) = id_str.strip_prefix("out:") {
                         println!("Switching Playback to: {}", dev_id);
-                        let state = app.state::<audio::AudioState>();
-                        let _ = state
-                            .tx
-                            .send(audio::AudioRequest::SetDefaultDevice(dev_id.to_string()));
-
-                        // Trigger immediate update
-                        let h = app.clone();
-                        tauri::async_runtime::spawn(async move {
-                            tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
-                            update_tray_menu(&h).await;
-                        });
+                        spawn_tray_switch(app, dev_id, audio::DeviceRole::EVERYDAY.to_vec());
                     } else if let Some(style_str) = id_str.strip_prefix("style:") {
                         let new_style = match style_str {
                             "mica" => BlurStyle::Mica,
@@ -526,19 +904,46 @@ pub fn run() {
                         tauri::async_runtime::spawn(async move {
                             update_tray_menu(&h).await;
                         });
-                    } else if let Some(dev_id) = id_str.strip_prefix("in:") {
-                        println!("Switching Recording to: {}", dev_id);
-                        let state = app.state::<audio::AudioState>();
-                        let _ = state
-                            .tx
-                            .send(audio::AudioRequest::SetDefaultDevice(dev_id.to_string()));
-
-                        // Trigger immediate update
+                    } else if let Some(name) = id_str.strip_prefix("scene:apply:") {
                         let h = app.clone();
+                        let name = name.to_string();
                         tauri::async_runtime::spawn(async move {
-                            tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
-                            update_tray_menu(&h).await;
+                            if let Err(e) = apply_scene(h, name).await {
+                                println!("Failed to apply scene: {}", e);
+                            }
+                        });
+                    } else if let Some(name) = id_str.strip_prefix("scene:delete:") {
+                        let h = app.clone();
+                        let name = name.to_string();
+                        tauri::async_runtime::spawn(async move {
+                            if let Err(e) = delete_scene(h, name).await {
+                                println!("Failed to delete scene: {}", e);
+                            }
                         });
+                    } else if id_str == "scene:save" {
+                        let h = app.clone();
+                        tauri::async_runtime::spawn(async move {
+                            let taken: Vec<String> = h
+                                .state::<audio::scene::SceneStore>()
+                                .list()
+                                .unwrap_or_default()
+                                .into_iter()
+                                .map(|s| s.name)
+                                .collect();
+                            let name = (1..)
+                                .map(|n| format!("场景 {}", n))
+                                .find(|n| !taken.contains(n))
+                                .unwrap();
+                            if let Err(e) = save_scene(h, name).await {
+                                println!("Failed to save scene: {}", e);
+                            }
+                        });
+                    } else if let Some(dev_id) = id_str.strip_prefix("comm:") {
+                        println!("Switching Communications device to: {}", dev_id);
+                        spawn_tray_switch(app, dev_id, vec![audio::DeviceRole::Communications]);
+                    } else if let Some(dev_id) = id_str.strip_prefix("in:") {
+                        println!("Switching Recording to: {}", dev_id);
+                        spawn_tray_switch(app, dev_id, audio::DeviceRole::EVERYDAY.to_vec());
                     }
                 })
                 .on_tray_icon_event(|tray, event| {
@@ -547,7 +952,8 @@ pub fn run() {
                             button: MouseButton::Right,
                             ..
                         } => {
-                            // Menu is updated in background loop
+                            // The menu is rebuilt on every `audio://devices-changed`
+                            // event, so it is already current.
                         }
                         TrayIconEvent::Click {
                             button: MouseButton::Left,
@@ -633,6 +1039,8 @@ pub fn run() {
             if let Some(state) = app.try_state::<AppState>() {
                 *state.tray.lock().unwrap() = Some(_tray);
             }
+            // Badge for apps that were already recording while the tray was built.
+            refresh_tray_icon(app.handle());
 
             if let Some(window) = app.get_webview_window("main") {
                 let w = window.clone();
@@ -685,6 +1093,28 @@ pub fn run() {
             get_app_volumes,
             set_app_volume,
             set_app_mute,
+            get_capture_apps,
+            set_capture_app_volume,
+            set_capture_app_mute,
+            set_app_group_volume,
+            set_app_group_mute,
+            set_app_output_device,
+            reset_app_output_device,
+            set_default_device,
+            set_level_meter_rate,
+            get_audio_queue_stats,
+            get_backend_health,
+            get_cache_stats,
+            get_helper_rules,
+            set_helper_rules,
+            get_volume_memory_exclusions,
+            set_volume_memory_excluded,
+            get_mixer_view,
+            set_mixer_view,
+            get_scenes,
+            save_scene,
+            apply_scene,
+            delete_scene,
             get_brightness,
             set_brightness,
             get_mouse_speed,
@@ -697,27 +1127,40 @@ pub fn run() {
 
 async fn update_tray_menu(app_handle: &tauri::AppHandle) {
     let audio_state = app_handle.state::<audio::AudioState>();
-    let (tx, rx) = tokio::sync::oneshot::channel();
-    let _ = audio_state
-        .tx
-        .send(audio::AudioRequest::GetPlaybackDevices(tx));
-    let out_devs = rx.await.ok().and_then(|r| r.ok()).unwrap_or_default();
-
-    let (tx, rx) = tokio::sync::oneshot::channel();
-    let _ = audio_state
-        .tx
-        .send(audio::AudioRequest::GetCaptureDevices(tx));
-    let in_devs = rx.await.ok().and_then(|r| r.ok()).unwrap_or_default();
+    let out_devs = audio_state
+        .request(audio::AudioRequest::GetPlaybackDevices)
+        .await
+        .unwrap_or_default();
+    let in_devs = audio_state
+        .request(audio::AudioRequest::GetCaptureDevices)
+        .await
+        .unwrap_or_default();
+    rebuild_tray_menu(app_handle, out_devs, in_devs);
+}
 
+fn rebuild_tray_menu(
+    app_handle: &tauri::AppHandle,
+    out_devs: Vec<audio::AudioDevice>,
+    in_devs: Vec<audio::AudioDevice>,
+) {
     let is_auto = get_autostart();
     let app_state = app_handle.state::<AppState>();
     let current_style = *app_state.blur_style.lock().unwrap();
 
+    let scenes: Vec<String> = app_handle
+        .state::<audio::scene::SceneStore>()
+        .list()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|s| s.name)
+        .collect();
+
     let new_state = LastTrayState {
         out_devs: out_devs.clone(),
         in_devs: in_devs.clone(),
         autostart: is_auto,
         blur_style: current_style,
+        scenes: scenes.clone(),
     };
 
     {
@@ -731,35 +1174,49 @@ async fn update_tray_menu(app_handle: &tauri::AppHandle) {
         *last = Some(new_state);
     }
 
-    let out_menu = Submenu::new(app_handle, "播放设备", true).unwrap();
-    for d in out_devs {
-        let _ = out_menu.append(
-            &CheckMenuItem::with_id(
+    let out_menu = device_menu(app_handle, "播放设备", "out:", &out_devs);
+    let in_menu = device_menu(app_handle, "录音设备", "in:", &in_devs);
+
+    let scene_menu = Submenu::new(app_handle, "音频场景", true).unwrap();
+    for name in &scenes {
+        let _ = scene_menu.append(
+            &MenuItem::with_id(
                 app_handle,
-                format!("out:{}", d.id),
-                &d.name,
+                format!("scene:apply:{}", name),
+                name,
                 true,
-                d.is_default,
                 None::<&str>,
             )
             .unwrap(),
         );
     }
-
-    let in_menu = Submenu::new(app_handle, "录音设备", true).unwrap();
-    for d in in_devs {
-        let _ = in_menu.append(
-            &CheckMenuItem::with_id(
+    if !scenes.is_empty() {
+        let _ = scene_menu.append(&PredefinedMenuItem::separator(app_handle).unwrap());
+    }
+    let _ = scene_menu.append(
+        &MenuItem::with_id(
+            app_handle,
+            "scene:save",
+            "保存当前为新场景",
+            true,
+            None::<&str>,
+        )
+        .unwrap(),
+    );
+    let delete_menu = Submenu::new(app_handle, "删除场景", !scenes.is_empty()).unwrap();
+    for name in &scenes {
+        let _ = delete_menu.append(
+            &MenuItem::with_id(
                 app_handle,
-                format!("in:{}", d.id),
-                &d.name,
+                format!("scene:delete:{}", name),
+                name,
                 true,
-                d.is_default,
                 None::<&str>,
             )
             .unwrap(),
         );
     }
+    let _ = scene_menu.append(&delete_menu);
 
     let style_menu = Submenu::new(app_handle, "模糊样式", true).unwrap();
     let _ = style_menu.append(
@@ -810,7 +1267,14 @@ async fn update_tray_menu(app_handle: &tauri::AppHandle) {
 
     let menu = Menu::with_items(
         app_handle,
-        &[&out_menu, &in_menu, &style_menu, &auto_item, &quit_item],
+        &[
+            &out_menu,
+            &in_menu,
+            &scene_menu,
+            &style_menu,
+            &auto_item,
+            &quit_item,
+        ],
     )
     .unwrap();
 
@@ -819,6 +1283,70 @@ async fn update_tray_menu(app_handle: &tauri::AppHandle) {
     }
 }
 
+/// One device submenu: the everyday default on top, then, where the backend
+/// keeps one, a separate section for the communications default used by call
+/// apps.
+fn device_menu(
+    app_handle: &tauri::AppHandle,
+    title: &str,
+    prefix: &str,
+    devs: &[audio::AudioDevice],
+) -> Submenu<tauri::Wry> {
+    let menu = Submenu::new(app_handle, title, true).unwrap();
+    for d in devs {
+        let _ = menu.append(
+            &CheckMenuItem::with_id(
+                app_handle,
+                format!("{}{}", prefix, d.id),
+                &d.name,
+                true,
+                d.is_default,
+                None::<&str>,
+            )
+            .unwrap(),
+        );
+    }
+    if devs.is_empty() || !audio::SEPARATE_ROLES {
+        return menu;
+    }
+    let _ = menu.append(&PredefinedMenuItem::separator(app_handle).unwrap());
+    let _ = menu.append(
+        &MenuItem::with_id(
+            app_handle,
+            format!("{}comm-header", prefix),
+            "通讯设备",
+            false,
+            None::<&str>,
+        )
+        .unwrap(),
+    );
+    for d in devs {
+        let _ = menu.append(
+            &CheckMenuItem::with_id(
+                app_handle,
+                format!("comm:{}", d.id),
+                &d.name,
+                true,
+                d.default_roles.contains(&audio::DeviceRole::Communications),
+                None::<&str>,
+            )
+            .unwrap(),
+        );
+    }
+    menu
+}
+
+#[cfg(not(target_os = "windows"))]
+fn get_autostart() -> bool {
+    false
+}
+
+#[cfg(not(target_os = "windows"))]
+fn set_autostart(_enable: bool) -> Result<(), String> {
+    Err("Autostart is only supported on Windows".into())
+}
+
+#[cfg(target_os = "windows")]
 fn get_autostart() -> bool {
     let hkcu = RegKey::predef(HKEY_CURRENT_USER);
     if let Ok(run) = hkcu.open_subkey("Software\\Microsoft\\Windows\\CurrentVersion\\Run") {
@@ -829,6 +1357,7 @@ fn get_autostart() -> bool {
     false
 }
 
+#[cfg(target_os = "windows")]
 fn set_autostart(enable: bool) -> Result<(), String> {
     let hkcu = RegKey::predef(HKEY_CURRENT_USER);
     let run = hkcu
@@ -849,3 +1378,33 @@ fn set_autostart(enable: bool) -> Result<(), String> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize_keeps_only_checked_heights() {
+        let edges = [
+            f64::NAN,
+            f64::NEG_INFINITY,
+            -1.0,
+            0.0,
+            validate::MAX_WINDOW_HEIGHT + 1.0,
+            f64::INFINITY,
+        ];
+        for height in edges {
+            let mut cached = 400.0;
+            let res = cache_height(&mut cached, height);
+            assert!(
+                matches!(res, Err(ControlError::InvalidArgument(_))),
+                "{} accepted",
+                height
+            );
+            assert_eq!(cached, 400.0, "{} was kept", height);
+        }
+        let mut cached = 400.0;
+        assert_eq!(cache_height(&mut cached, 520.5).unwrap(), 400.0);
+        assert_eq!(cached, 520.5);
+    }
+}
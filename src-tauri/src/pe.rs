@@ -0,0 +1,421 @@
+//! Reads icons and version strings straight from a PE file's resources, so
+//! names and icons do not depend on GDI or on the file's own platform. Only
+//! the headers and the resource section are read, not the whole image.
+
+use crate::icons::IconImage;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const RT_ICON: u32 = 3;
+const RT_GROUP_ICON: u32 = 14;
+const RT_VERSION: u32 = 16;
+const IMAGE_DIRECTORY_ENTRY_RESOURCE: usize = 2;
+/// Directory levels of a resource tree: type, name and language.
+const RESOURCE_LEVELS: usize = 3;
+/// Resource sections beyond this are not worth reading for a row label.
+const MAX_RESOURCE_SECTION: u32 = 64 * 1024 * 1024;
+
+/// Strings of the `StringFileInfo` table that best matches the file's
+/// declared translation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VersionInfo {
+    pub file_description: Option<String>,
+    pub product_name: Option<String>,
+    pub company_name: Option<String>,
+}
+
+impl VersionInfo {
+    /// What Windows' own mixer shows: the description, else the product.
+    pub fn display_name(&self) -> Option<&str> {
+        self.file_description
+            .as_deref()
+            .or(self.product_name.as_deref())
+    }
+}
+
+fn u16_at(data: &[u8], off: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(off..off + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], off: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(off..off + 4)?.try_into().ok()?))
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// The resource section of a PE file, addressed by RVA.
+pub struct Resources {
+    data: Vec<u8>,
+    /// RVA of `data[0]`.
+    base: u32,
+    /// Offset of the root resource directory within `data`.
+    root: usize,
+}
+
+impl Resources {
+    pub fn load(path: &Path) -> Option<Self> {
+        Self::read(&mut BufReader::new(File::open(path).ok()?))
+    }
+
+    pub fn read<R: Read + Seek>(r: &mut R) -> Option<Self> {
+        let mut dos = [0u8; 64];
+        r.read_exact(&mut dos).ok()?;
+        if &dos[..2] != b"MZ" {
+            return None;
+        }
+        let pe = u32_at(&dos, 0x3c)? as u64;
+        // Signature, COFF header, and as much optional header as we use.
+        let mut head = [0u8; 24 + 240];
+        r.seek(SeekFrom::Start(pe)).ok()?;
+        r.read_exact(&mut head).ok()?;
+        if &head[..4] != b"PE\0\0" {
+            return None;
+        }
+        let sections = u16_at(&head, 6)? as usize;
+        let optional_size = u16_at(&head, 20)? as usize;
+        let optional = &head[24..];
+        let (count_at, dirs_at) = match u16_at(optional, 0)? {
+            0x10b => (92, 96),
+            0x20b => (108, 112),
+            _ => return None,
+        };
+        if u32_at(optional, count_at)? as usize <= IMAGE_DIRECTORY_ENTRY_RESOURCE {
+            return None;
+        }
+        let dir = dirs_at + IMAGE_DIRECTORY_ENTRY_RESOURCE * 8;
+        let rsrc_rva = u32_at(optional, dir)?;
+        if rsrc_rva == 0 {
+            return None;
+        }
+
+        let mut table = vec![0u8; sections * 40];
+        r.seek(SeekFrom::Start(pe + 24 + optional_size as u64))
+            .ok()?;
+        r.read_exact(&mut table).ok()?;
+        let section = table.chunks_exact(40).find(|s| {
+            let (Some(vsize), Some(va), Some(raw)) = (u32_at(s, 8), u32_at(s, 12), u32_at(s, 16))
+            else {
+                return false;
+            };
+            (va..va.saturating_add(vsize.max(raw))).contains(&rsrc_rva)
+        })?;
+        let va = u32_at(section, 12)?;
+        let raw_size = u32_at(section, 16)?;
+        let raw_ptr = u32_at(section, 20)?;
+        if raw_size > MAX_RESOURCE_SECTION {
+            return None;
+        }
+        let mut data = vec![0u8; raw_size as usize];
+        r.seek(SeekFrom::Start(raw_ptr as u64)).ok()?;
+        r.read_exact(&mut data).ok()?;
+        Some(Self {
+            data,
+            base: va,
+            root: (rsrc_rva - va) as usize,
+        })
+    }
+
+    /// Bytes at an RVA, as referenced by resource data entries.
+    fn at_rva(&self, rva: u32, len: u32) -> Option<&[u8]> {
+        let start = rva.checked_sub(self.base)? as usize;
+        self.data.get(start..start.checked_add(len as usize)?)
+    }
+
+    /// Entries of the directory at `dir` (relative to the root) as
+    /// `(id, offset, is_directory)`. Named entries get no id.
+    fn entries(&self, dir: usize) -> Vec<(Option<u32>, usize, bool)> {
+        let at = self.root + dir;
+        let (Some(named), Some(ids)) = (u16_at(&self.data, at + 12), u16_at(&self.data, at + 14))
+        else {
+            return Vec::new();
+        };
+        (0..named as usize + ids as usize)
+            .filter_map(|i| {
+                let e = at + 16 + i * 8;
+                let name = u32_at(&self.data, e)?;
+                let target = u32_at(&self.data, e + 4)?;
+                let id = (name & 0x8000_0000 == 0).then_some(name);
+                Some((
+                    id,
+                    (target & 0x7fff_ffff) as usize,
+                    target & 0x8000_0000 != 0,
+                ))
+            })
+            .collect()
+    }
+
+    /// Data of a resource leaf: the first language of the given entry.
+    /// `entered` are the directories passed on the way, root first.
+    fn leaf(&self, mut offset: usize, mut is_dir: bool, entered: &[usize]) -> Option<&[u8]> {
+        let mut entered = entered.to_vec();
+        // Descend through the levels that remain. A directory below the
+        // language level, or one already entered, means a broken or looping
+        // tree.
+        while is_dir {
+            if entered.len() == RESOURCE_LEVELS || entered.contains(&offset) {
+                return None;
+            }
+            entered.push(offset);
+            (_, offset, is_dir) = *self.entries(offset).first()?;
+        }
+        let entry = self.root + offset;
+        self.at_rva(u32_at(&self.data, entry)?, u32_at(&self.data, entry + 4)?)
+    }
+
+    /// Every resource of a type, in directory order, by id or name.
+    fn of_type(&self, kind: u32) -> Vec<(Option<u32>, &[u8])> {
+        let Some(&(_, names, true)) = self.entries(0).iter().find(|e| e.0 == Some(kind)) else {
+            return Vec::new();
+        };
+        if names == 0 {
+            return Vec::new();
+        }
+        self.entries(names)
+            .into_iter()
+            .filter_map(|(id, offset, is_dir)| Some((id, self.leaf(offset, is_dir, &[0, names])?)))
+            .collect()
+    }
+
+    pub fn version_info(&self) -> Option<VersionInfo> {
+        let (_, data) = self.of_type(RT_VERSION).into_iter().next()?;
+        parse_version_info(data)
+    }
+
+    /// The image closest to `size` pixels of the icon group `index` refers
+    /// to: the smallest at least that big, else the largest. Groups are
+    /// counted the way `ExtractIconEx` counts them, from zero in directory
+    /// order, or negated to name a resource id.
+    pub fn icon_at(&self, index: i32, size: u32) -> Option<IconImage> {
+        let groups = self.of_type(RT_GROUP_ICON);
+        let (_, group) = if index < 0 {
+            let id = index.unsigned_abs();
+            groups.into_iter().find(|(i, _)| *i == Some(id))?
+        } else {
+            groups.into_iter().nth(index as usize)?
+        };
+        let count = u16_at(group, 4)? as usize;
+        // GRPICONDIRENTRY: width, height, colors, reserved, planes,
+        // bit count, bytes in resource, icon id. Width 0 means 256.
+        let entries: Vec<&[u8]> = (0..count)
+            .filter_map(|i| group.get(6 + i * 14..6 + (i + 1) * 14))
+            .collect();
+        let width = |e: &[u8]| if e[0] == 0 { 256 } else { e[0] as u32 };
+        let depth = |e: &[u8]| u16_at(e, 6).unwrap_or(0);
+        let best = entries
+            .iter()
+            .filter(|e| width(e) >= size)
+            .min_by_key(|e| (width(e), std::cmp::Reverse(depth(e))))
+            .or_else(|| entries.iter().max_by_key(|e| (width(e), depth(e))))?;
+        let id = u16_at(best, 12)? as u32;
+        let icons = self.of_type(RT_ICON);
+        let (_, image) = icons.into_iter().find(|(i, _)| *i == Some(id))?;
+        decode_icon(&best[..12], image)
+    }
+}
+
+/// Wrap one `RT_ICON` image in a single-entry .ico file, which handles
+/// both the PNG and the DIB form.
+fn decode_icon(entry: &[u8], image: &[u8]) -> Option<IconImage> {
+    let mut ico = Vec::with_capacity(22 + image.len());
+    ico.extend_from_slice(&[0, 0, 1, 0, 1, 0]);
+    ico.extend_from_slice(&entry[..8]);
+    ico.extend_from_slice(&(image.len() as u32).to_le_bytes());
+    ico.extend_from_slice(&22u32.to_le_bytes());
+    ico.extend_from_slice(image);
+    let img = image::load_from_memory_with_format(&ico, image::ImageFormat::Ico)
+        .ok()?
+        .into_rgba8();
+    Some(IconImage {
+        width: img.width(),
+        height: img.height(),
+        rgba: img.into_raw(),
+    })
+}
+
+/// A `VS_VERSIONINFO` node: `wLength`, `wValueLength`, `wType`, a
+/// NUL-terminated UTF-16 key, then the value and children, each aligned to
+/// four bytes.
+struct Block<'a> {
+    key: String,
+    value: &'a [u8],
+    children: &'a [u8],
+}
+
+fn utf16(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&u| u != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Parse the block at the start of `data`, returning it and its length.
+fn block(data: &[u8]) -> Option<(Block<'_>, usize)> {
+    let len = u16_at(data, 0)? as usize;
+    let value_len = u16_at(data, 2)? as usize;
+    let text = u16_at(data, 4)? == 1;
+    let data = data.get(..len)?;
+    let key_end = (6..len).step_by(2).find(|&i| u16_at(data, i) == Some(0))?;
+    let key = utf16(&data[6..key_end]);
+    let value_at = align4(key_end + 2).min(len);
+    let value_bytes = if text { value_len * 2 } else { value_len };
+    let value_end = (value_at + value_bytes).min(len);
+    let children_at = align4(value_end).min(len);
+    Some((
+        Block {
+            key,
+            value: &data[value_at..value_end],
+            children: &data[children_at..],
+        },
+        len,
+    ))
+}
+
+fn children(mut data: &[u8]) -> Vec<Block<'_>> {
+    let mut out = Vec::new();
+    while let Some((b, len)) = block(data) {
+        if len == 0 {
+            break;
+        }
+        out.push(b);
+        data = data.get(align4(len)..).unwrap_or_default();
+    }
+    out
+}
+
+fn parse_version_info(data: &[u8]) -> Option<VersionInfo> {
+    let (root, _) = block(data)?;
+    if root.key != "VS_VERSION_INFO" {
+        return None;
+    }
+    let sections = children(root.children);
+    // Language and code page pairs, as the 8 hex digit table keys spell them.
+    let translations: Vec<String> = sections
+        .iter()
+        .filter(|b| b.key == "VarFileInfo")
+        .flat_map(|b| children(b.children))
+        .filter(|b| b.key == "Translation")
+        .flat_map(|b| {
+            b.value
+                .chunks_exact(4)
+                .map(|c| {
+                    let lang = u16::from_le_bytes([c[0], c[1]]);
+                    let page = u16::from_le_bytes([c[2], c[3]]);
+                    format!("{:04x}{:04x}", lang, page)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let tables: Vec<Block> = sections
+        .iter()
+        .filter(|b| b.key == "StringFileInfo")
+        .flat_map(|b| children(b.children))
+        .collect();
+    let table = translations
+        .iter()
+        .find_map(|t| tables.iter().find(|b| b.key.eq_ignore_ascii_case(t)))
+        .or_else(|| tables.first())?;
+    let mut info = VersionInfo::default();
+    for s in children(table.children) {
+        let value = utf16(s.value).trim().to_string();
+        if value.is_empty() {
+            continue;
+        }
+        match s.key.as_str() {
+            "FileDescription" => info.file_description = Some(value),
+            "ProductName" => info.product_name = Some(value),
+            "CompanyName" => info.company_name = Some(value),
+            _ => {}
+        }
+    }
+    Some(info)
+}
+
+/// The fixtures are written by `tests/fixtures/pe/make.py`.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const APP: &[u8] = include_bytes!("../tests/fixtures/pe/app.dll");
+    const TRUNCATED: &[u8] = include_bytes!("../tests/fixtures/pe/truncated.dll");
+    const CYCLIC: &[u8] = include_bytes!("../tests/fixtures/pe/cyclic.dll");
+
+    fn resources(file: &[u8]) -> Resources {
+        Resources::read(&mut Cursor::new(file)).expect("resource section")
+    }
+
+    /// Size and first pixel of the chosen image.
+    fn icon(file: &[u8], index: i32, size: u32) -> Option<(u32, [u8; 4])> {
+        let img = resources(file).icon_at(index, size)?;
+        assert_eq!(img.width, img.height);
+        Some((img.width, img.rgba[..4].try_into().unwrap()))
+    }
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    #[test]
+    fn version_strings() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/pe/app.dll");
+        let info = Resources::load(&path).unwrap().version_info().unwrap();
+        assert_eq!(
+            info,
+            VersionInfo {
+                file_description: Some("Fixture Player".into()),
+                product_name: Some("Fixture Suite".into()),
+                company_name: Some("Example Corp".into()),
+            }
+        );
+        assert_eq!(info.display_name(), Some("Fixture Player"));
+    }
+
+    #[test]
+    fn icon_closest_to_the_size_asked_for() {
+        assert_eq!(icon(APP, 0, 16), Some((16, RED)));
+        assert_eq!(icon(APP, 0, 24), Some((32, BLUE)));
+        assert_eq!(icon(APP, 0, 32), Some((32, BLUE)));
+        // Nothing big enough: the largest there is.
+        assert_eq!(icon(APP, 0, 256), Some((32, BLUE)));
+    }
+
+    #[test]
+    fn icon_groups_by_index_and_by_id() {
+        assert_eq!(icon(APP, 1, 32), Some((16, RED)));
+        assert_eq!(icon(APP, -101, 32), Some((32, BLUE)));
+        assert_eq!(icon(APP, -102, 32), Some((16, RED)));
+        assert_eq!(icon(APP, 2, 16), None);
+        assert_eq!(icon(APP, -103, 16), None);
+    }
+
+    #[test]
+    fn cut_off_files_are_rejected() {
+        for len in 0..APP.len() {
+            assert!(
+                Resources::read(&mut Cursor::new(&APP[..len])).is_none(),
+                "read {} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn resources_running_past_the_section_are_ignored() {
+        let r = resources(TRUNCATED);
+        assert_eq!(r.version_info(), None);
+        assert!(r.icon_at(0, 16).is_none());
+        assert!(r.icon_at(-101, 16).is_none());
+    }
+
+    #[test]
+    fn looping_and_too_deep_directories_end_the_walk() {
+        let r = resources(CYCLIC);
+        assert_eq!(r.version_info(), None);
+        assert!(r.icon_at(0, 16).is_none());
+        assert!(r.icon_at(-101, 16).is_none());
+    }
+}
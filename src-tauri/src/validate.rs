@@ -0,0 +1,340 @@
+//! Argument checks shared by the command handlers. Everything that reaches
+//! a backend passes through here first, so backends can assume their
+//! documented domain: volumes and brightness in 0..=1, mouse speed in 1..=20.
+
+use crate::audio::scene::Scene;
+use crate::audio::view::MixerView;
+use crate::audio::MAX_METER_RATE_HZ;
+use crate::error::{ControlError, ControlResult};
+use std::ops::RangeInclusive;
+
+pub const MOUSE_SPEED: RangeInclusive<u32> = 1..=20;
+/// Taller than any screen the flyout is shown on; the frontend asks for
+/// the height of its content.
+pub const MAX_WINDOW_HEIGHT: f64 = 8192.0;
+
+fn invalid(message: String) -> ControlError {
+    ControlError::InvalidArgument(message)
+}
+
+/// A fraction in 0..=1. NaN and infinities are rejected, as is anything
+/// outside the range; `-0.0` is normalized to `0.0`.
+pub fn unit(what: &str, value: f32) -> ControlResult<f32> {
+    if !value.is_finite() {
+        return Err(invalid(format!("{} must be a number, got {}", what, value)));
+    }
+    if !(0.0..=1.0).contains(&value) {
+        return Err(invalid(format!(
+            "{} must be between 0 and 1, got {}",
+            what, value
+        )));
+    }
+    Ok(value + 0.0)
+}
+
+pub fn volume(value: f32) -> ControlResult<f32> {
+    unit("volume", value)
+}
+
+pub fn brightness(value: f32) -> ControlResult<f32> {
+    unit("brightness", value)
+}
+
+pub fn mouse_speed(value: u32) -> ControlResult<u32> {
+    if !MOUSE_SPEED.contains(&value) {
+        return Err(invalid(format!(
+            "mouse speed must be between {} and {}, got {}",
+            MOUSE_SPEED.start(),
+            MOUSE_SPEED.end(),
+            value
+        )));
+    }
+    Ok(value)
+}
+
+/// Names, groups and device ids must have something besides whitespace.
+pub fn non_empty(what: &str, value: String) -> ControlResult<String> {
+    if value.trim().is_empty() {
+        return Err(invalid(format!("{} must not be empty", what)));
+    }
+    Ok(value)
+}
+
+/// A list that must name at least one item.
+pub fn non_empty_list<T>(what: &str, values: Vec<T>) -> ControlResult<Vec<T>> {
+    if values.is_empty() {
+        return Err(invalid(format!("{} must not be empty", what)));
+    }
+    Ok(values)
+}
+
+pub fn window_height(value: f64) -> ControlResult<f64> {
+    if !value.is_finite() || value <= 0.0 {
+        return Err(invalid(format!(
+            "window height must be a positive number, got {}",
+            value
+        )));
+    }
+    if value > MAX_WINDOW_HEIGHT {
+        return Err(invalid(format!(
+            "window height must be at most {}, got {}",
+            MAX_WINDOW_HEIGHT, value
+        )));
+    }
+    Ok(value)
+}
+
+/// Peak meter samples per second; 0 turns the meter off.
+pub fn meter_rate(hz: u32) -> ControlResult<u32> {
+    if hz > MAX_METER_RATE_HZ {
+        return Err(invalid(format!(
+            "meter rate must be at most {} Hz, got {}",
+            MAX_METER_RATE_HZ, hz
+        )));
+    }
+    Ok(hz)
+}
+
+/// A cut-off of zero minutes would hide every row the moment it pauses.
+pub fn mixer_view(view: &MixerView) -> ControlResult<()> {
+    if view.hide_inactive_after_mins == Some(0) {
+        return Err(invalid(
+            "inactive rows must be hidden after at least 1 minute".to_string(),
+        ));
+    }
+    for group in &view.pinned {
+        non_empty("pinned group", group.clone())?;
+    }
+    Ok(())
+}
+
+/// Scenes come from a file the user may have edited by hand.
+pub fn scene(scene: &Scene) -> ControlResult<()> {
+    volume(scene.master.volume)?;
+    if let Some(mic) = scene.mic {
+        volume(mic.volume)?;
+    }
+    for app in &scene.apps {
+        volume(app.volume).map_err(|e| invalid(format!("{} in scene app {}", e, app.name)))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::mock::MockBackend;
+    use crate::audio::tests::{block_on, Harness};
+    use crate::audio::AudioRequest;
+
+    /// xorshift64*, so failures reproduce without a seed being printed.
+    struct Gen(u64);
+
+    impl Gen {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        /// The edge cases, then values near the range, then arbitrary bit
+        /// patterns (which include NaN payloads and subnormals).
+        fn floats(&mut self, n: usize) -> Vec<f32> {
+            let mut out = vec![
+                f32::NAN,
+                -f32::NAN,
+                f32::INFINITY,
+                f32::NEG_INFINITY,
+                -0.0,
+                0.0,
+                1.0,
+                -f32::MIN_POSITIVE,
+                1.0 + f32::EPSILON,
+                f32::MAX,
+                f32::MIN,
+            ];
+            for i in 0..n {
+                let bits = self.next() as u32;
+                out.push(if i % 2 == 0 {
+                    (bits as f32 / u32::MAX as f32) * 3.0 - 1.0
+                } else {
+                    f32::from_bits(bits)
+                });
+            }
+            out
+        }
+    }
+
+    fn in_unit(v: f32) -> bool {
+        v.is_finite() && (0.0..=1.0).contains(&v)
+    }
+
+    /// Through the helper every volume command uses.
+    fn set_volume(
+        h: &Harness,
+        v: f32,
+        make: fn(f32, tokio::sync::oneshot::Sender<ControlResult<()>>) -> AudioRequest,
+    ) -> ControlResult<()> {
+        block_on(h.state.request_volume(v, make))
+    }
+
+    /// Every volume request either reaches the backend with the value
+    /// unchanged or is rejected before the worker sees it.
+    fn check_volume_command(
+        call: &str,
+        make: fn(f32, tokio::sync::oneshot::Sender<ControlResult<()>>) -> AudioRequest,
+    ) {
+        let h = Harness::new(
+            MockBackend::new()
+                .with_session(10, "player", 0.5)
+                .with_capture("mic", "Microphone", true),
+        );
+        h.mock.add_recording(20, "voice", 0.5);
+        for v in Gen(0x5eed).floats(200) {
+            let before = h.writes();
+            let result = set_volume(&h, v, make);
+            let after = h.writes();
+            if in_unit(v) {
+                assert!(result.is_ok(), "{} rejected: {:?}", v, result);
+                assert_eq!(after.len(), before.len() + 1, "{}", v);
+                assert!(after.last().unwrap().starts_with(call), "{:?}", after);
+            } else {
+                assert!(
+                    matches!(result, Err(ControlError::InvalidArgument(_))),
+                    "{} accepted",
+                    v
+                );
+                assert_eq!(after, before, "{} reached the backend", v);
+            }
+        }
+    }
+
+    #[test]
+    fn master_volume_rejects_what_is_not_a_fraction() {
+        check_volume_command("set_master_volume", AudioRequest::SetMasterVolume);
+    }
+
+    #[test]
+    fn mic_volume_rejects_what_is_not_a_fraction() {
+        check_volume_command("set_mic_volume", AudioRequest::SetMicVolume);
+    }
+
+    #[test]
+    fn app_volume_rejects_what_is_not_a_fraction() {
+        check_volume_command("set_app_volume 10", |v, tx| {
+            AudioRequest::SetAppVolume(10, v, tx)
+        });
+    }
+
+    #[test]
+    fn capture_app_volume_rejects_what_is_not_a_fraction() {
+        check_volume_command("set_capture_app_volume 20", |v, tx| {
+            AudioRequest::SetCaptureAppVolume(20, v, tx)
+        });
+    }
+
+    #[test]
+    fn negative_zero_volume_is_sent_as_zero() {
+        let h = Harness::new(MockBackend::new());
+        set_volume(&h, -0.0, AudioRequest::SetMasterVolume).unwrap();
+        let sent = h.mock.mixer().master.0;
+        assert!(sent == 0.0 && sent.is_sign_positive());
+    }
+
+    #[test]
+    fn brightness_accepts_exactly_the_unit_range() {
+        for v in Gen(0xb1e55).floats(1000) {
+            match brightness(v) {
+                Ok(b) => {
+                    assert!(in_unit(v), "{} accepted", v);
+                    assert!(b == v && b.is_sign_positive(), "{} became {}", v, b);
+                }
+                Err(e) => {
+                    assert!(!in_unit(v), "{} rejected", v);
+                    assert!(matches!(e, ControlError::InvalidArgument(_)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mouse_speed_accepts_exactly_1_to_20() {
+        let mut g = Gen(0x30053);
+        let edges = [0, 1, 20, 21, u32::MAX];
+        let random = (0..1000).map(|i| match i % 2 {
+            0 => g.next() as u32 % 40,
+            _ => g.next() as u32,
+        });
+        for v in edges.into_iter().chain(random) {
+            match mouse_speed(v) {
+                Ok(s) => assert!(MOUSE_SPEED.contains(&v) && s == v, "{} accepted", v),
+                Err(_) => assert!(!MOUSE_SPEED.contains(&v), "{} rejected", v),
+            }
+        }
+    }
+
+    #[test]
+    fn group_volume_rejects_what_is_not_a_fraction() {
+        check_volume_command("set_app_volume 10", |v, tx| {
+            AudioRequest::SetGroupVolume("/mock/player".into(), v, tx)
+        });
+    }
+
+    #[test]
+    fn meter_rate_is_checked_before_the_worker_sees_it() {
+        let h = Harness::new(MockBackend::new());
+        // Past the worker's own startup notifications.
+        h.request(AudioRequest::GetMasterVolume).unwrap();
+        let mut g = Gen(0x3e7e);
+        let edges = [0, 1, MAX_METER_RATE_HZ, MAX_METER_RATE_HZ + 1, u32::MAX];
+        let random = (0..200).map(|i| match i % 2 {
+            0 => g.next() as u32 % (2 * MAX_METER_RATE_HZ),
+            _ => g.next() as u32,
+        });
+        for hz in edges.into_iter().chain(random) {
+            let before = h.state.queue_stats().received;
+            let result = block_on(h.state.request_meter_rate(hz));
+            let received = h.state.queue_stats().received - before;
+            if hz <= MAX_METER_RATE_HZ {
+                assert!(result.is_ok(), "{} rejected: {:?}", hz, result);
+                assert_eq!(received, 1, "{}", hz);
+            } else {
+                assert!(
+                    matches!(result, Err(ControlError::InvalidArgument(_))),
+                    "{} accepted",
+                    hz
+                );
+                assert_eq!(received, 0, "{} reached the worker", hz);
+            }
+        }
+    }
+
+    #[test]
+    fn window_height_accepts_positive_finite_values() {
+        let mut g = Gen(0x4e16);
+        let edges = [
+            f64::NAN,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            -0.0,
+            0.0,
+            f64::MIN_POSITIVE,
+            -1.0,
+            MAX_WINDOW_HEIGHT,
+            MAX_WINDOW_HEIGHT + 0.5,
+            f64::MAX,
+        ];
+        let random = (0..1000).map(|i| match i % 2 {
+            0 => (g.next() as f64 / u64::MAX as f64) * 2.0 * MAX_WINDOW_HEIGHT - 500.0,
+            _ => f64::from_bits(g.next()),
+        });
+        for v in edges.into_iter().chain(random) {
+            let valid = v.is_finite() && v > 0.0 && v <= MAX_WINDOW_HEIGHT;
+            match window_height(v) {
+                Ok(h) => assert!(valid && h == v, "{} accepted", v),
+                Err(_) => assert!(!valid, "{} rejected", v),
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+#!/usr/bin/env python3
+"""Writes the PE fixtures read by the tests in src/pe.rs.
+
+    app.dll        two icon groups (16 and 32 px PNG images) and a version
+                   resource
+    truncated.dll  app.dll with every resource data entry claiming more bytes
+                   than the section holds
+    cyclic.dll     resource directories that point back at themselves or
+                   nest below the language level
+"""
+import os
+import struct
+import zlib
+
+RT_ICON, RT_GROUP_ICON, RT_VERSION = 3, 14, 16
+LANG = 1033
+SECTION_RVA = 0x1000
+SECTION_FILE = 0x200
+
+
+def png(size, rgba):
+    def chunk(kind, data):
+        body = kind + data
+        return struct.pack(">I", len(data)) + body + struct.pack(">I", zlib.crc32(body))
+
+    rows = b"".join(b"\0" + bytes(rgba) * size for _ in range(size))
+    return (b"\x89PNG\r\n\x1a\n"
+            + chunk(b"IHDR", struct.pack(">IIBBBBB", size, size, 8, 6, 0, 0, 0))
+            + chunk(b"IDAT", zlib.compress(rows))
+            + chunk(b"IEND", b""))
+
+
+def group(entries):
+    """GRPICONDIR for (size, icon id, image) entries."""
+    out = struct.pack("<HHH", 0, 1, len(entries))
+    for size, icon_id, image in entries:
+        out += struct.pack("<BBBBHHIH", size % 256, size % 256, 0, 0, 1, 32, len(image), icon_id)
+    return out
+
+
+def version_block(key, value=b"", children=b"", text=False):
+    key = key.encode("utf-16-le") + b"\0\0"
+    head = 6 + len(key)
+    pad = b"\0" * (-head % 4)
+    value_len = len(value) // 2 if text else len(value)
+    body = key + pad + value
+    body += b"\0" * (-(6 + len(body)) % 4) + children
+    return struct.pack("<HHH", 6 + len(body), value_len, 1 if text else 0) + body
+
+
+def align4(b):
+    return b + b"\0" * (-len(b) % 4)
+
+
+def version_info():
+    def string(key, value):
+        return align4(version_block(key, (value + "\0").encode("utf-16-le"), text=True))
+
+    strings = (string("CompanyName", "Example Corp")
+               + string("FileDescription", "Fixture Player")
+               + string("ProductName", "Fixture Suite"))
+    table = align4(version_block("040904b0", children=strings, text=True))
+    string_info = align4(version_block("StringFileInfo", children=table, text=True))
+    translation = align4(version_block("Translation", struct.pack("<HH", 0x0409, 0x04B0)))
+    var_info = align4(version_block("VarFileInfo", children=translation, text=True))
+    fixed = struct.pack("<I", 0xFEEF04BD) + b"\0" * 48
+    return version_block("VS_VERSION_INFO", fixed, string_info + var_info)
+
+
+class Tree:
+    """Resource section laid out as directories, then data entries, then data."""
+
+    def __init__(self):
+        self.dirs = []  # [(offset, [(id, target, is_dir)])]
+        self.data = []  # [(entry offset, bytes, claimed size or None)]
+
+    def directory(self, entries):
+        self.dirs.append([None, entries])
+        return len(self.dirs) - 1
+
+    def leaf(self, payload, claimed=None):
+        self.data.append([None, payload, claimed])
+        return ("data", len(self.data) - 1)
+
+    def build(self):
+        at = 0
+        for d in self.dirs:
+            d[0] = at
+            at += 16 + 8 * len(d[1])
+        for d in self.data:
+            d[0] = at
+            at += 16
+        payload_at = at
+        blob = b""
+        rvas = []
+        for d in self.data:
+            rvas.append(SECTION_RVA + payload_at + len(blob))
+            blob = align4(blob + d[1])
+        out = b""
+        for _, entries in self.dirs:
+            out += struct.pack("<IIHHHH", 0, 0, 0, 0, 0, len(entries))
+            for entry_id, target in entries:
+                if isinstance(target, tuple):
+                    out += struct.pack("<II", entry_id, self.data[target[1]][0])
+                else:
+                    out += struct.pack("<II", entry_id, 0x8000_0000 | self.dirs[target][0])
+        for d, rva in zip(self.data, rvas):
+            size = d[2] if d[2] is not None else len(d[1])
+            out += struct.pack("<IIII", rva, size, 0, 0)
+        return out + blob
+
+
+def resource(tree, kind_entries):
+    """Root directory over (type, [(name id, payload leaf)]) pairs."""
+    types = []
+    for kind, names in kind_entries:
+        name_entries = []
+        for name_id, leaf in names:
+            lang = tree.directory([(LANG, leaf)])
+            name_entries.append((name_id, lang))
+        types.append((kind, tree.directory(name_entries)))
+    return types
+
+
+def pe(section):
+    dos = b"MZ" + b"\0" * 58 + struct.pack("<I", 0x40)
+    optional = bytearray(240)
+    struct.pack_into("<H", optional, 0, 0x20B)
+    struct.pack_into("<I", optional, 108, 16)
+    struct.pack_into("<II", optional, 112 + 2 * 8, SECTION_RVA, len(section))
+    coff = struct.pack("<HHIIIHH", 0x8664, 1, 0, 0, 0, len(optional), 0x2022)
+    header = b".rsrc\0\0\0" + struct.pack(
+        "<IIIIIIHHI", len(section), SECTION_RVA, len(section), SECTION_FILE, 0, 0, 0, 0, 0x40000040)
+    head = dos + b"PE\0\0" + coff + bytes(optional) + header
+    return head + b"\0" * (SECTION_FILE - len(head)) + section
+
+
+def app(claim=None):
+    tree = Tree()
+    small = png(16, (255, 0, 0, 255))
+    large = png(32, (0, 0, 255, 255))
+    root = tree.directory([])
+    claimed = lambda b: None if claim is None else len(b) + claim
+    tree.dirs[root][1] = resource(tree, [
+        (RT_ICON, [(1, tree.leaf(small, claimed(small))), (2, tree.leaf(large, claimed(large)))]),
+        (RT_GROUP_ICON, [
+            (101, tree.leaf(group([(16, 1, small), (32, 2, large)]))),
+            (102, tree.leaf(group([(16, 1, small)]))),
+        ]),
+        (RT_VERSION, [(1, tree.leaf(version_info(), claimed(version_info())))]),
+    ])
+    return pe(tree.build())
+
+
+def cyclic():
+    tree = Tree()
+    small = png(16, (255, 0, 0, 255))
+    root = tree.directory([])
+    icon = tree.directory([])
+    groups = tree.directory([])
+    looping = tree.directory([])
+    versions = tree.directory([])
+    too_deep = tree.directory([])
+    tree.dirs[root][1] = [(RT_ICON, icon), (RT_GROUP_ICON, groups), (RT_VERSION, versions)]
+    tree.dirs[icon][1] = [(1, tree.directory([(LANG, tree.leaf(small))]))]
+    # The group's language directory lists itself.
+    tree.dirs[groups][1] = [(101, looping)]
+    tree.dirs[looping][1] = [(LANG, looping)]
+    # The version's name entry points back at the root; a second name
+    # nests a fourth level.
+    tree.dirs[versions][1] = [(1, root), (2, too_deep)]
+    tree.dirs[too_deep][1] = [(LANG, tree.directory([(0, tree.leaf(version_info()))]))]
+    return pe(tree.build())
+
+
+here = os.path.dirname(os.path.abspath(__file__))
+for name, data in [("app.dll", app()), ("truncated.dll", app(claim=4096)), ("cyclic.dll", cyclic())]:
+    with open(os.path.join(here, name), "wb") as f:
+        f.write(data)